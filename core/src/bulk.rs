@@ -0,0 +1,152 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Check many emails at once, see [`check_emails`].
+
+use crate::util::{input_output::CheckEmailInput, ttl_cache::TtlCache};
+use crate::{check_single_email, CheckEmailOutput};
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// TTL of the caches shared by the checks of [`check_emails`], for the
+/// catch-all statuses, and the MX lookups which have no DNS TTL.
+const SHARED_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// The domain of `email`, lowercased, i.e. everything after the last "@".
+fn domain(email: &str) -> String {
+	email
+		.rsplit_once('@')
+		.map_or("", |(_, domain)| domain)
+		.to_lowercase()
+}
+
+/// Split `inputs` into inputs with a single email each, ordered round-robin
+/// over the domains, so that consecutive checks go to different domains
+/// when possible. Domains come in the order of their first email.
+fn interleave_by_domain(inputs: Vec<CheckEmailInput>) -> Vec<CheckEmailInput> {
+	// The queue of each domain, in the order of their first email.
+	let mut queues: Vec<VecDeque<CheckEmailInput>> = vec![];
+	let mut by_domain: HashMap<String, usize> = HashMap::new();
+	for input in inputs {
+		for to_email in &input.to_emails {
+			let i = *by_domain.entry(domain(to_email)).or_insert_with(|| {
+				queues.push(VecDeque::new());
+				queues.len() - 1
+			});
+			queues[i].push_back(CheckEmailInput {
+				to_emails: vec![to_email.clone()],
+				..input.clone()
+			});
+		}
+	}
+
+	// Take one email from the first domain, and move it to the back unless
+	// it has no emails left.
+	let mut queues: VecDeque<_> = queues.into();
+	let mut interleaved = vec![];
+	while let Some(mut queue) = queues.pop_front() {
+		interleaved.extend(queue.pop_front());
+		if !queue.is_empty() {
+			queues.push_back(queue);
+		}
+	}
+
+	interleaved
+}
+
+/// Check all the emails of `inputs`, running at most `concurrency` checks
+/// at once. The outputs are yielded as the checks complete, i.e. not in
+/// order, `CheckEmailOutput::input` tells which email each one is about.
+///
+/// Consecutive checks go to different domains when possible, which spreads
+/// the load over the MX hosts. The checks also share the catch-all and MX
+/// caches, see `CheckEmailInput::catch_all_cache` and `mx_cache`: inputs
+/// without their own use caches common to all the checks of this call, so
+/// that the catch-all probe and the MX lookup usually run once per domain.
+pub fn check_emails(
+	inputs: Vec<CheckEmailInput>,
+	concurrency: usize,
+) -> impl Stream<Item = CheckEmailOutput> {
+	let catch_all_cache = TtlCache::new(SHARED_CACHE_TTL);
+	let mx_cache = TtlCache::new(SHARED_CACHE_TTL);
+	let inputs = interleave_by_domain(inputs)
+		.into_iter()
+		.map(move |mut input| {
+			input
+				.catch_all_cache
+				.get_or_insert_with(|| catch_all_cache.clone());
+			input.mx_cache.get_or_insert_with(|| mx_cache.clone());
+			input
+		});
+
+	stream::iter(inputs)
+		.map(check_single_email)
+		.buffer_unordered(concurrency.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{check_emails, interleave_by_domain};
+	use crate::util::input_output::{CheckEmailInput, Reachable};
+	use futures::StreamExt;
+	use tokio::runtime::Runtime;
+
+	#[test]
+	fn should_interleave_domains() {
+		let inputs = vec![
+			CheckEmailInput::new(vec!["a@foo.com".into(), "b@foo.com".into()]),
+			CheckEmailInput::new(vec!["c@FOO.com".into(), "d@bar.com".into()]),
+			CheckEmailInput::new(vec!["e@baz.com".into()]),
+		];
+
+		let emails = interleave_by_domain(inputs)
+			.into_iter()
+			.map(|input| input.to_emails[0].clone())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			emails,
+			vec![
+				"a@foo.com",
+				"d@bar.com",
+				"e@baz.com",
+				"b@foo.com",
+				"c@FOO.com"
+			]
+		);
+	}
+
+	#[test]
+	fn should_check_all_emails() {
+		let runtime = Runtime::new().unwrap();
+		// Invalid emails don't need the network.
+		let inputs = vec![
+			CheckEmailInput::new(vec!["foo".into(), "bar".into()]),
+			CheckEmailInput::new(vec!["baz".into()]),
+		];
+
+		let mut emails = runtime.block_on(
+			check_emails(inputs, 2)
+				.map(|output| {
+					assert_eq!(output.is_reachable, Reachable::Invalid);
+					output.input
+				})
+				.collect::<Vec<_>>(),
+		);
+		emails.sort();
+		assert_eq!(emails, vec!["bar", "baz", "foo"]);
+	}
+}
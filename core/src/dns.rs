@@ -0,0 +1,263 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::mx::create_resolver;
+use crate::syntax::SyntaxDetails;
+use crate::util::{constants::LOG_TARGET, input_output::CheckEmailInput};
+use async_std_resolver::AsyncStdResolver;
+use futures::future;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// The policy of a SPF record for the hosts it doesn't list, i.e. the
+/// qualifier of its `all` mechanism.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpfPolicy {
+	/// `+all`: all hosts may send emails for the domain.
+	Pass,
+	/// `-all`: other hosts may not send emails for the domain.
+	Fail,
+	/// `~all`: emails from other hosts are suspicious.
+	SoftFail,
+	/// `?all`: the domain makes no assertion about other hosts.
+	Neutral,
+}
+
+/// The SPF record of a domain (RFC 7208).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SpfRecord {
+	/// The raw TXT record, e.g. "v=spf1 mx -all".
+	pub record: String,
+	/// The policy for other hosts. It will be `None` if the record has no
+	/// `all` mechanism, e.g. if it only redirects to another record.
+	pub policy: Option<SpfPolicy>,
+}
+
+/// The policy of a DMARC record for emails failing authentication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmarcPolicy {
+	/// `p=none`: only report failures.
+	None,
+	/// `p=quarantine`: treat failures as suspicious, e.g. as spam.
+	Quarantine,
+	/// `p=reject`: reject failures.
+	Reject,
+}
+
+/// The DMARC record of a domain (RFC 7489).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DmarcRecord {
+	/// The raw TXT record, e.g. "v=DMARC1; p=reject".
+	pub record: String,
+	/// The `p` tag. It will be `None` if it's missing or invalid.
+	pub policy: Option<DmarcPolicy>,
+	/// The addresses aggregate reports are sent to, from the `rua` tag, e.g.
+	/// "mailto:dmarc@example.org".
+	pub aggregate_report_uris: Vec<String>,
+	/// The addresses failure reports are sent to, from the `ruf` tag.
+	pub failure_report_uris: Vec<String>,
+}
+
+/// Details about the DNS records of the domain, other than MX.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DnsDetails {
+	/// The SPF record of the domain. It will be `None` if there's none, or
+	/// if the lookup failed.
+	pub spf: Option<SpfRecord>,
+	/// The DMARC record of the domain, at `_dmarc.<domain>`. It will be
+	/// `None` if there's none, or if the lookup failed.
+	pub dmarc: Option<DmarcRecord>,
+}
+
+/// Parse `record` if it's a SPF record, i.e. starts with "v=spf1".
+fn parse_spf(record: &str) -> Option<SpfRecord> {
+	let mut terms = record.split_whitespace();
+	if !terms.next()?.eq_ignore_ascii_case("v=spf1") {
+		return None;
+	}
+
+	// Mechanisms are evaluated in order, and `all` always matches.
+	let policy = terms.find_map(|term| match term.to_lowercase().as_str() {
+		"all" | "+all" => Some(SpfPolicy::Pass),
+		"-all" => Some(SpfPolicy::Fail),
+		"~all" => Some(SpfPolicy::SoftFail),
+		"?all" => Some(SpfPolicy::Neutral),
+		_ => None,
+	});
+
+	Some(SpfRecord {
+		record: record.into(),
+		policy,
+	})
+}
+
+/// Parse `record` if it's a DMARC record, i.e. starts with "v=DMARC1".
+/// Malformed tags are ignored.
+fn parse_dmarc(record: &str) -> Option<DmarcRecord> {
+	let mut tags = record
+		.split(';')
+		.map(str::trim)
+		.filter(|tag| !tag.is_empty())
+		.map(|tag| match tag.split_once('=') {
+			Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+			None => (tag.to_lowercase(), ""),
+		});
+	if tags.next()? != ("v".into(), "DMARC1") {
+		return None;
+	}
+
+	let mut dmarc = DmarcRecord {
+		record: record.into(),
+		policy: None,
+		aggregate_report_uris: vec![],
+		failure_report_uris: vec![],
+	};
+	let uris = |value: &str| -> Vec<String> {
+		value
+			.split(',')
+			.map(str::trim)
+			.filter(|uri| !uri.is_empty())
+			.map(String::from)
+			.collect()
+	};
+	for (name, value) in tags {
+		match name.as_str() {
+			"p" => {
+				dmarc.policy = match value.to_lowercase().as_str() {
+					"none" => Some(DmarcPolicy::None),
+					"quarantine" => Some(DmarcPolicy::Quarantine),
+					"reject" => Some(DmarcPolicy::Reject),
+					_ => None,
+				}
+			}
+			"rua" => dmarc.aggregate_report_uris = uris(value),
+			"ruf" => dmarc.failure_report_uris = uris(value),
+			_ => {}
+		}
+	}
+
+	Some(dmarc)
+}
+
+/// Fetch the TXT records of `name`. Failed lookups, including when there's
+/// no record, return an empty list.
+async fn txt_records(resolver: &AsyncStdResolver, name: &str) -> Vec<String> {
+	match resolver.txt_lookup(name).await {
+		Ok(lookup) => lookup
+			.iter()
+			.map(|txt| {
+				// Long records are split into several strings.
+				txt.txt_data()
+					.iter()
+					.map(|data| String::from_utf8_lossy(data))
+					.collect()
+			})
+			.collect(),
+		Err(err) => {
+			log::debug!(target: LOG_TARGET, "Failed TXT lookup for {}: {}", name, err);
+			vec![]
+		}
+	}
+}
+
+/// The name in the PTR record of `ip`, without the final dot, or None if
+/// the lookup fails.
+pub(crate) async fn ptr_name(ip: IpAddr, input: &CheckEmailInput) -> Option<String> {
+	let resolver = create_resolver(input).await.ok()?;
+	match resolver.reverse_lookup(ip).await {
+		Ok(lookup) => lookup
+			.iter()
+			.next()
+			.map(|name| name.to_string().trim_end_matches('.').to_string()),
+		Err(err) => {
+			log::debug!(target: LOG_TARGET, "Failed PTR lookup for {}: {}", ip, err);
+			None
+		}
+	}
+}
+
+/// Look up the DNS records of the domain giving information about its
+/// emails, such as SPF and DMARC. Missing records and failed lookups are `None`.
+pub async fn check_dns(syntax: &SyntaxDetails, input: &CheckEmailInput) -> DnsDetails {
+	// Internationalized domains are looked up in punycode.
+	let domain = match idna::domain_to_ascii(&syntax.domain) {
+		Ok(domain) => domain,
+		Err(_) => return DnsDetails::default(),
+	};
+	let resolver = match create_resolver(input).await {
+		Ok(resolver) => resolver,
+		Err(err) => {
+			log::debug!(target: LOG_TARGET, "Failed to create resolver: {}", err);
+			return DnsDetails::default();
+		}
+	};
+
+	let (spf_records, dmarc_records) = future::join(
+		txt_records(&resolver, &domain),
+		txt_records(&resolver, &format!("_dmarc.{}", domain)),
+	)
+	.await;
+
+	DnsDetails {
+		spf: spf_records.iter().find_map(|record| parse_spf(record)),
+		dmarc: dmarc_records.iter().find_map(|record| parse_dmarc(record)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_dmarc, parse_spf, DmarcPolicy, SpfPolicy};
+
+	#[test]
+	fn should_parse_spf_policy() {
+		let policy = |record: &str| parse_spf(record).unwrap().policy;
+
+		assert_eq!(policy("v=spf1 mx -all"), Some(SpfPolicy::Fail));
+		assert_eq!(
+			policy("V=SPF1 include:_spf.example.org ~ALL"),
+			Some(SpfPolicy::SoftFail)
+		);
+		assert_eq!(policy("v=spf1 ?all"), Some(SpfPolicy::Neutral));
+		assert_eq!(policy("v=spf1 a all"), Some(SpfPolicy::Pass));
+		assert_eq!(policy("v=spf1 redirect=_spf.example.org"), None);
+
+		assert_eq!(parse_spf("google-site-verification=abc"), None);
+		assert_eq!(parse_spf("v=spf10 -all"), None);
+	}
+
+	#[test]
+	fn should_parse_dmarc_record() {
+		let dmarc = parse_dmarc(
+			"v=DMARC1; p=Quarantine; rua=mailto:a@example.org, mailto:b@example.org; ruf=mailto:f@example.org",
+		)
+		.unwrap();
+		assert_eq!(dmarc.policy, Some(DmarcPolicy::Quarantine));
+		assert_eq!(
+			dmarc.aggregate_report_uris,
+			vec!["mailto:a@example.org", "mailto:b@example.org"]
+		);
+		assert_eq!(dmarc.failure_report_uris, vec!["mailto:f@example.org"]);
+
+		let dmarc = parse_dmarc("v=DMARC1;p=whatever;foo").unwrap();
+		assert_eq!(dmarc.policy, None);
+		assert!(dmarc.aggregate_report_uris.is_empty());
+
+		assert_eq!(parse_dmarc("v=spf1 -all"), None);
+		assert_eq!(parse_dmarc("p=reject; v=DMARC1"), None);
+	}
+}
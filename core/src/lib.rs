@@ -23,13 +23,13 @@
 //! - Email deliverability: Is an email sent to this address deliverable?
 //! - Syntax validation. Is the address syntactically valid?
 //! - DNS records validation. Does the domain of the email address have valid
-//! MX DNS records?
+//!   MX DNS records?
 //! - Disposable email address (DEA) validation. Is the address provided by a
-//! known disposable email address provider?
+//!   known disposable email address provider?
 //! - SMTP server validation. Can the mail exchanger of the email address
-//! domain be contacted successfully?
+//!   domain be contacted successfully?
 //! - Mailbox disabled. Has this email address been disabled by the email
-//! provider?
+//!   provider?
 //! - Full inbox. Is the inbox of this mailbox full?
 //! - Catch-all address. Is this email address a catch-all address?
 //!
@@ -45,10 +45,11 @@
 //!     input
 //!         .set_from_email("me@example.org".into()) // Used in the `MAIL FROM:` command
 //!         .set_hello_name("example.org".into())    // Used in the `EHLO` command
-//!         .set_smtp(587)                           // Use port 587 instead of 25
+//!         .set_smtp_port(587)                      // Use port 587 instead of 25
 //!         .set_proxy(CheckEmailInputProxy {        // Use a SOCKS5 proxy to verify the email
 //!             host: "my-proxy.io".into(),
-//!             port: 1080
+//!             port: 1080,
+//!             ..Default::default()
 //!     });
 //!
 //!     // Verify this input, using async/await syntax.
@@ -59,36 +60,70 @@
 //!     println!("{:?}", result);
 //! }
 //! ```
+//!
+//! Long-running services can instead create one [`EmailVerifier`], which
+//! keeps state (such as caches) across checks:
+//!
+//! ```rust
+//! use check_if_email_exists::{CheckEmailInput, EmailVerifier};
+//! use std::time::Duration;
+//!
+//! async fn check() {
+//!     let mut verifier = EmailVerifier::new(CheckEmailInput::default());
+//!     verifier.set_catch_all_cache(Duration::from_secs(3600));
+//!
+//!     let result = verifier.verify("someone@gmail.com").await;
+//!     println!("{:?}", result);
+//! }
+//! ```
 
+mod bulk;
+pub mod dns;
 pub mod misc;
 pub mod mx;
 pub mod smtp;
 pub mod syntax;
 mod util;
+pub mod verifier;
 
+pub use bulk::check_emails;
+use dns::check_dns;
 use futures::future;
-use misc::{check_misc, MiscDetails};
-use mx::check_mx;
-use smtp::{check_smtp, SmtpDetails, SmtpError};
+use misc::{check_misc, offline_misc_details, MiscDetails};
+use mx::{check_mx, MxError};
+use smtp::{check_smtp_mx, SmtpDetails, SmtpError, VerifReason};
+use std::future::Future;
+use std::time::Instant;
 use syntax::check_syntax;
+pub use util::cancellation::CancellationToken;
 use util::constants::LOG_TARGET;
 pub use util::input_output::*;
+pub use util::ttl_cache::TtlCache;
+pub use verifier::EmailVerifier;
 
 /// Given an email's misc and smtp details, calculate an estimate of our
 /// confidence on how reachable the email is.
 fn calculate_reachable(misc: &MiscDetails, smtp: &Result<SmtpDetails, SmtpError>) -> Reachable {
-	if let Ok(smtp) = smtp {
-		if misc.is_disposable || misc.is_role_account || smtp.is_catch_all || smtp.has_full_inbox {
-			return Reachable::Risky;
+	match smtp.as_ref().map(SmtpDetails::reachable) {
+		Ok(Reachable::Safe) | Ok(Reachable::Invalid)
+			if misc.is_disposable || misc.is_role_account =>
+		{
+			Reachable::Risky
 		}
+		Ok(reachable) => reachable,
+		Err(_) => Reachable::Unknown,
+	}
+}
 
-		if !smtp.is_deliverable || !smtp.can_connect_smtp || smtp.is_disabled {
-			return Reachable::Invalid;
+/// Run `future` to completion, or return None if `deadline` passes first.
+async fn before_deadline<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+	match deadline {
+		Some(deadline) => {
+			async_std::future::timeout(deadline.saturating_duration_since(Instant::now()), future)
+				.await
+				.ok()
 		}
-
-		Reachable::Safe
-	} else {
-		Reachable::Unknown
+		None => Some(future.await),
 	}
 }
 
@@ -125,7 +160,22 @@ async fn check_single_email(input: CheckEmailInput) -> CheckEmailOutput {
 		my_syntax
 	);
 
-	let my_mx = match check_mx(&my_syntax).await {
+	let deadline = input.deadline.map(|deadline| Instant::now() + deadline);
+	// The other DNS records are looked up alongside MX records.
+	let (my_mx, my_dns) = future::join(
+		before_deadline(deadline, check_mx(&my_syntax, &input)),
+		before_deadline(deadline, check_dns(&my_syntax, &input)),
+	)
+	.await;
+	let my_dns = my_dns.unwrap_or_default();
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Found the following DNS details: {:?}",
+		to_email,
+		my_dns
+	);
+
+	let my_mx = match my_mx.unwrap_or(Err(MxError::DeadlineExceeded)) {
 		Ok(m) => m,
 		e => {
 			// This happens when there's an internal error while checking MX
@@ -133,6 +183,7 @@ async fn check_single_email(input: CheckEmailInput) -> CheckEmailOutput {
 			return CheckEmailOutput {
 				input: to_email.to_string(),
 				is_reachable: Reachable::Unknown,
+				dns: my_dns,
 				mx: e,
 				syntax: my_syntax,
 				..Default::default()
@@ -140,12 +191,22 @@ async fn check_single_email(input: CheckEmailInput) -> CheckEmailOutput {
 		}
 	};
 
-	// Return if we didn't find any MX records.
-	if my_mx.lookup.is_err() {
+	// Return early if we didn't find any MX records, e.g. because the domain
+	// doesn't exist (NXDOMAIN) or its DNS fails (SERVFAIL): there's no SMTP
+	// server to connect to.
+	if let Err(err) = &my_mx.lookup {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} No MX records found ({}), skipping SMTP.",
+			to_email,
+			err
+		);
 		return CheckEmailOutput {
 			input: to_email.to_string(),
 			is_reachable: Reachable::Invalid,
+			dns: my_dns,
 			mx: Ok(my_mx),
+			smtp: Ok(SmtpDetails::domain_not_found()),
 			syntax: my_syntax,
 			..Default::default()
 		};
@@ -164,7 +225,10 @@ async fn check_single_email(input: CheckEmailInput) -> CheckEmailOutput {
 			.collect::<Vec<String>>()
 	);
 
-	let my_misc = check_misc(&my_syntax);
+	// Only the Gravatar request can miss the deadline.
+	let my_misc = before_deadline(deadline, check_misc(&my_syntax, &input))
+		.await
+		.unwrap_or_else(|| offline_misc_details(&my_syntax, &input));
 	log::debug!(
 		target: LOG_TARGET,
 		"email={} Found the following misc details: {:?}",
@@ -172,43 +236,72 @@ async fn check_single_email(input: CheckEmailInput) -> CheckEmailOutput {
 		my_misc
 	);
 
+	if input.skip_smtp {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} Skipping SMTP verification",
+			to_email
+		);
+		let my_smtp = Ok(SmtpDetails {
+			is_skipped: true,
+			reason: Some(VerifReason::Skipped),
+			..Default::default()
+		});
+
+		return CheckEmailOutput {
+			input: to_email.to_string(),
+			is_reachable: calculate_reachable(&my_misc, &my_smtp),
+			dns: my_dns,
+			misc: Ok(my_misc),
+			mx: Ok(my_mx),
+			smtp: my_smtp,
+			syntax: my_syntax,
+		};
+	}
+
 	// We loop through all the MX records, and check each one of them. This is
 	// because to prevent SPAM, some servers put a dummy server as 1st MX
 	// record.
 	// ref: https://github.com/reacherhq/check-if-email-exists/issues/1049
-	let mut my_smtp: Option<Result<SmtpDetails, SmtpError>> = None;
-	for host in my_mx
+	let mx_records = my_mx
 		.lookup
 		.as_ref()
 		.expect("If lookup is error, we already returned. qed.")
 		.iter()
-	{
-		let res = check_smtp(
-			my_syntax
-				.address
-				.as_ref()
-				.expect("We already checked that the email has valid format. qed."),
-			host.exchange(),
+		.cloned()
+		.collect::<Vec<_>>();
+	let to_address = match (&my_syntax.base_address, input.verify_base_address) {
+		(Some(base_address), true) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"email={} Verifying base address {}",
+				to_email,
+				base_address
+			);
+			base_address
+		}
+		_ => my_syntax
+			.address
+			.as_ref()
+			.expect("We already checked that the email has valid format. qed."),
+	};
+	let my_smtp = before_deadline(
+		deadline,
+		check_smtp_mx(
+			to_address,
+			&mx_records,
 			input.smtp_port,
 			my_syntax.domain.as_ref(),
 			&input,
-		)
-		.await;
-		let is_reachable = res.is_ok();
-		my_smtp = Some(res);
-		if !is_reachable {
-			continue; // If error, then we move on to next MX record.
-		} else {
-			break; // If successful, then we break.
-		}
-	}
-	let my_smtp = my_smtp.expect(
-		"As long as lookup has at least 1 element (which we checked), my_smtp will be a Some. qed.",
-	);
+		),
+	)
+	.await
+	.unwrap_or(Err(SmtpError::DeadlineExceeded));
 
 	CheckEmailOutput {
 		input: to_email.to_string(),
 		is_reachable: calculate_reachable(&my_misc, &my_smtp),
+		dns: my_dns,
 		misc: Ok(my_misc),
 		mx: Ok(my_mx),
 		smtp: my_smtp,
@@ -224,6 +317,7 @@ async fn check_single_email(input: CheckEmailInput) -> CheckEmailOutput {
 /// emails in the `inputs.to_emails` Vec) is still a **beta** feature, and not
 /// fully optimized. For more info, see #65
 /// <https://github.com/reacherhq/check-if-email-exists/issues/65>.
+/// To check many emails, use [`check_emails`] instead.
 pub async fn check_email(inputs: &CheckEmailInput) -> Vec<CheckEmailOutput> {
 	if inputs.to_emails.len() > 1 {
 		log::warn!(
@@ -239,19 +333,19 @@ pub async fn check_email(inputs: &CheckEmailInput) -> Vec<CheckEmailOutput> {
 	// - if multiple email addresses share the same domain, we should call
 	// `check_smtp` with grouped email addresses, to share a SMTP connection.
 	// ref: https://github.com/reacherhq/check-if-email-exists/issues/65.
-	let inputs = inputs.to_emails.iter().map(|email| {
-		// Create n `CheckEmailInput`s, each with one email address.
-		CheckEmailInput {
-			to_emails: vec![email.clone()],
-			from_email: inputs.from_email.clone(),
-			hello_name: inputs.hello_name.clone(),
-			proxy: inputs.proxy.clone(),
-			retries: inputs.retries,
-			smtp_port: inputs.smtp_port,
-			smtp_security: inputs.smtp_security,
-			smtp_timeout: inputs.smtp_timeout,
-			yahoo_use_api: inputs.yahoo_use_api,
-		}
-	});
-	future::join_all(inputs.map(check_single_email)).await
+	let verifier = EmailVerifier::new(inputs.clone());
+	future::join_all(inputs.to_emails.iter().map(|email| verifier.verify(email))).await
+}
+
+/// The blocking version of [`check_email`], for synchronous code. It runs
+/// the check on a runtime created on the first call of each thread, and
+/// reused by the next calls.
+///
+/// # Panics
+///
+/// This function panics if called from within an async runtime: use
+/// [`check_email`] there instead.
+#[cfg(feature = "blocking")]
+pub fn check_email_blocking(inputs: &CheckEmailInput) -> Vec<CheckEmailOutput> {
+	util::blocking::block_on(check_email(inputs))
 }
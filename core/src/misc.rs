@@ -15,10 +15,28 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::syntax::SyntaxDetails;
+use crate::util::{constants::LOG_TARGET, input_output::CheckEmailInput, md5::md5_hex};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::default::Default;
+use std::time::Duration;
 
 const ROLE_ACCOUNTS: &str = include_str!("./util/roles.json");
+/// The roles of roles.json, parsed once.
+static ROLE_ACCOUNT_LIST: Lazy<Vec<&'static str>> =
+	Lazy::new(|| serde_json::from_str(ROLE_ACCOUNTS).expect("roles.json is a valid json. qed."));
+const FREE_PROVIDERS: &str = include_str!("./util/free_providers.json");
+/// The domains of free_providers.json, parsed once.
+static FREE_PROVIDER_DOMAINS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+	serde_json::from_str(FREE_PROVIDERS).expect("free_providers.json is a valid json. qed.")
+});
+/// The built-in disposable domains, from the `mailchecker` crate.
+static DISPOSABLE_DOMAINS: Lazy<HashSet<&'static str>> =
+	Lazy::new(|| mailchecker::blacklist().into_iter().collect());
+const GRAVATAR_URL: &str = "https://www.gravatar.com/avatar";
+/// Timeout of the Gravatar request, see `CheckEmailInput::check_gravatar`.
+const GRAVATAR_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Miscelleanous details about the email address.
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -27,32 +45,196 @@ pub struct MiscDetails {
 	pub is_disposable: bool,
 	/// Is this email a role-based account?
 	pub is_role_account: bool,
+	/// Is this email hosted by a free email provider, e.g. Gmail, as opposed
+	/// to a company domain? Custom domains hosted by these providers, e.g.
+	/// with Google Workspace, are not free providers.
+	pub is_free_provider: bool,
+	/// Does this email have a Gravatar? Only checked if
+	/// `CheckEmailInput::check_gravatar` is set, false otherwise.
+	pub has_gravatar: bool,
 }
 
 /// Error occured connecting to this email server via SMTP. Right now this
 /// enum has no variant, as `check_misc` cannot fail. But putting a placeholder
 /// right now to avoid future breaking changes.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "type", content = "message")]
 pub enum MiscError {}
 
+/// Is `domain`, or one of its parent domains, a known disposable email
+/// provider? The built-in list comes from the `mailchecker` crate, see
+/// `CheckEmailInput::use_builtin_disposable_domains`.
+fn is_disposable(domain: &str, input: &CheckEmailInput) -> bool {
+	let domain = domain.trim_end_matches('.').to_lowercase();
+
+	// "mail.mailinator.com" is checked as "mail.mailinator.com", then
+	// "mailinator.com", then "com".
+	let mut suffix = domain.as_str();
+	loop {
+		if (input.use_builtin_disposable_domains && DISPOSABLE_DOMAINS.contains(suffix))
+			|| input
+				.disposable_domains
+				.iter()
+				.any(|d| d.trim_end_matches('.').eq_ignore_ascii_case(suffix))
+		{
+			return true;
+		}
+		match suffix.split_once('.') {
+			Some((_, parent)) => suffix = parent,
+			None => return false,
+		}
+	}
+}
+
+/// Is `username` a role-based account, e.g. "info" or "sales"? The
+/// comparison ignores ASCII case and any "+tag". Besides exact matches,
+/// roles followed by a separator also match, e.g. "sales-eu" and
+/// "support.team", but not "salesman".
+fn is_role_account(username: &str, input: &CheckEmailInput) -> bool {
+	let username = username.split('+').next().unwrap_or_default();
+	let is_role = |role: &str| {
+		username
+			.get(..role.len())
+			.map_or(false, |prefix| prefix.eq_ignore_ascii_case(role))
+			&& matches!(
+				username[role.len()..].chars().next(),
+				None | Some('.') | Some('-') | Some('_')
+			)
+	};
+
+	match &input.role_accounts {
+		Some(roles) => roles.iter().any(|role| is_role(role)),
+		None => ROLE_ACCOUNT_LIST.iter().any(|role| is_role(role)),
+	}
+}
+
+/// Is `domain` the domain of a free email provider? See
+/// `CheckEmailInput::free_provider_domains`.
+fn is_free_provider(domain: &str, input: &CheckEmailInput) -> bool {
+	let domain = domain.trim_end_matches('.');
+
+	FREE_PROVIDER_DOMAINS.contains(domain.to_lowercase().as_str())
+		|| input
+			.free_provider_domains
+			.iter()
+			.any(|provider| provider.eq_ignore_ascii_case(domain))
+}
+
+/// The URL of the Gravatar of `email`, which returns a 404 if there's none.
+fn gravatar_url(email: &str) -> String {
+	format!(
+		"{}/{}?d=404",
+		GRAVATAR_URL,
+		md5_hex(email.trim().to_lowercase().as_bytes())
+	)
+}
+
+/// Check whether `email` has a Gravatar, which suggests that a real person
+/// uses it. Errors and timeouts are logged, and count as no Gravatar.
+async fn check_gravatar(email: &str) -> bool {
+	let response = reqwest::Client::builder()
+		.timeout(GRAVATAR_TIMEOUT)
+		.build()
+		.expect("The client has a valid config. qed.")
+		.head(gravatar_url(email))
+		.send()
+		.await;
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Gravatar response: {:?}",
+		email,
+		response
+	);
+
+	response.map_or(false, |response| response.status().is_success())
+}
+
+/// The misc details which don't need any network request, i.e. all but
+/// `has_gravatar`, which is false.
+pub(crate) fn offline_misc_details(syntax: &SyntaxDetails, input: &CheckEmailInput) -> MiscDetails {
+	MiscDetails {
+		is_disposable: is_disposable(&syntax.domain, input),
+		is_role_account: is_role_account(&syntax.username, input),
+		is_free_provider: is_free_provider(&syntax.domain, input),
+		has_gravatar: false,
+	}
+}
+
 /// Fetch misc details about the email address, such as whether it's disposable.
-pub fn check_misc(syntax: &SyntaxDetails) -> MiscDetails {
-	let role_accounts: Vec<&str> =
-		serde_json::from_str(ROLE_ACCOUNTS).expect("roles.json is a valid json. qed.");
+///
+/// Breaking change: this function used to be synchronous and only take the
+/// `SyntaxDetails`. It now also takes the `CheckEmailInput`, for the custom
+/// domain lists and the Gravatar check.
+pub async fn check_misc(syntax: &SyntaxDetails, input: &CheckEmailInput) -> MiscDetails {
+	let address = syntax
+		.address
+		.as_ref()
+		.expect("We already checked that the syntax was valid. qed.")
+		.to_string();
 
 	MiscDetails {
-		// mailchecker::is_valid checks also if the syntax is valid. But if
-		// we're here, it means we're sure the syntax is valid, so is_valid
-		// actually will only check if it's disposable.
-		is_disposable: !mailchecker::is_valid(
-			syntax
-				.address
-				.as_ref()
-				.expect("We already checked that the syntax was valid. qed.")
-				.to_string()
-				.as_ref(),
-		),
-		is_role_account: role_accounts.contains(&syntax.username.to_lowercase().as_ref()),
+		has_gravatar: input.check_gravatar && check_gravatar(&address).await,
+		..offline_misc_details(syntax, input)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{gravatar_url, is_disposable, is_free_provider, is_role_account};
+	use crate::util::input_output::CheckEmailInput;
+
+	#[test]
+	fn should_detect_disposable_domains() {
+		let mut input = CheckEmailInput::default();
+
+		assert!(is_disposable("mailinator.com", &input));
+		assert!(is_disposable("Mail.Mailinator.com.", &input));
+		assert!(!is_disposable("gmail.com", &input));
+		assert!(!is_disposable("example.org", &input));
+
+		input.add_disposable_domain("throwaway.example".into());
+		assert!(is_disposable("mx.throwaway.example", &input));
+		assert!(is_disposable("mailinator.com", &input));
+
+		input.set_use_builtin_disposable_domains(false);
+		assert!(is_disposable("throwaway.example", &input));
+		assert!(!is_disposable("mailinator.com", &input));
+	}
+
+	#[test]
+	fn should_detect_role_accounts() {
+		let mut input = CheckEmailInput::default();
+
+		assert!(is_role_account("info", &input));
+		assert!(is_role_account("Sales", &input));
+		assert!(is_role_account("support.team", &input));
+		assert!(is_role_account("postmaster+tag", &input));
+		assert!(!is_role_account("salesman", &input));
+		assert!(!is_role_account("john.doe", &input));
+
+		input.set_role_accounts(vec!["Team".into()]);
+		assert!(is_role_account("team-eu", &input));
+		assert!(!is_role_account("info", &input));
+	}
+
+	#[test]
+	fn should_detect_free_providers() {
+		let mut input = CheckEmailInput::default();
+
+		assert!(is_free_provider("gmail.com", &input));
+		assert!(is_free_provider("Yahoo.co.uk", &input));
+		assert!(!is_free_provider("reacher.email", &input));
+
+		input.add_free_provider_domain("free.example".into());
+		assert!(is_free_provider("free.example", &input));
+		assert!(!is_free_provider("mail.free.example", &input));
+	}
+
+	#[test]
+	fn should_hash_normalized_email_for_gravatar() {
+		assert_eq!(
+			gravatar_url(" MyEmailAddress@example.com "),
+			"https://www.gravatar.com/avatar/0bc83cb571cd1c50ba6f3e8a78ef1346?d=404"
+		);
 	}
 }
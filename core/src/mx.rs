@@ -15,29 +15,67 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::syntax::SyntaxDetails;
-use crate::util::ser_with_display::ser_with_display;
-use async_std_resolver::{lookup::MxLookup, resolver_from_system_conf, ResolveError};
-use serde::{ser::SerializeMap, Serialize, Serializer};
+use crate::util::{
+	constants::LOG_TARGET,
+	deserialized_error::DeserializedError,
+	input_output::{CheckEmailInput, DnsResolver},
+	ser_with_display::ser_with_display,
+	ttl_cache::TtlCache,
+};
+use async_std_resolver::{
+	config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+	lookup::{Lookup, MxLookup},
+	resolver, resolver_from_system_conf, AsyncStdResolver, ResolveError,
+};
+use serde::{de::Error as _, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
 use std::io::Error;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::{Query, ResponseCode};
+use trust_dns_proto::rr::{rdata::MX, Name, RData, Record, RecordType};
+use trust_dns_resolver::error::ResolveErrorKind;
 
 /// Details about the MX lookup.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MxDetails {
 	/// MX lookup of this DNS.
 	pub lookup: Result<MxLookup, ResolveError>,
+	/// Is `lookup` an implicit MX (RFC 5321), i.e. the domain itself,
+	/// because it has no MX records but has A or AAAA records?
+	pub is_implicit_mx: bool,
+}
+
+impl MxDetails {
+	/// Does the domain publish a null MX, see [`is_null_mx`]?
+	pub fn is_null_mx(&self) -> bool {
+		self.lookup.as_ref().map_or(false, |lookup| {
+			is_null_mx(&lookup.iter().cloned().collect::<Vec<_>>())
+		})
+	}
+}
+
+/// Is this a null MX (RFC 7505), i.e. a single MX record with the host ".",
+/// by which a domain declares that it doesn't accept emails?
+pub fn is_null_mx(mx_records: &[MX]) -> bool {
+	matches!(mx_records, [mx] if mx.exchange().is_root())
 }
 
 impl Default for MxDetails {
 	fn default() -> Self {
 		MxDetails {
 			lookup: Err(ResolveError::from("Skipped")),
+			is_implicit_mx: false,
 		}
 	}
 }
 
 impl From<MxLookup> for MxDetails {
 	fn from(lookup: MxLookup) -> Self {
-		MxDetails { lookup: Ok(lookup) }
+		MxDetails {
+			lookup: Ok(lookup),
+			is_implicit_mx: false,
+		}
 	}
 }
 
@@ -57,16 +95,64 @@ impl Serialize for MxDetails {
 			})
 			.unwrap_or_else(|_| Vec::new()); // In case of a resolve error, we don't serialize the error.
 
-		let mut map = serializer.serialize_map(Some(2))?;
-		map.serialize_entry("accepts_mail", &!records.is_empty())?;
+		let mut map = serializer.serialize_map(Some(3))?;
+		map.serialize_entry("accepts_mail", &(!records.is_empty() && !self.is_null_mx()))?;
 		map.serialize_entry("records", &records)?;
+		map.serialize_entry("is_implicit_mx", &self.is_implicit_mx)?;
 		map.end()
 	}
 }
 
+impl<'de> Deserialize<'de> for MxDetails {
+	/// Rebuild the MX lookup from the serialized `records`. Their preferences
+	/// aren't serialized, so they are numbered in order instead, and the
+	/// lookup's query is for the root domain. A failed lookup is rebuilt with
+	/// a generic error, as the original one isn't serialized either.
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		#[derive(Deserialize)]
+		struct Serialized {
+			records: Vec<String>,
+			#[serde(default)]
+			is_implicit_mx: bool,
+		}
+
+		let serialized = Serialized::deserialize(deserializer)?;
+		if serialized.records.is_empty() {
+			return Ok(MxDetails {
+				lookup: Err(ResolveError::from("No MX records")),
+				is_implicit_mx: serialized.is_implicit_mx,
+			});
+		}
+
+		let records = serialized
+			.records
+			.iter()
+			.enumerate()
+			.map(|(preference, exchange)| {
+				let exchange = Name::from_str(exchange).map_err(D::Error::custom)?;
+				let preference = u16::try_from(preference).map_err(D::Error::custom)?;
+				Ok(Record::from_rdata(
+					exchange.clone(),
+					0,
+					RData::MX(MX::new(preference, exchange)),
+				))
+			})
+			.collect::<Result<Vec<_>, D::Error>>()?;
+		let query = Query::query(Name::root(), RecordType::MX);
+
+		Ok(MxDetails {
+			lookup: Ok(Lookup::new_with_max_ttl(query, records.into()).into()),
+			is_implicit_mx: serialized.is_implicit_mx,
+		})
+	}
+}
+
 /// Errors that can happen on MX lookups.
 #[derive(Debug, Serialize)]
-#[serde(tag = "type", content = "message")]
+#[serde(remote = "Self", tag = "type", content = "message")]
 pub enum MxError {
 	/// Error with IO.
 	#[serde(serialize_with = "ser_with_display")]
@@ -74,6 +160,46 @@ pub enum MxError {
 	/// Error while resolving MX lookups.
 	#[serde(serialize_with = "ser_with_display")]
 	ResolveError(Box<ResolveError>),
+	/// The MX lookup didn't finish before `CheckEmailInput::deadline`.
+	DeadlineExceeded,
+	/// The domain is an invalid internationalized domain name, which can't
+	/// be converted to punycode.
+	InvalidDomain(String),
+	/// An error read back from JSON, which wrapped an error that can't be
+	/// rebuilt, e.g. a resolve error. It serializes back to the same JSON.
+	#[serde(skip)]
+	Deserialized(DeserializedError),
+}
+
+impl Serialize for MxError {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		match self {
+			MxError::Deserialized(error) => error.serialize(serializer),
+			_ => MxError::serialize(self, serializer),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for MxError {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		let error = DeserializedError::deserialize(deserializer)?;
+		let rebuilt = match error.error_type() {
+			Some("DeadlineExceeded") => Some(MxError::DeadlineExceeded),
+			Some("InvalidDomain") => error
+				.message()
+				.and_then(serde_json::Value::as_str)
+				.map(|domain| MxError::InvalidDomain(domain.into())),
+			_ => None,
+		};
+
+		Ok(rebuilt.unwrap_or(MxError::Deserialized(error)))
+	}
 }
 
 impl From<ResolveError> for MxError {
@@ -82,16 +208,203 @@ impl From<ResolveError> for MxError {
 	}
 }
 
-/// Make a MX lookup.
-pub async fn check_mx(syntax: &SyntaxDetails) -> Result<MxDetails, MxError> {
-	// Construct a new Resolver with default configuration options
-	let resolver = resolver_from_system_conf().await?;
+/// Create the resolver configured by `input.dns_resolver`.
+pub(crate) async fn create_resolver(
+	input: &CheckEmailInput,
+) -> Result<AsyncStdResolver, ResolveError> {
+	match &input.dns_resolver {
+		DnsResolver::System => resolver_from_system_conf().await,
+		DnsResolver::Nameservers(addrs) => {
+			let mut nameservers = NameServerConfigGroup::new();
+			for addr in addrs {
+				nameservers.merge(NameServerConfigGroup::from_ips_clear(
+					&[addr.ip()],
+					addr.port(),
+					true,
+				));
+			}
+			resolver(
+				ResolverConfig::from_parts(None, vec![], nameservers),
+				ResolverOpts::default(),
+			)
+			.await
+		}
+		DnsResolver::Custom(config, opts) => resolver((**config).clone(), *opts).await,
+	}
+}
+
+/// A lookup with a single MX record pointing to `domain` itself.
+fn implicit_mx_lookup(domain: Name) -> MxLookup {
+	let query = Query::query(domain.clone(), RecordType::MX);
+	Lookup::from_rdata(query, RData::MX(MX::new(0, domain))).into()
+}
+
+/// If the MX lookup of `domain` failed with `err` because the domain has no
+/// MX records, look for its A or AAAA records instead. Per RFC 5321, the
+/// domain itself is then the mail host.
+async fn implicit_mx(
+	resolver: &AsyncStdResolver,
+	domain: &str,
+	err: &ResolveError,
+) -> Option<MxLookup> {
+	match err.kind() {
+		// NXDOMAIN means that the domain has no record at all.
+		ResolveErrorKind::NoRecordsFound { response_code, .. }
+			if *response_code != ResponseCode::NXDomain => {}
+		_ => return None,
+	}
+
+	// The final dot makes it a FQDN, like the hosts of MX records.
+	let name = Name::from_ascii(format!("{}.", domain)).ok()?;
+	match resolver.lookup_ip(name.clone()).await {
+		Ok(lookup) if lookup.iter().next().is_some() => {
+			log::debug!(
+				target: LOG_TARGET,
+				"No MX records for {}, using its A/AAAA records.",
+				domain
+			);
+			Some(implicit_mx_lookup(name))
+		}
+		_ => None,
+	}
+}
+
+/// Cache `details` for `domain`: successful lookups for their DNS TTL, and
+/// domains without MX records for the negative TTL of the response if any,
+/// or else the default TTL of the cache. Other errors, e.g. timeouts, aren't
+/// cached.
+fn cache_mx(cache: &TtlCache<MxDetails>, domain: &str, details: &MxDetails) {
+	match &details.lookup {
+		Ok(lookup) if !details.is_implicit_mx => cache.insert_with_ttl(
+			domain,
+			details.clone(),
+			lookup
+				.valid_until()
+				.saturating_duration_since(Instant::now()),
+		),
+		Ok(_) => cache.insert(domain, details.clone()),
+		Err(err) => {
+			if let ResolveErrorKind::NoRecordsFound { negative_ttl, .. } = err.kind() {
+				match negative_ttl {
+					Some(ttl) => cache.insert_with_ttl(
+						domain,
+						details.clone(),
+						Duration::from_secs((*ttl).into()),
+					),
+					None => cache.insert(domain, details.clone()),
+				}
+			}
+		}
+	}
+}
+
+/// Make a MX lookup, or reuse the one in `input.mx_cache` if any.
+pub async fn check_mx(
+	syntax: &SyntaxDetails,
+	input: &CheckEmailInput,
+) -> Result<MxDetails, MxError> {
+	// Internationalized domains are looked up in punycode.
+	let domain = idna::domain_to_ascii(&syntax.domain)
+		.map_err(|err| MxError::InvalidDomain(err.to_string()))?;
+
+	if let Some(details) = input.mx_cache.as_ref().and_then(|cache| cache.get(&domain)) {
+		return Ok(details);
+	}
+	let details = lookup_mx(&domain, input).await?;
+	if let Some(cache) = &input.mx_cache {
+		cache_mx(cache, &domain, &details);
+	}
+
+	Ok(details)
+}
+
+/// Make a MX lookup of `domain`, in punycode.
+async fn lookup_mx(domain: &str, input: &CheckEmailInput) -> Result<MxDetails, MxError> {
+	let resolver = create_resolver(input).await?;
 
 	// Lookup the MX records associated with a name.
 	// The final dot forces this to be an FQDN, otherwise the search rules as specified
 	// in `ResolverOpts` will take effect. FQDN's are generally cheaper queries.
-	match resolver.mx_lookup(syntax.domain.as_ref()).await {
+	match resolver.mx_lookup(domain).await {
 		Ok(lookup) => Ok(MxDetails::from(lookup)),
-		Err(err) => Ok(MxDetails { lookup: Err(err) }),
+		Err(err) => match implicit_mx(&resolver, domain, &err).await {
+			Some(lookup) => Ok(MxDetails {
+				lookup: Ok(lookup),
+				is_implicit_mx: true,
+			}),
+			None => Ok(MxDetails {
+				lookup: Err(err),
+				is_implicit_mx: false,
+			}),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{check_mx, create_resolver, implicit_mx_lookup, is_null_mx, MxDetails};
+	use crate::syntax::check_syntax;
+	use crate::util::{
+		input_output::{CheckEmailInput, DnsResolver},
+		ttl_cache::TtlCache,
+	};
+	use async_std_resolver::ResolveError;
+	use std::str::FromStr;
+	use std::time::Duration;
+	use tokio::runtime::Runtime;
+	use trust_dns_proto::rr::{rdata::MX, Name};
+
+	#[test]
+	fn should_detect_null_mx() {
+		let mx = |host: &str| MX::new(0, Name::from_str(host).unwrap());
+
+		assert!(is_null_mx(&[mx(".")]));
+		assert!(!is_null_mx(&[mx("mx.example.org.")]));
+		assert!(!is_null_mx(&[mx("."), mx("mx.example.org.")]));
+		assert!(!is_null_mx(&[]));
+	}
+
+	#[test]
+	fn should_use_domain_as_implicit_mx() {
+		let domain = Name::from_str("example.org.").unwrap();
+		let lookup = implicit_mx_lookup(domain.clone());
+
+		let records = lookup.iter().cloned().collect::<Vec<_>>();
+		assert_eq!(records, vec![MX::new(0, domain)]);
+		assert!(!is_null_mx(&records));
+	}
+
+	#[test]
+	fn should_reuse_cached_mx_lookup() {
+		let runtime = Runtime::new().unwrap();
+
+		let cache = TtlCache::new(Duration::from_secs(60));
+		cache.insert(
+			"example.org",
+			MxDetails {
+				lookup: Err(ResolveError::from("cached")),
+				is_implicit_mx: false,
+			},
+		);
+		let mut input = CheckEmailInput::default();
+		input.set_mx_cache(cache);
+
+		let details = runtime
+			.block_on(check_mx(&check_syntax("foo@Example.org"), &input))
+			.unwrap();
+		assert_eq!(details.lookup.unwrap_err().to_string(), "cached");
+	}
+
+	#[test]
+	fn should_create_resolver_with_custom_nameservers() {
+		let runtime = Runtime::new().unwrap();
+
+		let mut input = CheckEmailInput::default();
+		input.set_dns_resolver(DnsResolver::Nameservers(vec![
+			"10.0.0.53:53".parse().unwrap(),
+			"[fd00::53]:5353".parse().unwrap(),
+		]));
+
+		assert!(runtime.block_on(create_resolver(&input)).is_ok());
 	}
 }
@@ -0,0 +1,235 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::util::input_output::CheckEmailInput;
+use serde::{Deserialize, Serialize};
+
+/// What a server's rejection of `RCPT TO` says about the email, as guessed
+/// from the rejection message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Classification {
+	/// The email account is disabled or blocked.
+	Disabled,
+	/// The inbox is full.
+	FullInbox,
+	/// The account receives too many emails right now, so it exists.
+	ReceivingAtRate,
+	/// The email account doesn't exist.
+	UserUnknown,
+	/// The server refused the email because of an anti-spam policy, e.g. the
+	/// sender's reputation. This says nothing about the email.
+	Blocked,
+}
+
+/// The built-in lowercase substrings of rejection messages, with their
+/// classification. They're checked in order, so the first match wins.
+const PATTERNS: &[(&str, Classification)] = &[
+	// 554 The email account that you tried to reach is disabled. Learn more at https://support.google.com/mail/?p=DisabledUser"
+	("disabled", Classification::Disabled),
+	// 554 delivery error: Sorry your message to [email] cannot be delivered. This account has been disabled or discontinued
+	("discontinued", Classification::Disabled),
+	("insufficient", Classification::FullInbox),
+	("over quota", Classification::FullInbox),
+	// 550 user has too many messages on the server
+	("too many messages", Classification::FullInbox),
+	// 4.2.1 The user you are trying to contact is receiving mail at a rate that
+	(
+		"the user you are trying to contact is receiving mail at a rate that",
+		Classification::ReceivingAtRate,
+	),
+	// 550 Address rejected
+	// 550 5.1.1 : Recipient address rejected
+	// 550 5.1.1 : Recipient address rejected: User unknown in virtual alias table
+	// 550 5.1.1 <user@domain.com>: Recipient address rejected: User unknown in relay recipient table
+	("address rejected", Classification::UserUnknown),
+	// 550 5.1.1 : Unrouteable address
+	("unrouteable", Classification::UserUnknown),
+	// 550 5.1.1 : The email account that you tried to reach does not exist
+	("does not exist", Classification::UserUnknown),
+	// 550 invalid address
+	// 550 User not local or invalid address – Relay denied
+	("invalid address", Classification::UserUnknown),
+	// 5.1.1 Invalid email address
+	("invalid email address", Classification::UserUnknown),
+	// 550 Invalid recipient
+	("invalid recipient", Classification::UserUnknown),
+	("may not exist", Classification::UserUnknown),
+	("recipient invalid", Classification::UserUnknown),
+	// 550 5.1.1 : Recipient rejected
+	("recipient rejected", Classification::UserUnknown),
+	("undeliverable", Classification::UserUnknown),
+	// 550 User unknown
+	// 550 5.1.1 <EMAIL> User unknown
+	// 550 recipient address rejected: user unknown in local recipient table
+	("user unknown", Classification::UserUnknown),
+	// 550 Unknown user
+	("unknown user", Classification::UserUnknown),
+	// 5.1.1 Recipient unknown <EMAIL>
+	("recipient unknown", Classification::UserUnknown),
+	// 550 5.1.1 No such user - pp
+	// 550 No such user here
+	("no such user", Classification::UserUnknown),
+	// 550 5.1.1 : Mailbox not found
+	// 550 Unknown address error ‘MAILBOX NOT FOUND’
+	("not found", Classification::UserUnknown),
+	// 550 5.1.1 : Invalid mailbox
+	("invalid mailbox", Classification::UserUnknown),
+	// 550 5.1.1 Sorry, no mailbox here by that name
+	("no mailbox", Classification::UserUnknown),
+	// 5.2.0 No such mailbox
+	("no such mailbox", Classification::UserUnknown),
+	// 550 Requested action not taken: mailbox unavailable
+	("mailbox unavailable", Classification::UserUnknown),
+	// 550 5.1.1 Is not a valid mailbox
+	("not a valid mailbox", Classification::UserUnknown),
+	// No such recipient here
+	("no such recipient", Classification::UserUnknown),
+	// 554 delivery error: This user doesn’t have an account
+	("have an account", Classification::UserUnknown),
+	// 554 delivery error: dd This user doesn't have a yahoo.com account
+	("user doesn't have a", Classification::UserUnknown),
+	// 5.1.1 RCP-P1 Domain facebook.com no longer available https://www.facebook.com/postmaster/response_codes?ip=3.80.111.155#RCP-P1
+	("no longer available", Classification::UserUnknown),
+	// Anti-spam blocks come last, as messages about unknown users sometimes
+	// mention them, e.g. "Recipient address rejected: Access denied".
+	// 550 5.7.1 Access denied
+	("access denied", Classification::Blocked),
+	// 554 5.7.1 Message rejected due to policy
+	("due to policy", Classification::Blocked),
+	// 550 5.7.1 Rejected for policy reasons
+	("policy reasons", Classification::Blocked),
+	// 554 5.7.1 Service unavailable; Client host blocked using Spamhaus
+	("spam", Classification::Blocked),
+	// 550 5.7.1 Poor sender reputation
+	("reputation", Classification::Blocked),
+	// 554 5.7.1 Message blocked
+	("blocked", Classification::Blocked),
+];
+
+/// Lowercase substrings of the replies of Yahoo's SMTP servers, which the
+/// built-in patterns get wrong, with their classification, see
+/// `YahooVerifMethod::Smtp`.
+pub(super) const YAHOO_PATTERNS: &[(&str, Classification)] = &[
+	// 554 30 Sorry, your message to <EMAIL> cannot be delivered. This mailbox is disabled (554.30).
+	("(554.30)", Classification::Disabled),
+	// 421 4.7.0 [TSS04] Messages from 1.2.3.4 temporarily deferred due to unexpected volume or user complaints
+	("[tss0", Classification::Blocked),
+	// 421 4.7.0 [TS01] Messages from 1.2.3.4 temporarily deferred due to user complaints
+	("[ts0", Classification::Blocked),
+	// 553 5.7.1 [BL21] Connections will not be accepted from 1.2.3.4, because the ip is in Spamhaus's list
+	("[bl2", Classification::Blocked),
+];
+
+/// Classify a rejection `message`, using the patterns of
+/// `input.rcpt_error_patterns` first, then the built-in ones, and finally
+/// `input.extra_unknown_user_patterns`. Matching is case-insensitive.
+/// Returns None if no pattern matches.
+pub(super) fn classify_message(message: &str, input: &CheckEmailInput) -> Option<Classification> {
+	let message = message.to_lowercase();
+	let extra_patterns = input
+		.extra_unknown_user_patterns
+		.iter()
+		.map(|pattern| (pattern.as_str(), Classification::UserUnknown));
+
+	input
+		.rcpt_error_patterns
+		.iter()
+		.map(|(pattern, classification)| (pattern.as_str(), *classification))
+		.chain(PATTERNS.iter().copied())
+		.chain(extra_patterns)
+		.find(|(pattern, _)| message.contains(&pattern.to_lowercase()))
+		.map(|(_, classification)| classification)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{classify_message, Classification, PATTERNS, YAHOO_PATTERNS};
+	use crate::util::input_output::CheckEmailInput;
+
+	#[test]
+	fn should_match_each_pattern() {
+		let input = CheckEmailInput::default();
+
+		for (pattern, classification) in PATTERNS {
+			assert_eq!(pattern.to_lowercase(), *pattern);
+			// Each pattern must map to its own classification, i.e. not be
+			// shadowed by an earlier one.
+			assert_eq!(
+				classify_message(&format!("550 {}", pattern), &input),
+				Some(*classification),
+				"{}",
+				pattern
+			);
+		}
+		assert_eq!(classify_message("550 Go away", &input), None);
+	}
+
+	#[test]
+	fn should_not_shadow_yahoo_patterns() {
+		let mut input = CheckEmailInput::default();
+		for (pattern, classification) in YAHOO_PATTERNS {
+			input.add_rcpt_error_pattern(pattern.to_string(), *classification);
+		}
+
+		assert_eq!(
+			classify_message(
+				"421 4.7.0 [TSS04] Messages from 1.2.3.4 temporarily deferred due to unexpected volume or user complaints",
+				&input
+			),
+			Some(Classification::Blocked)
+		);
+		assert_eq!(
+			classify_message(
+				"554 30 Sorry, your message to foo@yahoo.com cannot be delivered. This mailbox is disabled (554.30).",
+				&input
+			),
+			Some(Classification::Disabled)
+		);
+	}
+
+	#[test]
+	fn should_check_custom_patterns_first() {
+		let mut input = CheckEmailInput::default();
+		input.add_rcpt_error_pattern("Mailbox Is Frozen".into(), Classification::Disabled);
+		input.add_rcpt_error_pattern("not found".into(), Classification::FullInbox);
+
+		assert_eq!(
+			classify_message("550 5.2.1 mailbox is frozen", &input),
+			Some(Classification::Disabled)
+		);
+		assert_eq!(
+			classify_message("550 Mailbox not found", &input),
+			Some(Classification::FullInbox)
+		);
+	}
+
+	#[test]
+	fn should_match_extra_unknown_user_patterns() {
+		let mut input = CheckEmailInput::default();
+		input.set_extra_unknown_user_patterns(vec!["Adresse Inconnue".into()]);
+
+		assert_eq!(
+			classify_message("550 5.1.1 adresse inconnue", &input),
+			Some(Classification::UserUnknown)
+		);
+		// The built-in patterns still win.
+		assert_eq!(
+			classify_message("550 Adresse inconnue: account disabled", &input),
+			Some(Classification::Disabled)
+		);
+	}
+}
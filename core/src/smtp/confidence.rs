@@ -0,0 +1,197 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{Provider, SmtpDetails};
+use serde::{Deserialize, Serialize};
+
+/// A signal taken into account in the [`Confidence`] score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfidenceFactor {
+	/// The local part of the email is invalid, see
+	/// `SmtpDetails::invalid_local_part`.
+	InvalidLocalPart,
+	/// The domain doesn't accept emails, see `SmtpDetails::has_null_mx`.
+	NullMx,
+	/// We didn't get an answer from the SMTP server, e.g. we couldn't
+	/// connect, the check timed out or was skipped, or the server blocked
+	/// us.
+	NoAnswer,
+	/// The domain accepts all emails, so the answer says nothing about this
+	/// one. If the server rejected the email anyway, see
+	/// `CheckEmailInput::strict_catch_all`, it only weakens the rejection.
+	CatchAll,
+	/// The provider accepts all recipients, see `SmtpDetails::is_unverifiable`.
+	Unverifiable,
+	/// The server accepted the recipient.
+	Accepted,
+	/// The server accepted the recipient, but the inbox is full.
+	FullInbox,
+	/// The server deferred the recipient, as it receives too many emails
+	/// right now, see `SmtpDetails::is_rate_limited`.
+	RateLimited,
+	/// The server rejected the recipient with a code saying that the mailbox
+	/// doesn't exist or is disabled, e.g. "5.1.1".
+	SpecificRejection,
+	/// The server rejected the recipient, without telling why.
+	GenericRejection,
+	/// The answer only came after retries, e.g. because of greylisting.
+	Retried,
+	/// `VRFY` contradicted `RCPT TO`.
+	VrfyMismatch,
+	/// The answer depended on the `MAIL FROM` address, see
+	/// `SmtpDetails::has_from_email_mismatch`.
+	FromEmailMismatch,
+	/// The provider isn't one whose answers we know to be reliable.
+	UnknownProvider,
+}
+
+/// How sure we are that an email exists, from 0.0 (surely not) to 1.0
+/// (surely), with the signals which led to this score.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Confidence {
+	pub score: f32,
+	/// The signals taken into account, starting with the one which gave
+	/// the initial score. The other ones bring the score closer to 0.5.
+	pub factors: Vec<ConfidenceFactor>,
+}
+
+impl Confidence {
+	fn new(score: f32, factor: ConfidenceFactor) -> Self {
+		Confidence {
+			score,
+			factors: vec![factor],
+		}
+	}
+
+	/// Bring the score closer to 0.5, i.e. "we don't know", by `ratio`.
+	fn weaken(&mut self, ratio: f32, factor: ConfidenceFactor) {
+		self.score = 0.5 + (self.score - 0.5) * (1.0 - ratio);
+		self.factors.push(factor);
+	}
+}
+
+impl SmtpDetails {
+	/// Summarize these details into a confidence score that the email
+	/// exists. The score only depends on these details, and is meant to be
+	/// compared with thresholds tuned by the caller.
+	pub fn confidence(&self) -> Confidence {
+		if self.invalid_local_part.is_some() {
+			return Confidence::new(0.0, ConfidenceFactor::InvalidLocalPart);
+		}
+		if self.has_null_mx {
+			return Confidence::new(0.0, ConfidenceFactor::NullMx);
+		}
+		if self.is_skipped || self.is_timed_out || self.is_blocked || !self.can_connect_smtp {
+			return Confidence::new(0.5, ConfidenceFactor::NoAnswer);
+		}
+		if self.is_catch_all && !self.is_catch_all_rejection() {
+			return Confidence::new(0.5, ConfidenceFactor::CatchAll);
+		}
+		if self.is_unverifiable {
+			return Confidence::new(0.5, ConfidenceFactor::Unverifiable);
+		}
+
+		let mut confidence = if self.is_rate_limited {
+			Confidence::new(0.85, ConfidenceFactor::RateLimited)
+		} else if self.is_deliverable {
+			Confidence::new(0.95, ConfidenceFactor::Accepted)
+		} else if self.has_full_inbox {
+			Confidence::new(0.9, ConfidenceFactor::FullInbox)
+		} else if self.is_disabled
+			|| self
+				.smtp_enhanced_code
+				.as_deref()
+				.map_or(false, |code| code.starts_with("5.1."))
+		{
+			Confidence::new(0.02, ConfidenceFactor::SpecificRejection)
+		} else {
+			Confidence::new(0.15, ConfidenceFactor::GenericRejection)
+		};
+
+		if self.attempts > 1 || self.is_greylisted {
+			confidence.weaken(0.2, ConfidenceFactor::Retried);
+		}
+		if self.is_catch_all {
+			// A rejection on a catch-all domain, see
+			// `CheckEmailInput::strict_catch_all`.
+			confidence.weaken(0.2, ConfidenceFactor::CatchAll);
+		}
+		if self.has_vrfy_mismatch {
+			confidence.weaken(0.5, ConfidenceFactor::VrfyMismatch);
+		}
+		if self.has_from_email_mismatch {
+			confidence.weaken(0.3, ConfidenceFactor::FromEmailMismatch);
+		}
+		if self.provider == Provider::Other {
+			confidence.weaken(0.1, ConfidenceFactor::UnknownProvider);
+		}
+
+		confidence
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ConfidenceFactor, Provider, SmtpDetails};
+
+	#[test]
+	fn should_score_confidence() {
+		let accepted = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			attempts: 1,
+			provider: Provider::Google,
+			..Default::default()
+		};
+		let confidence = accepted.confidence();
+		assert_eq!(confidence.score, 0.95);
+		assert_eq!(confidence.factors, vec![ConfidenceFactor::Accepted]);
+
+		let user_unknown = SmtpDetails {
+			is_deliverable: false,
+			smtp_enhanced_code: Some("5.1.1".into()),
+			..accepted
+		};
+		assert!(user_unknown.confidence().score < 0.05);
+
+		let catch_all = SmtpDetails {
+			is_catch_all: true,
+			..user_unknown
+		};
+		assert_eq!(catch_all.confidence().score, 0.5);
+	}
+
+	#[test]
+	fn should_weaken_confidence() {
+		let confidence = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			attempts: 2,
+			..Default::default()
+		}
+		.confidence();
+		assert!(confidence.score > 0.5 && confidence.score < 0.95);
+		assert_eq!(
+			confidence.factors,
+			vec![
+				ConfidenceFactor::Accepted,
+				ConfidenceFactor::Retried,
+				ConfidenceFactor::UnknownProvider
+			]
+		);
+	}
+}
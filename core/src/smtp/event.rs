@@ -0,0 +1,100 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{SmtpDetails, SmtpError};
+use async_smtp::EmailAddress;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use trust_dns_proto::rr::Name;
+
+/// An event happening during a SMTP check, reported to the hook set with
+/// `CheckEmailInput::set_smtp_event_hook`, e.g. to collect metrics.
+#[derive(Debug)]
+pub enum SmtpEvent<'a> {
+	/// We tried to connect to the SMTP server and to start a mail
+	/// transaction. Reused connections from the pool don't trigger this
+	/// event.
+	Connect {
+		host: &'a Name,
+		port: u16,
+		/// The time spent connecting, up to the `MAIL FROM` reply.
+		duration: Duration,
+		/// The error, if the connection failed.
+		error: Option<&'a SmtpError>,
+	},
+	/// The server replied to the `RCPT TO` command, or the command failed.
+	RcptTo {
+		to_email: &'a EmailAddress,
+		/// The time the server took to reply.
+		duration: Duration,
+		/// The SMTP reply code, if any.
+		smtp_code: Option<u16>,
+		/// Did the server accept the recipient?
+		is_accepted: bool,
+	},
+	/// An attempt to check the email on a host finished, see
+	/// `CheckEmailInput::retries`. `SmtpDetails::provider` tells the
+	/// provider of the host, if the attempt succeeded.
+	Attempt {
+		to_email: &'a EmailAddress,
+		host: &'a Name,
+		port: u16,
+		/// The attempt number, starting at 1.
+		attempt: usize,
+		duration: Duration,
+		result: &'a Result<SmtpDetails, SmtpError>,
+	},
+	/// The attempt failed with a retryable error, and we'll try again.
+	Retry {
+		to_email: &'a EmailAddress,
+		host: &'a Name,
+		port: u16,
+		/// The number of the attempt which failed, starting at 1.
+		attempt: usize,
+		/// How long we wait before the next attempt.
+		delay: Option<Duration>,
+		/// Did the server greylist us?
+		is_greylisted: bool,
+	},
+}
+
+/// A callback receiving the events of SMTP checks. Cloning the hook is
+/// cheap, and all clones call the same callback.
+#[derive(Clone)]
+pub struct SmtpEventHook(Arc<dyn Fn(&SmtpEvent) + Send + Sync>);
+
+impl fmt::Debug for SmtpEventHook {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("SmtpEventHook")
+	}
+}
+
+impl SmtpEventHook {
+	/// Create a hook calling `on_smtp_event` for each event. The callback is
+	/// called inline, so it should return quickly.
+	pub fn new<F>(on_smtp_event: F) -> Self
+	where
+		F: Fn(&SmtpEvent) + Send + Sync + 'static,
+	{
+		SmtpEventHook(Arc::new(on_smtp_event))
+	}
+
+	/// Report an event to the callback.
+	pub(super) fn emit(&self, event: &SmtpEvent) {
+		(self.0)(event)
+	}
+}
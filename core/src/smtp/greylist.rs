@@ -0,0 +1,132 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{parse_retry_delay_hint, SmtpError};
+use crate::util::input_output::CheckEmailInput;
+use async_smtp::{smtp::error::Error as AsyncSmtpError, EmailAddress};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long to wait before resuming a greylisted check, if the server doesn't
+/// suggest a delay. Most greylisting setups reject retries for a few minutes.
+const DEFAULT_GREYLIST_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// What's needed to resume a check deferred because the server greylisted
+/// us, see `CheckEmailInput::defer_greylisting`. It serializes to JSON, so
+/// that it can be stored e.g. in a job queue, then passed back with
+/// `CheckEmailInput::set_greylist_token`.
+///
+/// Greylisting remembers the sender, the recipient and the client's IP, so
+/// the resumed check connects to the same host with the same `MAIL FROM`.
+/// It should also go through the same proxy, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GreylistToken {
+	/// The email being checked.
+	pub to_email: String,
+	/// The MX host which greylisted us.
+	pub host: String,
+	pub port: u16,
+	/// The `MAIL FROM` address of the greylisted check.
+	pub from_email: String,
+	/// When to resume the check, in seconds since the Unix epoch: the delay
+	/// suggested by the server, or 5 minutes from the greylisting.
+	pub retry_after: u64,
+}
+
+impl GreylistToken {
+	pub(super) fn new(
+		to_email: &EmailAddress,
+		host: String,
+		port: u16,
+		err: &SmtpError,
+		input: &CheckEmailInput,
+	) -> Self {
+		let delay = match err {
+			SmtpError::SmtpError(AsyncSmtpError::Transient(response)) => {
+				parse_retry_delay_hint(response)
+			}
+			_ => None,
+		}
+		.unwrap_or(DEFAULT_GREYLIST_DELAY);
+
+		GreylistToken {
+			to_email: to_email.to_string(),
+			host,
+			port,
+			from_email: input.from_email.clone(),
+			retry_after: (SystemTime::now() + delay)
+				.duration_since(UNIX_EPOCH)
+				.map(|since_epoch| since_epoch.as_secs())
+				.unwrap_or_default(),
+		}
+	}
+
+	/// How long to wait before resuming the check, zero if it's already
+	/// time.
+	pub fn remaining(&self) -> Duration {
+		(UNIX_EPOCH + Duration::from_secs(self.retry_after))
+			.duration_since(SystemTime::now())
+			.unwrap_or_default()
+	}
+
+	/// Does this token resume the check of `to_email`?
+	pub(super) fn resumes(&self, to_email: &EmailAddress) -> bool {
+		self.to_email.eq_ignore_ascii_case(to_email.as_ref())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{GreylistToken, SmtpError};
+	use crate::util::input_output::CheckEmailInput;
+	use async_smtp::smtp::{error::Error as AsyncSmtpError, response::Response};
+	use async_smtp::EmailAddress;
+	use std::str::FromStr;
+	use std::time::Duration;
+
+	#[test]
+	fn should_use_the_suggested_delay() {
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let input = CheckEmailInput::default();
+		let err = |reply: &str| {
+			SmtpError::SmtpError(AsyncSmtpError::Transient(
+				Response::from_str(reply).unwrap(),
+			))
+		};
+
+		let token = GreylistToken::new(
+			&to_email,
+			"mx.example.org".into(),
+			25,
+			&err("451 4.7.1 Greylisted, please try again in 2 minutes\r\n"),
+			&input,
+		);
+		assert!(token.remaining() > Duration::from_secs(110));
+		assert!(token.remaining() <= Duration::from_secs(120));
+		assert_eq!(token.from_email, input.from_email);
+		assert!(token.resumes(&EmailAddress::from_str("Foo@example.org").unwrap()));
+		assert!(!token.resumes(&EmailAddress::from_str("bar@example.org").unwrap()));
+
+		let token = GreylistToken::new(
+			&to_email,
+			"mx.example.org".into(),
+			25,
+			&err("451 4.7.1 Greylisted\r\n"),
+			&input,
+		);
+		assert!(token.remaining() > Duration::from_secs(290));
+	}
+}
@@ -0,0 +1,112 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{
+	create_smtp_future, parse_smtp_code, parse_smtp_enhanced_code, Progress, SmtpDetails, SmtpError,
+};
+use crate::util::{constants::LOG_TARGET, input_output::CheckEmailInput};
+use async_smtp::{
+	smtp::{error::Error as AsyncSmtpError, response::Response},
+	EmailAddress,
+};
+use trust_dns_proto::rr::Name;
+
+/// Interpret a permanent rejection of `RCPT TO` using the enhanced status
+/// codes documented by Microsoft, whose messages don't always contain the
+/// phrases we look for on other servers. Returns None if the code doesn't
+/// tell anything about the recipient, e.g. when our IP is blocked (5.7.x).
+///
+/// See https://learn.microsoft.com/en-us/exchange/mail-flow-best-practices/non-delivery-reports-in-exchange-online/non-delivery-reports-in-exchange-online
+fn details_from_rejection(response: &Response) -> Option<SmtpDetails> {
+	let smtp_enhanced_code = parse_smtp_enhanced_code(response)?;
+	let (has_full_inbox, is_disabled) = match smtp_enhanced_code.as_str() {
+		// 550 5.1.1 RESOLVER.ADR.ExRecipNotFound; not found
+		// 550 5.1.10 RESOLVER.ADR.RecipientNotFound; Recipient not found by SMTP address lookup
+		// 550 5.4.1 Recipient address rejected: Access denied. AS(201806281)
+		"5.1.1" | "5.1.10" | "5.4.1" => (false, false),
+		// 552 5.2.2 mailbox full
+		"5.2.2" => (true, false),
+		// 550 5.2.1 mailbox disabled
+		"5.2.1" => (false, true),
+		_ => return None,
+	};
+
+	Some(SmtpDetails {
+		can_connect_smtp: true,
+		has_full_inbox,
+		is_disabled,
+		smtp_code: parse_smtp_code(response),
+		smtp_enhanced_code: Some(smtp_enhanced_code),
+		smtp_message: Some(response.message.join(" ")),
+		..Default::default()
+	})
+}
+
+/// Verify an email hosted by Microsoft via SMTP, like any other email, but
+/// with a Microsoft-specific interpretation of the rejections which are
+/// ambiguous otherwise.
+pub(super) async fn check_microsoft(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	progress: &mut Progress,
+) -> Result<SmtpDetails, SmtpError> {
+	match create_smtp_future(to_email, host, port, domain, input, progress).await {
+		Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(response))) => {
+			details_from_rejection(&response).ok_or_else(|| {
+				log::debug!(
+					target: LOG_TARGET,
+					"email={} Unknown rejection from Microsoft: {:?}",
+					to_email,
+					response
+				);
+				SmtpError::SmtpError(AsyncSmtpError::Permanent(response))
+			})
+		}
+		result => result,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::details_from_rejection;
+	use async_smtp::smtp::response::Response;
+	use std::str::FromStr;
+
+	#[test]
+	fn should_interpret_microsoft_rejections() {
+		let details = |reply: &str| details_from_rejection(&Response::from_str(reply).unwrap());
+
+		let access_denied =
+			details("550 5.4.1 Recipient address rejected: Access denied. AS(201806281)\r\n")
+				.unwrap();
+		assert!(!access_denied.is_deliverable);
+		assert_eq!(access_denied.smtp_code, Some(550));
+		assert!(
+			details("552 5.2.2 mailbox full\r\n")
+				.unwrap()
+				.has_full_inbox
+		);
+		assert!(
+			details("550 5.2.1 mailbox disabled\r\n")
+				.unwrap()
+				.is_disabled
+		);
+		assert!(details("550 5.7.606 Access denied, banned sending IP\r\n").is_none());
+	}
+}
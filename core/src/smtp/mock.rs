@@ -0,0 +1,199 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A scripted in-process SMTP server, to test the SMTP checks end to end
+//! without a network.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The replies to the commands of one SMTP connection. Commands are matched
+/// against the rules in order, by case-insensitive prefix, and the first
+/// matching rule gives the reply. Unmatched commands get a positive reply.
+pub(super) struct MockSession {
+	greeting: &'static str,
+	/// The reply to an HTTP `CONNECT` request expected before the SMTP
+	/// conversation, if the session is behind a mock HTTP proxy.
+	proxy_reply: Option<&'static str>,
+	/// The prefix, the reply, and whether the rule only applies once.
+	rules: Vec<(&'static str, &'static str, bool)>,
+}
+
+impl MockSession {
+	/// A reply leaving the command unanswered, e.g. to trigger timeouts.
+	pub(super) const HANG: &'static str = "HANG";
+
+	/// A session accepting everything.
+	pub(super) fn new() -> Self {
+		MockSession {
+			greeting: "220 localhost ESMTP\r\n",
+			proxy_reply: None,
+			rules: vec![],
+		}
+	}
+
+	/// Act as an HTTP proxy first, answering the `CONNECT` request with
+	/// `reply`. The SMTP conversation only follows a "200" reply. The
+	/// request, with its headers, is recorded as a single command.
+	pub(super) fn http_proxy(mut self, reply: &'static str) -> Self {
+		self.proxy_reply = Some(reply);
+		self
+	}
+
+	/// Greet the client with `greeting` instead of the default one. An empty
+	/// greeting leaves the client waiting, e.g. to act as a silent proxy.
+	pub(super) fn greeting(mut self, greeting: &'static str) -> Self {
+		self.greeting = greeting;
+		self
+	}
+
+	/// Answer the commands starting with `prefix`, e.g. "RCPT TO:<foo@",
+	/// with `reply`. An empty reply closes the connection instead, and
+	/// [`MockSession::HANG`] never answers.
+	pub(super) fn on(mut self, prefix: &'static str, reply: &'static str) -> Self {
+		self.rules.push((prefix, reply, false));
+		self
+	}
+
+	/// Like [`MockSession::on`], but only for the first matching command.
+	pub(super) fn once(mut self, prefix: &'static str, reply: &'static str) -> Self {
+		self.rules.push((prefix, reply, true));
+		self
+	}
+
+	fn reply(&mut self, command: &str) -> &'static str {
+		let upper = command.to_uppercase();
+		if let Some(i) = self
+			.rules
+			.iter()
+			.position(|(prefix, _, _)| upper.starts_with(&prefix.to_uppercase()))
+		{
+			let (_, reply, once) = self.rules[i];
+			if once {
+				self.rules.remove(i);
+			}
+			return reply;
+		}
+
+		match upper.split_whitespace().next().unwrap_or_default() {
+			"EHLO" | "HELO" => "250 localhost\r\n",
+			"MAIL" => "250 2.1.0 Ok\r\n",
+			"RCPT" => "250 2.1.5 Ok\r\n",
+			"QUIT" => "221 2.0.0 Bye\r\n",
+			_ => "250 2.0.0 Ok\r\n",
+		}
+	}
+}
+
+/// A running mock server.
+pub(super) struct MockServer {
+	pub(super) port: u16,
+	commands: Arc<Mutex<Vec<String>>>,
+	/// The commands grouped by the write they came in, as far as we can
+	/// tell, i.e. a command joins the previous one's group if it was already
+	/// received when we read the previous one.
+	writes: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl MockServer {
+	/// Start a server on localhost, which accepts one connection per
+	/// session, in order.
+	pub(super) fn start(sessions: Vec<MockSession>) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let commands = Arc::new(Mutex::new(vec![]));
+		let writes: Arc<Mutex<Vec<Vec<String>>>> = Arc::new(Mutex::new(vec![]));
+
+		let server_commands = commands.clone();
+		let server_writes = writes.clone();
+		thread::spawn(move || {
+			for mut session in sessions {
+				let (stream, _) = match listener.accept() {
+					Ok(accepted) => accepted,
+					Err(_) => return,
+				};
+				let mut reader = BufReader::new(stream.try_clone().unwrap());
+				let mut writer = stream;
+				if let Some(proxy_reply) = session.proxy_reply {
+					let mut request = String::new();
+					while !request.ends_with("\r\n\r\n") {
+						if reader.read_line(&mut request).unwrap_or(0) == 0 {
+							break;
+						}
+					}
+					server_commands.lock().unwrap().push(request);
+					if writer.write_all(proxy_reply.as_bytes()).is_err()
+						|| !proxy_reply.starts_with("HTTP/1.1 200")
+					{
+						continue;
+					}
+				}
+				if writer.write_all(session.greeting.as_bytes()).is_err() {
+					continue;
+				}
+
+				let mut line = String::new();
+				let mut is_buffered = false;
+				while reader.read_line(&mut line).unwrap_or(0) > 0 {
+					let command = line.trim_end().to_string();
+					let reply = session.reply(&command);
+					let is_quit = command.eq_ignore_ascii_case("QUIT");
+					let mut writes = server_writes.lock().unwrap();
+					match writes.last_mut() {
+						Some(write) if is_buffered => write.push(command.clone()),
+						_ => writes.push(vec![command.clone()]),
+					}
+					drop(writes);
+					is_buffered = !reader.buffer().is_empty();
+					server_commands.lock().unwrap().push(command);
+					line.clear();
+					if reply == MockSession::HANG {
+						continue;
+					}
+					if reply.is_empty() || writer.write_all(reply.as_bytes()).is_err() || is_quit {
+						break;
+					}
+				}
+			}
+		});
+
+		MockServer {
+			port,
+			commands,
+			writes,
+		}
+	}
+
+	/// The commands received so far, over all connections.
+	pub(super) fn commands(&self) -> Vec<String> {
+		self.commands.lock().unwrap().clone()
+	}
+
+	/// The received commands, grouped by the write they came in.
+	pub(super) fn writes(&self) -> Vec<Vec<String>> {
+		self.writes.lock().unwrap().clone()
+	}
+
+	/// The number of received commands starting with `prefix`.
+	pub(super) fn count(&self, prefix: &str) -> usize {
+		self.commands()
+			.iter()
+			.filter(|command| command.to_uppercase().starts_with(&prefix.to_uppercase()))
+			.count()
+	}
+}
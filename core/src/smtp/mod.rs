@@ -14,34 +14,93 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod classification;
+mod confidence;
+mod event;
+mod gmail;
+mod greylist;
+mod microsoft;
+#[cfg(test)]
+mod mock;
+mod mta;
+mod net;
+mod pipelining;
+mod pool;
+mod protonmail;
+mod provider;
+mod rate_limit;
+mod reason;
+mod transcript;
 mod yahoo;
 
-use super::util::{constants::LOG_TARGET, input_output::CheckEmailInput};
-use crate::util::ser_with_display::ser_with_display;
-use async_native_tls::TlsConnector;
+use super::util::{
+	constants::LOG_TARGET,
+	input_output::{
+		CheckEmailInput, CheckEmailInputProxy, IpPreference, ProxyRotation, ProxyType, Reachable,
+		SmtpEndPolicy, SmtpSecurity, YahooVerifMethod,
+	},
+};
+use crate::dns;
+use crate::mx::is_null_mx;
+use crate::syntax::{validate_local_part, LocalPartError};
+use crate::util::{
+	cancellation::until_cancelled, deserialized_error::DeserializedError,
+	ser_with_display::ser_with_display, span::in_span,
+};
+use async_native_tls::{Certificate, TlsConnector};
 use async_recursion::async_recursion;
 use async_smtp::{
 	smtp::{
-		client::net::NetworkStream, commands::*, error::Error as AsyncSmtpError,
-		extension::ClientId,
+		authentication::{Credentials, Mechanism},
+		client::net::NetworkStream,
+		commands::*,
+		error::Error as AsyncSmtpError,
+		extension::{ClientId, MailParameter},
+		response::Response,
 	},
 	ClientTlsParameters, EmailAddress, SmtpClient, SmtpTransport,
 };
 use async_std::future;
+use async_std::net::{SocketAddr, TcpStream};
+use classification::classify_message;
 use fast_socks5::{
 	client::{Config, Socks5Stream},
-	Result, SocksError,
+	ReplyError, Result, SocksError,
 };
+use gmail::GmailError;
+use pool::PoolKey;
 use rand::rngs::SmallRng;
 use rand::{distributions::Alphanumeric, Rng, SeedableRng};
-use serde::{Deserialize, Serialize};
+use rate_limit::{ConnectionPermit, RateLimitPermit};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::default::Default;
+use std::fmt::{self, Display};
+use std::future::Future;
 use std::iter;
+use std::net::IpAddr;
 use std::str::FromStr;
-use std::time::Duration;
-use trust_dns_proto::rr::Name;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use transcript::Transcript;
+use trust_dns_proto::rr::{rdata::MX, Name};
 use yahoo::YahooError;
 
+pub use classification::Classification;
+pub use confidence::{Confidence, ConfidenceFactor};
+pub use event::{SmtpEvent, SmtpEventHook};
+pub use greylist::GreylistToken;
+pub use pool::SmtpPool;
+pub use provider::{classify, Provider};
+pub use rate_limit::{ConnectionLimit, RateLimiter};
+pub use reason::VerifReason;
+
+/// The `MAIL FROM` address used if neither `CheckEmailInput::from_email` nor
+/// its fallback are valid email addresses.
+const DEFAULT_FROM_EMAIL: &str = "user@example.org";
+
 /// Details that we gathered from connecting to this email via SMTP
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct SmtpDetails {
@@ -55,11 +114,210 @@ pub struct SmtpDetails {
 	pub is_deliverable: bool,
 	/// Is the email blocked or disabled by the provider?
 	pub is_disabled: bool,
+	/// Did the server refuse the email because of an anti-spam policy, e.g.
+	/// "access denied" or a "5.7.1" code? This says nothing about the email:
+	/// `is_deliverable` is false, but the email may well exist.
+	pub is_blocked: bool,
+	/// Did the server defer the email because the account receives too many
+	/// emails right now, e.g. Gmail's "4.2.1 The user you are trying to
+	/// contact is receiving mail at a rate that...". The account exists, so
+	/// `is_deliverable` stays true, but the email would be delayed.
+	pub is_rate_limited: bool,
+	/// The SMTP reply code (e.g. 250, 550) returned by the server to the
+	/// `RCPT TO` command, if any.
+	pub smtp_code: Option<u16>,
+	/// The enhanced status code (RFC 3463, e.g. "5.1.1") returned by the
+	/// server to the `RCPT TO` command, if any.
+	pub smtp_enhanced_code: Option<String>,
+	/// The text of the server's reply to the `RCPT TO` command, e.g.
+	/// "5.1.1 <foo@example.org>: Recipient address rejected: User unknown",
+	/// if any. On a catch-all domain, there's only a reply if
+	/// `CheckEmailInput::strict_catch_all` is set, so that the caller can
+	/// make their own call.
+	pub smtp_message: Option<String>,
+	/// When `CheckEmailInput::use_vrfy` is set, and the `VRFY` command
+	/// contradicted the `RCPT TO` command. In this case, the `RCPT TO` result
+	/// is the one reported.
+	pub has_vrfy_mismatch: bool,
+	/// When `CheckEmailInput::alternative_from_emails` is set, and the
+	/// server accepted the email with some `MAIL FROM` addresses but not with
+	/// others. In this case, the most specific answer is reported, e.g. a
+	/// "user unknown" rejection over an acceptance, and `from_email` tells
+	/// which address gave it.
+	pub has_from_email_mismatch: bool,
+	/// Is this email a mailing list, i.e. does the `EXPN` command expand it
+	/// into multiple recipients? Only checked if `CheckEmailInput::use_expn`
+	/// is set. Most servers disable `EXPN`, in which case this is false.
+	pub is_mailing_list: bool,
+	/// Is `is_deliverable` meaningless because the provider's SMTP servers
+	/// accept recipients without checking that they exist? This is the case
+	/// for ProtonMail, see [`Provider::ProtonMail`]. It's also the case for
+	/// emails whose local part isn't ASCII, if the server doesn't support
+	/// SMTPUTF8: we can't send them in `RCPT TO`.
+	pub is_unverifiable: bool,
+	/// Did the server greylist us during one of the attempts, i.e. reply
+	/// with a `451` or `421` code and a greylisting message? In this case,
+	/// the check was retried, and these details come from a later attempt.
+	///
+	/// If the server still greylists us after all the retries, `check_smtp`
+	/// returns an error instead, see [`SmtpError::is_greylisted`]. So does it
+	/// on the first greylisting if `CheckEmailInput::defer_greylisting` is
+	/// set.
+	pub is_greylisted: bool,
+	/// The number of attempts it took to get these details, see
+	/// `CheckEmailInput::retries`. It's more than 1 if the previous attempts
+	/// failed with a retryable error, e.g. a timeout or greylisting.
+	pub attempts: usize,
+	/// Did these details come from the last attempt allowed by
+	/// `CheckEmailInput::retries`, after the previous ones failed? E.g. we
+	/// couldn't connect on any attempt. It's false if the first attempt was
+	/// the only one, or if the last attempt fails with an error, which
+	/// `check_smtp` returns instead.
+	pub retries_exhausted: bool,
+	/// The pattern of `CheckEmailInput::catch_all_mx_patterns` which matched
+	/// the MX host, if any. In this case, we didn't connect to the SMTP
+	/// server, and only reported the domain as catch-all.
+	pub catch_all_mx_pattern: Option<String>,
+	/// The error of the catch-all probe, if it failed, e.g. because the
+	/// server rejected the probe with a policy error. In this case,
+	/// `is_catch_all` is false, and the check went on with the email.
+	pub catch_all_check_error: Option<String>,
+	/// The MX host these details come from, i.e. the host which answered.
+	pub mx_host: Option<String>,
+	/// The priority (preference) of `mx_host` in the domain's MX records.
+	/// None if the host wasn't picked from MX records, e.g. with
+	/// [`check_smtp`].
+	pub mx_priority: Option<u16>,
+	/// The IP address of `mx_host` we connected to. None if we connected
+	/// through a proxy, which resolved the host itself.
+	pub mx_ip: Option<IpAddr>,
+	/// The MTA software of the server, as told by its greeting and its
+	/// `EHLO` reply, e.g. "Postfix" or "Exim 4.94.2". None if the greeting
+	/// doesn't tell.
+	pub mta_software: Option<String>,
+	/// The email provider of this MX host, see [`classify`].
+	pub provider: Provider,
+	/// The extensions advertised by the server in its `EHLO` reply, with
+	/// their parameters, e.g. "PIPELINING", "SIZE 35882577" or "SMTPUTF8".
+	/// Usually empty unless `CheckEmailInput::report_smtp_extensions` is set.
+	pub supported_extensions: Vec<String>,
+	/// The maximum message size in bytes accepted by the server, from the
+	/// `SIZE` extension (RFC 1870). None if the server doesn't advertise
+	/// `SIZE`, or advertises it without a limit.
+	pub max_message_size: Option<u64>,
+	/// The address we used in the `MAIL FROM` command. It differs from
+	/// `CheckEmailInput::from_email` if the latter isn't a valid email, see
+	/// `CheckEmailInput::from_email_fallback`. An empty string means that we
+	/// used the null sender, see `CheckEmailInput::use_null_sender`.
+	pub from_email: Option<String>,
+	/// The proxy we connected through, as "host:port", or None if we
+	/// connected directly to the SMTP server. With several proxies, this is
+	/// the one which worked, see `CheckEmailInput::proxies`.
+	pub used_proxy: Option<String>,
+	/// The local IP address we connected from, if known. Through a proxy,
+	/// this is the address we reached the proxy from, and the SMTP server
+	/// saw the proxy's address instead.
+	pub source_ip: Option<IpAddr>,
+	/// Why the local part of the email is invalid, if it is. In this case,
+	/// we didn't connect to the server, and `is_deliverable` is false. See
+	/// `CheckEmailInput::strict_local_part`.
+	pub invalid_local_part: Option<LocalPartError>,
+	/// Does the domain publish a null MX (RFC 7505), declaring that it
+	/// doesn't accept emails? In this case, we didn't connect to any server,
+	/// and `is_deliverable` is false.
+	pub has_null_mx: bool,
+	/// Did we skip the SMTP verification step, because
+	/// `CheckEmailInput::skip_smtp` is set? In this case, we didn't connect
+	/// to the server, and all the other fields have their default values.
+	pub is_skipped: bool,
+	/// Did the check time out after we connected to the server, see
+	/// `CheckEmailInput::smtp_timeout`? In this case, the other fields are
+	/// what we learned before the timeout, e.g. `is_catch_all` if the
+	/// catch-all probe finished, and `is_deliverable` is false.
+	///
+	/// If the check times out before we could connect, `check_smtp` returns
+	/// an error instead.
+	pub is_timed_out: bool,
+	/// Why the email isn't safe to send to, e.g. `UserUnknown` or `CatchAll`,
+	/// derived from the other fields. None if the email is deliverable. See
+	/// [`SmtpError::reason`] for failed checks.
+	pub reason: Option<VerifReason>,
+	/// The raw SMTP conversation, only collected if
+	/// `CheckEmailInput::collect_transcript` is set. Lines sent to the server
+	/// are prefixed with "> ", lines received with "< ".
+	///
+	/// Note: the greeting and the initial `EHLO` exchange happen inside
+	/// async-smtp's `connect()`, and are not part of the transcript. The
+	/// transcript starts with a second `EHLO`, which we send to get the
+	/// server's extensions.
+	pub transcript: Vec<String>,
+}
+
+impl SmtpDetails {
+	/// The details of an email whose domain has no MX host, e.g. because it
+	/// doesn't exist (NXDOMAIN) or its DNS fails (SERVFAIL). We don't connect
+	/// to any server, so `can_connect_smtp` is false.
+	pub(crate) fn domain_not_found() -> Self {
+		SmtpDetails {
+			reason: Some(VerifReason::DomainNotFound),
+			..Default::default()
+		}
+	}
+
+	/// Did the server reject the email, although the domain is catch-all?
+	/// This only happens with `CheckEmailInput::strict_catch_all`, as we
+	/// otherwise don't send the `RCPT TO` command on catch-all domains.
+	pub(crate) fn is_catch_all_rejection(&self) -> bool {
+		self.is_catch_all && !self.is_deliverable && self.smtp_code.is_some()
+	}
+
+	/// A single verdict on the email, from these SMTP details only. Unlike
+	/// `is_deliverable`, it tells apart the emails we can't conclude on:
+	/// - `Invalid` if the local part is invalid, or the domain doesn't
+	///   accept emails (no MX host, or a null MX),
+	/// - `Unknown` if the SMTP check was skipped or timed out, we couldn't
+	///   connect to the server (e.g. because our port 25 is blocked), or the
+	///   server blocked the email because of an anti-spam policy,
+	/// - `Risky` for catch-all domains, full inboxes, or providers which
+	///   can't be verified, unless the server rejected the email on a
+	///   catch-all domain, see `CheckEmailInput::strict_catch_all`,
+	/// - `Invalid` if the email was rejected or is disabled,
+	/// - `Safe` otherwise.
+	///
+	/// A failed check, i.e. `Err(SmtpError)`, would be `Unknown`.
+	pub fn reachable(&self) -> Reachable {
+		if self.invalid_local_part.is_some()
+			|| self.has_null_mx
+			|| self.reason == Some(VerifReason::DomainNotFound)
+		{
+			Reachable::Invalid
+		} else if self.is_skipped
+			|| self.is_timed_out
+			|| self.is_blocked
+			|| (!self.can_connect_smtp && self.catch_all_mx_pattern.is_none())
+		{
+			Reachable::Unknown
+		} else if (self.is_catch_all && !self.is_catch_all_rejection())
+			|| self.has_full_inbox
+			|| self.is_rate_limited
+			|| self.is_unverifiable
+		{
+			Reachable::Risky
+		} else if !self.is_deliverable || self.is_disabled {
+			Reachable::Invalid
+		} else {
+			Reachable::Safe
+		}
+	}
 }
 
 /// Error occured connecting to this email server via SMTP.
+///
+/// It serializes with its variant in `type` and its human-readable details
+/// in `message`, as well as the stable, machine-readable `kind` and
+/// `sub_kind`, see [`SmtpError::kind`].
 #[derive(Debug, Serialize)]
-#[serde(tag = "type", content = "message")]
+#[serde(remote = "Self", tag = "type", content = "message")]
 pub enum SmtpError {
 	/// Error if we're using a SOCKS5 proxy.
 	#[serde(serialize_with = "ser_with_display")]
@@ -72,6 +330,235 @@ pub enum SmtpError {
 	TimeoutError(future::TimeoutError),
 	/// Error when verifying a Yahoo email.
 	YahooError(YahooError),
+	/// Error when verifying a Gmail email.
+	GmailError(GmailError),
+	/// `CheckEmailInput::smtp_security` is `Required`, but the server doesn't
+	/// advertise STARTTLS.
+	StartTlsUnavailable,
+	/// The server's reply says that the IP we connect from (e.g. the proxy's)
+	/// is blocked, see `CheckEmailInput::ip_block_indicators`. This says
+	/// nothing about the email, which should be checked again from another
+	/// IP.
+	#[serde(serialize_with = "ser_with_display")]
+	IpBlocked(AsyncSmtpError),
+	/// The server rejected our `MAIL FROM` address, e.g. because its sender
+	/// verification callback to the address's domain failed ("Sender verify
+	/// failed"). This says nothing about the email, which should be checked
+	/// again with another `CheckEmailInput::from_email`, on a domain which
+	/// accepts emails.
+	#[serde(serialize_with = "ser_with_display")]
+	SenderRejected(AsyncSmtpError),
+	/// We couldn't connect to port 25, but the server answers on the
+	/// submission port in this variant, see
+	/// `CheckEmailInput::submission_port_fallback`. Outbound port 25 is most
+	/// likely blocked on our side, so the check is inconclusive.
+	Port25Blocked(u16),
+	/// The domain is an invalid internationalized domain name, which can't
+	/// be converted to punycode.
+	InvalidDomain(String),
+	/// The SMTP verification didn't finish before
+	/// `CheckEmailInput::deadline`, and was cancelled.
+	DeadlineExceeded,
+	/// The SMTP verification was cancelled with
+	/// `CheckEmailInput::cancellation_token`.
+	Cancelled,
+	/// We couldn't authenticate with `CheckEmailInput::smtp_auth`, e.g. the
+	/// server rejected the credentials, or doesn't support the mechanism.
+	/// This says nothing about the email.
+	#[serde(serialize_with = "ser_with_display")]
+	AuthenticationFailed(AsyncSmtpError),
+	/// The server greylisted us, and `CheckEmailInput::defer_greylisting` is
+	/// set, so we didn't retry. The check should be resumed with this token
+	/// once it's time, see `CheckEmailInput::set_greylist_token`.
+	Greylisted(GreylistToken),
+	/// An error read back from JSON, which wrapped an error that can't be
+	/// rebuilt, e.g. an I/O error. It serializes back to the same JSON.
+	#[serde(skip)]
+	Deserialized(DeserializedError),
+}
+
+/// The `sub_kind` of an I/O error, see [`SmtpError::sub_kind`].
+fn io_sub_kind(err: &std::io::Error) -> &'static str {
+	match err.kind() {
+		std::io::ErrorKind::ConnectionRefused => "connection_refused",
+		std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted => {
+			"connection_reset"
+		}
+		std::io::ErrorKind::TimedOut => "timeout",
+		std::io::ErrorKind::UnexpectedEof => "incomplete",
+		_ => "io",
+	}
+}
+
+impl SmtpError {
+	/// Is this error a greylisting reply from the server? This means that
+	/// the email should be checked again later, as the result isn't final.
+	pub fn is_greylisted(&self) -> bool {
+		match self {
+			SmtpError::SmtpError(AsyncSmtpError::Transient(response)) => {
+				is_greylisting_response(response)
+			}
+			SmtpError::Greylisted(_) => true,
+			SmtpError::Deserialized(error) => error.sub_kind() == Some("greylisted"),
+			_ => false,
+		}
+	}
+
+	/// A stable, machine-readable name for the kind of error, e.g. "smtp"
+	/// or "timeout", to branch on without parsing the error message.
+	pub fn kind(&self) -> &str {
+		match self {
+			SmtpError::SocksError(_) => "socks",
+			SmtpError::SmtpError(_) | SmtpError::Greylisted(_) => "smtp",
+			SmtpError::TimeoutError(_) => "timeout",
+			SmtpError::YahooError(_) => "yahoo",
+			SmtpError::GmailError(_) => "gmail",
+			SmtpError::StartTlsUnavailable => "tls",
+			SmtpError::IpBlocked(_) => "ip_blocked",
+			SmtpError::SenderRejected(_) => "sender_rejected",
+			SmtpError::Port25Blocked(_) => "port_25_blocked",
+			SmtpError::InvalidDomain(_) => "invalid_domain",
+			SmtpError::DeadlineExceeded => "deadline_exceeded",
+			SmtpError::Cancelled => "cancelled",
+			SmtpError::AuthenticationFailed(_) => "authentication",
+			SmtpError::Deserialized(error) => error.kind().unwrap_or("unknown"),
+		}
+	}
+
+	/// A finer, stable, machine-readable name for the error within its
+	/// [`kind`](SmtpError::kind), when we can tell, e.g.
+	/// "connection_refused", "tls_handshake" or "recipient_rejected".
+	pub fn sub_kind(&self) -> Option<&str> {
+		let sub_kind = match self {
+			SmtpError::SocksError(err) => match err {
+				SocksError::Io(err) => io_sub_kind(err),
+				SocksError::FutureTimeout(_) => "timeout",
+				SocksError::AuthMethodUnacceptable(_)
+				| SocksError::AuthenticationFailed(_)
+				| SocksError::AuthenticationRejected(_) => "authentication",
+				SocksError::ReplyError(ReplyError::ConnectionRefused) => "connection_refused",
+				SocksError::ReplyError(_) => "proxy_reply",
+				_ => return None,
+			},
+			SmtpError::SmtpError(err)
+			| SmtpError::IpBlocked(err)
+			| SmtpError::SenderRejected(err) => match err {
+				AsyncSmtpError::Transient(response) if is_greylisting_response(response) => {
+					"greylisted"
+				}
+				AsyncSmtpError::Transient(_) => "transient",
+				AsyncSmtpError::Permanent(response) => {
+					match parse_smtp_enhanced_code(response).as_deref() {
+						// Sender address errors, e.g. "5.1.8 Bad sender's system address".
+						Some("5.1.7") | Some("5.1.8") => "permanent",
+						Some(code) if code.starts_with("5.1.") => "recipient_rejected",
+						_ => "permanent",
+					}
+				}
+				AsyncSmtpError::Io(err) => io_sub_kind(err),
+				AsyncSmtpError::Tls(_) => "tls_handshake",
+				AsyncSmtpError::Timeout(_) => "timeout",
+				AsyncSmtpError::Resolution => "resolution",
+				AsyncSmtpError::NoStream => "no_stream",
+				AsyncSmtpError::ResponseParsing(_)
+				| AsyncSmtpError::Parsing(_)
+				| AsyncSmtpError::Utf8Parsing(_) => "response_parsing",
+				_ => return None,
+			},
+			SmtpError::YahooError(err) => match err {
+				YahooError::NoAcrumb | YahooError::NoCookie => "unexpected_response",
+				YahooError::RateLimited => "rate_limited",
+				YahooError::Captcha => "captcha",
+				YahooError::ReqwestError(_) => "http",
+				YahooError::SerdeError(_) => "response_parsing",
+			},
+			SmtpError::GmailError(GmailError::ReqwestError(_)) => "http",
+			SmtpError::AuthenticationFailed(err) => match err {
+				AsyncSmtpError::Permanent(_) => "credentials_rejected",
+				AsyncSmtpError::Transient(_) => "transient",
+				AsyncSmtpError::Client(_) => "mechanism_unsupported",
+				AsyncSmtpError::Io(err) => io_sub_kind(err),
+				AsyncSmtpError::Timeout(_) => "timeout",
+				_ => return None,
+			},
+			SmtpError::StartTlsUnavailable => "starttls_unavailable",
+			SmtpError::Greylisted(_) => "greylisted",
+			SmtpError::Deserialized(error) => return error.sub_kind(),
+			_ => return None,
+		};
+
+		Some(sub_kind)
+	}
+}
+
+impl Serialize for SmtpError {
+	fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		fn ser_variant<S: Serializer>(
+			error: &&SmtpError,
+			serializer: S,
+		) -> std::result::Result<S::Ok, S::Error> {
+			SmtpError::serialize(error, serializer)
+		}
+
+		#[derive(Serialize)]
+		struct WithKind<'a> {
+			kind: &'a str,
+			sub_kind: Option<&'a str>,
+			#[serde(flatten, serialize_with = "ser_variant")]
+			error: &'a SmtpError,
+		}
+
+		if let SmtpError::Deserialized(error) = self {
+			return error.serialize(serializer);
+		}
+
+		WithKind {
+			kind: self.kind(),
+			sub_kind: self.sub_kind(),
+			error: self,
+		}
+		.serialize(serializer)
+	}
+}
+
+impl fmt::Display for SmtpError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:?}", self)
+	}
+}
+
+impl<'de> Deserialize<'de> for SmtpError {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		// Only the variants without foreign errors can be rebuilt.
+		let error = DeserializedError::deserialize(deserializer)?;
+		let rebuilt = match error.error_type() {
+			Some("StartTlsUnavailable") => Some(SmtpError::StartTlsUnavailable),
+			Some("Port25Blocked") => error
+				.message()
+				.and_then(serde_json::Value::as_u64)
+				.and_then(|port| u16::try_from(port).ok())
+				.map(SmtpError::Port25Blocked),
+			Some("InvalidDomain") => error
+				.message()
+				.and_then(serde_json::Value::as_str)
+				.map(|domain| SmtpError::InvalidDomain(domain.into())),
+			Some("DeadlineExceeded") => Some(SmtpError::DeadlineExceeded),
+			Some("Cancelled") => Some(SmtpError::Cancelled),
+			Some("Greylisted") => error
+				.message()
+				.and_then(|token| serde_json::from_value(token.clone()).ok())
+				.map(SmtpError::Greylisted),
+			_ => None,
+		};
+
+		Ok(rebuilt.unwrap_or(SmtpError::Deserialized(error)))
+	}
 }
 
 impl From<SocksError> for SmtpError {
@@ -92,6 +579,12 @@ impl From<YahooError> for SmtpError {
 	}
 }
 
+impl From<GmailError> for SmtpError {
+	fn from(error: GmailError) -> Self {
+		SmtpError::GmailError(error)
+	}
+}
+
 /// Try to send an smtp command, close and return Err if fails.
 macro_rules! try_smtp (
     ($res: expr, $client: ident, $to_email: expr, $host: expr, $port: expr) => ({
@@ -105,444 +598,4235 @@ macro_rules! try_smtp (
     })
 );
 
-/// Attempt to connect to host via SMTP, and return SMTP client on success.
+/// The identifier configured for `EHLO`: `input.hello_ip` as an address
+/// literal if set, otherwise `input.hello_name`.
+fn configured_client_id(input: &CheckEmailInput) -> ClientId {
+	match input.hello_ip {
+		Some(IpAddr::V4(ip)) => ClientId::Ipv4(ip),
+		Some(IpAddr::V6(ip)) => ClientId::Ipv6(ip),
+		None => ClientId::Domain(input.hello_name.clone()),
+	}
+}
+
+/// The identifier to send in `EHLO` when connecting to `host:port`: the PTR
+/// record of the IP address we connect from if `input.hello_name_from_ptr`
+/// is set, otherwise or if it has none, the configured one. `addrs` are the
+/// resolved addresses of the host, if any.
+async fn client_id(
+	host: &str,
+	port: u16,
+	addrs: Option<&[SocketAddr]>,
+	is_proxied: bool,
+	input: &CheckEmailInput,
+) -> ClientId {
+	if !input.hello_name_from_ptr {
+		return configured_client_id(input);
+	}
+
+	let source_ip = match (input.egress_ip, addrs) {
+		(Some(egress_ip), _) => Some(egress_ip),
+		// We don't know the exit IP of the proxy.
+		(None, _) if is_proxied => None,
+		(None, Some(addrs)) => addrs.first().and_then(|addr| net::route_source_ip(*addr)),
+		(None, None) => net::resolve(host, port, IpPreference::System)
+			.await
+			.ok()
+			.and_then(|addrs| addrs.first().and_then(|addr| net::route_source_ip(*addr))),
+	};
+	let ptr_name = match source_ip {
+		Some(source_ip) => future::timeout(input.connect_timeout, dns::ptr_name(source_ip, input))
+			.await
+			.ok()
+			.flatten(),
+		None => None,
+	};
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} PTR record of {:?}: {:?}",
+		input.to_emails[0],
+		source_ip,
+		ptr_name
+	);
+
+	ptr_name.map_or_else(|| configured_client_id(input), ClientId::Domain)
+}
+
+/// Attempt to connect to host via SMTP, and return the connection, on which
+/// a mail transaction has been started, on success.
 async fn connect_to_host(
 	host: &Name,
 	port: u16,
 	input: &CheckEmailInput,
-) -> Result<SmtpTransport, SmtpError> {
+	transcript: &mut Transcript,
+) -> Result<Connection, SmtpError> {
 	// hostname verification fails if it ends with '.', for example, using
 	// SOCKS5 proxies we can `io: incomplete` error.
 	let host = host.to_string();
 	let host = host.trim_end_matches('.').to_string();
 
-	let security = {
-		let tls_params = ClientTlsParameters::new(host.clone(), TlsConnector::new().use_sni(true));
+	let security = input
+		.smtp_security
+		.to_client_security(tls_parameters(&host, input).map_err(SmtpError::SmtpError)?);
 
-		input.smtp_security.to_client_security(tls_params)
+	// Resolve the host ourselves if we need to filter its addresses. Through
+	// a proxy, the proxy connects to the host, so there's nothing to filter.
+	let proxies = proxies_to_try(input);
+	let addrs = match (input.smtp_ip_override, input.ip_preference) {
+		_ if !proxies.is_empty() => None,
+		(Some(ip), _) => Some(vec![SocketAddr::new(ip, port)]),
+		(None, IpPreference::System) => None,
+		(None, preference) => Some(
+			future::timeout(input.connect_timeout, net::resolve(&host, port, preference))
+				.await?
+				.map_err(|err| SmtpError::SmtpError(err.into()))?,
+		),
 	};
 
-	let mut smtp_transport = SmtpClient::with_security((host.as_ref(), port), security)
-		.await
+	let client_id = client_id(&host, port, addrs.as_deref(), !proxies.is_empty(), input).await;
+	let smtp_client = match &addrs {
+		Some(addrs) => {
+			future::timeout(
+				input.connect_timeout,
+				SmtpClient::with_security(&addrs[..], security),
+			)
+			.await?
+		}
+		None => {
+			future::timeout(
+				input.connect_timeout,
+				SmtpClient::with_security((host.as_ref(), port), security),
+			)
+			.await?
+		}
+	};
+	let mut smtp_transport = smtp_client
 		.map_err(SmtpError::SmtpError)?
-		// FIXME Do not clone?
-		.hello_name(ClientId::Domain(input.hello_name.clone()))
-		.timeout(Some(Duration::new(30, 0))) // Set timeout to 30s
+		.hello_name(client_id.clone())
+		// The connect timeout also bounds each command, so make sure it
+		// doesn't cut commands shorter than `input.command_timeout`.
+		.timeout(Some(
+			input
+				.command_timeout
+				.map_or(input.connect_timeout, |t| t.max(input.connect_timeout)),
+		))
 		.into_transport();
 
-	if let Some(proxy) = &input.proxy {
-		let stream = match (&proxy.username, &proxy.password) {
-			(Some(username), Some(password)) => {
-				Socks5Stream::connect_with_password(
-					(proxy.host.as_ref(), proxy.port),
-					host.clone(),
-					port,
-					username.to_string(),
-					password.to_string(),
-					Config::default(),
+	let mut used_proxy = None;
+	let result = if proxies.is_empty() {
+		match (&addrs, input.ip_preference) {
+			(Some(addrs), IpPreference::HappyEyeballs) => {
+				future::timeout(
+					input.connect_timeout,
+					connect_happy_eyeballs(&mut smtp_transport, addrs, &host, input),
 				)
 				.await?
 			}
 			_ => {
-				Socks5Stream::connect(
-					(proxy.host.as_ref(), proxy.port),
-					host.clone(),
-					port,
-					Config::default(),
+				future::timeout(
+					input.connect_timeout,
+					connect_direct(&mut smtp_transport, addrs.as_deref(), &host, port, input),
 				)
 				.await?
 			}
-		};
-
-		try_smtp!(
-			smtp_transport
-				.connect_with_stream(NetworkStream::Socks5Stream(stream))
-				.await,
-			smtp_transport,
-			input.to_emails[0],
-			host,
-			port
-		);
+		}
 	} else {
-		try_smtp!(
-			smtp_transport.connect().await,
-			smtp_transport,
-			input.to_emails[0],
-			host,
-			port
-		);
+		// If a proxy fails, we try the next one.
+		let mut outcome = Err(SmtpError::SmtpError(AsyncSmtpError::NoStream));
+		for proxy in proxies {
+			outcome = connect_via_proxy(&mut smtp_transport, proxy, &host, port, input).await;
+			if !is_proxy_failure(&outcome) {
+				used_proxy = Some(format!("{}:{}", proxy.host, proxy.port));
+				break;
+			}
+			transcript.note(format!("! Proxy {}:{} failed", proxy.host, proxy.port));
+			let _ = smtp_transport.close().await;
+		}
+		outcome?
+	};
+	let socket_info = result.as_ref().ok().cloned().unwrap_or_default();
+	let result = result.map(|_| ());
+	record_connection(transcript, &result, &host, port);
+
+	// async-smtp fails with a generic client error when STARTTLS is required
+	// but not advertised by the server.
+	if matches!(input.smtp_security, SmtpSecurity::Required)
+		&& matches!(
+			result,
+			Err(AsyncSmtpError::Client(
+				"Could not encrypt connection, aborting"
+			))
+		) {
+		let _ = smtp_transport.close().await;
+		return Err(SmtpError::StartTlsUnavailable);
 	}
+	try_smtp!(result, smtp_transport, input.to_emails[0], host, port);
 
-	// "MAIL FROM: user@example.org"
-	let from_email = EmailAddress::from_str(input.from_email.as_ref()).unwrap_or_else(|_| {
-		log::warn!(
-			"Inputted from_email \"{}\" is not a valid email, using \"user@example.org\" instead",
-			input.from_email
-		);
-		EmailAddress::from_str("user@example.org").expect("This is a valid email. qed.")
-	});
-	try_smtp!(
-		smtp_transport
-			// FIXME Do not clone?
-			.command(MailCommand::new(Some(from_email.clone()), vec![],))
-			.await,
-		smtp_transport,
-		input.to_emails[0],
-		host,
-		port
+	let (ehlo_greeting, extensions) = if needs_extensions(input) {
+		ehlo_reply(&mut smtp_transport, client_id, input, transcript).await
+	} else {
+		(None, vec![])
+	};
+	let mta_software = mta::detect_mta(
+		socket_info
+			.greeting
+			.iter()
+			.flatten()
+			.chain(ehlo_greeting.iter())
+			.map(String::as_str),
 	);
+	authenticate(
+		&mut smtp_transport,
+		&host,
+		port,
+		&extensions,
+		input,
+		transcript,
+	)
+	.await?;
+	start_transaction(
+		&mut smtp_transport,
+		&host,
+		port,
+		&extensions,
+		input,
+		transcript,
+	)
+	.await?;
 
-	Ok(smtp_transport)
+	Ok(Connection {
+		transport: smtp_transport,
+		created_at: Instant::now(),
+		extensions,
+		used_proxy,
+		source_ip: socket_info.source_ip,
+		mx_ip: socket_info.mx_ip,
+		mta_software,
+		plaintext_stream: socket_info.plaintext_stream,
+	})
 }
 
-/// Description of the deliverability information we can gather from
-/// communicating with the SMTP server.
-struct Deliverability {
-	/// Is this email account's inbox full?
-	has_full_inbox: bool,
-	/// Can we send an email to this address?
-	is_deliverable: bool,
-	/// Is the email blocked or disabled by the provider?
-	is_disabled: bool,
+/// Do we need the extensions advertised by the server, see
+/// `CheckEmailInput::report_smtp_extensions`?
+pub(super) fn needs_extensions(input: &CheckEmailInput) -> bool {
+	input.report_smtp_extensions
+		// The AUTH mechanisms, see `authenticate`.
+		|| input.smtp_auth.is_some()
+		// SMTPUTF8, see `start_transaction`.
+		|| input.to_emails.iter().any(|email| !email.is_ascii())
+		// PIPELINING, see `can_pipeline`.
+		|| (input.to_emails.len() > 1 && matches!(input.smtp_security, SmtpSecurity::None))
 }
 
-/// Check if `to_email` exists on host SMTP server. This is the core logic of
-/// this tool.
-async fn email_deliverable(
+/// Send `EHLO` again: async-smtp sends it while connecting, but doesn't
+/// expose the reply. Only sent if [`needs_extensions`]. Returns the first
+/// line of the reply, i.e. the server's name and greeting, and the
+/// extensions advertised by the server, each with its parameters, e.g.
+/// "SIZE 35882577".
+///
+/// If the server doesn't reply to `EHLO`, no extension is returned.
+async fn ehlo_reply(
 	smtp_transport: &mut SmtpTransport,
-	to_email: &EmailAddress,
-) -> Result<Deliverability, SmtpError> {
-	// "RCPT TO: me@email.com"
-	// FIXME Do not clone?
-	match smtp_transport
-		.command(RcptCommand::new(to_email.clone(), vec![]))
-		.await
-	{
-		Ok(_) => {
-			// According to RFC 5321, `RCPT TO` command succeeds with 250 and
-			// 251 codes only (no 3xx codes at all):
-			// https://tools.ietf.org/html/rfc5321#page-56
-			//
-			// Where the 251 code is used for forwarding, which is not our case,
-			// because we always deliver to the SMTP server hosting the address
-			// itself.
-			//
-			// So, if `response.is_positive()` (which is a condition for
-			// returning `Ok` from the `command()` method above), then delivery
-			// succeeds, accordingly to RFC 5321.
-			Ok(Deliverability {
-				has_full_inbox: false,
-				is_deliverable: true, // response.is_positive()
-				is_disabled: false,
-			})
-		}
-		Err(err) => {
-			// We cast to lowercase, because our matched strings below are all
-			// lowercase.
-			let err_string = err.to_string().to_lowercase();
-
-			// Check if the email account has been disabled or blocked.
-			// 554 The email account that you tried to reach is disabled. Learn more at https://support.google.com/mail/?p=DisabledUser"
-			if err_string.contains("disabled")
-				// 554 delivery error: Sorry your message to [email] cannot be delivered. This account has been disabled or discontinued
-				|| err_string.contains("discontinued")
-			{
-				return Ok(Deliverability {
-					has_full_inbox: false,
-					is_deliverable: false,
-					is_disabled: true,
-				});
-			}
-
-			// Check if the email account has a full inbox.
-			if err_string.contains("insufficient")
-				|| err_string.contains("over quota")
-				// 550 user has too many messages on the server
-				|| err_string.contains("too many messages")
-			{
-				return Ok(Deliverability {
-					has_full_inbox: true,
-					is_deliverable: false,
-					is_disabled: false,
-				});
-			}
-
-			// Check error messages that say that user can actually receive
-			// emails.
-			// 4.2.1 The user you are trying to contact is receiving mail at a rate that
-			if err_string
-				.contains("the user you are trying to contact is receiving mail at a rate that")
-			{
-				return Ok(Deliverability {
-					has_full_inbox: false,
-					is_deliverable: true,
-					is_disabled: false,
-				});
-			}
-
-			// These are the possible error messages when email account doesn't exist.
-			// 550 Address rejected
-			// 550 5.1.1 : Recipient address rejected
-			// 550 5.1.1 : Recipient address rejected: User unknown in virtual alias table
-			// 550 5.1.1 <user@domain.com>: Recipient address rejected: User unknown in relay recipient table
-			if err_string.contains("address rejected")
-				// 550 5.1.1 : Unrouteable address
-				|| err_string.contains("unrouteable")
-				// 550 5.1.1 : The email account that you tried to reach does not exist
-				|| err_string.contains("does not exist")
-				// 550 invalid address
-				// 550 User not local or invalid address – Relay denied
-				|| err_string.contains("invalid address")
-				// 5.1.1 Invalid email address
-				|| err_string.contains("invalid email address")
-				// 550 Invalid recipient
-				|| err_string.contains("invalid recipient")
-				|| err_string.contains("may not exist")
-				|| err_string.contains("recipient invalid")
-				// 550 5.1.1 : Recipient rejected
-				|| err_string.contains("recipient rejected")
-				|| err_string.contains("undeliverable")
-				// 550 User unknown
-				// 550 5.1.1 <EMAIL> User unknown
-				// 550 recipient address rejected: user unknown in local recipient table
-				|| err_string.contains("user unknown")
-				// 550 Unknown user
-				|| err_string.contains("unknown user")
-				// 5.1.1 Recipient unknown <EMAIL>
-				|| err_string.contains("recipient unknown")
-				// 550 5.1.1 No such user - pp
-				// 550 No such user here
-				|| err_string.contains("no such user")
-				// 550 5.1.1 : Mailbox not found
-				// 550 Unknown address error ‘MAILBOX NOT FOUND’
-				|| err_string.contains("not found")
-				// 550 5.1.1 : Invalid mailbox
-				|| err_string.contains("invalid mailbox")
-				// 550 5.1.1 Sorry, no mailbox here by that name
-				|| err_string.contains("no mailbox")
-				// 5.2.0 No such mailbox
-				|| err_string.contains("no such mailbox")
-				// 550 Requested action not taken: mailbox unavailable
-				|| err_string.contains("mailbox unavailable")
-				// 550 5.1.1 Is not a valid mailbox
-				|| err_string.contains("not a valid mailbox")
-				// No such recipient here
-				|| err_string.contains("no such recipient")
-				// 554 delivery error: This user doesn’t have an account
-				|| err_string.contains("have an account")
-				// 5.1.1 RCP-P1 Domain facebook.com no longer available https://www.facebook.com/postmaster/response_codes?ip=3.80.111.155#RCP-P1
-				|| err_string.contains("no longer available")
-			{
-				return Ok(Deliverability {
-					has_full_inbox: false,
-					is_deliverable: false,
-					is_disabled: false,
-				});
-			}
-
-			Err(SmtpError::SmtpError(err))
+	client_id: ClientId,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> (Option<String>, Vec<String>) {
+	let ehlo_command = EhloCommand::new(client_id);
+	match send_command(smtp_transport, ehlo_command, input, transcript).await {
+		Ok(response) => {
+			let mut lines = response.message.iter().map(|line| line.trim().to_string());
+			let greeting = lines.next();
+			(greeting, lines.filter(|line| !line.is_empty()).collect())
 		}
+		Err(_) => (None, vec![]),
 	}
 }
 
-/// Verify the existence of a catch-all on the domain.
-async fn smtp_is_catch_all(
+/// Authenticate with `input.smtp_auth`, if set, using the mechanism it
+/// specifies, which the server must advertise in `extensions`. The
+/// credentials are redacted from the transcript.
+async fn authenticate(
 	smtp_transport: &mut SmtpTransport,
-	domain: &str,
-) -> Result<bool, SmtpError> {
-	// Create a random 15-char alphanumerical string.
-	let mut rng = SmallRng::from_entropy();
-	let random_email: String = iter::repeat(())
-		.map(|()| rng.sample(Alphanumeric))
-		.map(char::from)
-		.take(15)
-		.collect();
-	let random_email = EmailAddress::new(format!("{}@{}", random_email, domain));
-
-	email_deliverable(
-		smtp_transport,
-		&random_email.expect("Email is correctly constructed. qed."),
-	)
-	.await
-	.map(|deliverability| deliverability.is_deliverable)
-}
-
-async fn create_smtp_future(
-	to_email: &EmailAddress,
-	host: &Name,
+	host: &str,
 	port: u16,
-	domain: &str,
+	extensions: &[String],
 	input: &CheckEmailInput,
-) -> Result<(bool, Deliverability), SmtpError> {
-	// FIXME If the SMTP is not connectable, we should actually return an
-	// Ok(SmtpDetails { can_connect_smtp: false, ... }).
-	let mut smtp_transport = connect_to_host(host, port, input).await?;
+	transcript: &mut Transcript,
+) -> Result<(), SmtpError> {
+	let smtp_auth = match &input.smtp_auth {
+		Some(smtp_auth) => smtp_auth,
+		None => return Ok(()),
+	};
+	let mechanism = smtp_auth.mechanism.to_mechanism();
+	let credentials = Credentials::new(smtp_auth.username.clone(), smtp_auth.password.clone());
 
-	let is_catch_all = smtp_is_catch_all(&mut smtp_transport, domain)
-		.await
-		.unwrap_or(false);
-	let deliverability = if is_catch_all {
-		Deliverability {
-			has_full_inbox: false,
-			is_deliverable: true,
-			is_disabled: false,
+	let result = if supports_auth_mechanism(extensions, mechanism) {
+		let mut command = AuthCommand::new(mechanism, credentials.clone(), None);
+		let mut label = format!("AUTH {} <redacted>", mechanism);
+		// LOGIN sends the username and the password in reply to one
+		// challenge each.
+		let mut challenges = 2;
+		loop {
+			let response = match command {
+				Ok(command) => {
+					send_command_as(smtp_transport, command, &label, input, transcript).await
+				}
+				Err(err) => Err(err),
+			};
+			match response {
+				Ok(response) if response.has_code(334) && challenges > 0 => {
+					challenges -= 1;
+					command =
+						AuthCommand::new_from_response(mechanism, credentials.clone(), &response);
+					label = "<redacted>".into();
+				}
+				Ok(response) if response.has_code(334) => {
+					break Err(AsyncSmtpError::ResponseParsing(
+						"Unexpected number of challenges",
+					))
+				}
+				Ok(_) => break Ok(()),
+				Err(err) => break Err(err),
+			}
 		}
 	} else {
-		let mut result = email_deliverable(&mut smtp_transport, to_email).await;
-
-		// Some SMTP servers automatically close the connection after an error,
-		// so we should reconnect to perform a next command.
-		//
-		// Unfortunately `smtp_transport.is_connected()` doesn't report about this,
-		// so we can only check for "io: incomplete" SMTP error being returned.
-		// https://github.com/async-email/async-smtp/issues/37
-		if is_io_incomplete_smtp_error(&result) {
-			log::debug!(
-				target: LOG_TARGET,
-				"Got `io: incomplete` error, reconnecting."
-			);
-
-			let _ = smtp_transport.close().await;
-			smtp_transport = connect_to_host(host, port, input).await?;
-			result = email_deliverable(&mut smtp_transport, to_email).await;
-		}
-
-		result?
+		Err(AsyncSmtpError::Client(
+			"The server doesn't support the authentication mechanism",
+		))
 	};
 
-	smtp_transport.close().await.map_err(SmtpError::SmtpError)?;
+	if let Err(err) = result {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} Closing {}:{}, because authentication failed with '{:?}'.",
+			input.to_emails[0],
+			host,
+			port,
+			err
+		);
+		let _ = smtp_transport.close().await;
+		return Err(SmtpError::AuthenticationFailed(err));
+	}
 
-	Ok((is_catch_all, deliverability))
+	Ok(())
 }
 
-/// Indicates whether the given [`Result`] represents an `io: incomplete`
-/// [`SmtpError::Error`].
-fn is_io_incomplete_smtp_error<T>(result: &Result<T, SmtpError>) -> bool {
-	match result {
-		Err(SmtpError::SmtpError(AsyncSmtpError::Io(err))) => {
-			err.to_string().as_str() == "incomplete"
-		}
-		_ => false,
-	}
+/// Does the server advertise `mechanism` in its `AUTH` extension, e.g.
+/// "AUTH PLAIN LOGIN"?
+fn supports_auth_mechanism(extensions: &[String], mechanism: Mechanism) -> bool {
+	let mechanism = mechanism.to_string();
+	extensions.iter().any(|extension| {
+		let mut words = extension.split_whitespace();
+		words
+			.next()
+			.map_or(false, |word| word.eq_ignore_ascii_case("AUTH"))
+			&& words.any(|word| word.eq_ignore_ascii_case(&mechanism))
+	})
 }
 
-/// Get all email details we can from one single `EmailAddress`, without
-/// retries.
-async fn check_smtp_without_retry(
-	to_email: &EmailAddress,
-	host: &Name,
-	port: u16,
-	domain: &str,
+/// Build the TLS parameters to connect to `host`, using the TLS options from
+/// `input`.
+///
+/// Note: we can't report the negotiated TLS version or cipher suite. The
+/// `native-tls` API doesn't expose them, on any of its backends, and
+/// async-smtp keeps the TLS stream private after the `STARTTLS` upgrade.
+fn tls_parameters(
+	host: &str,
 	input: &CheckEmailInput,
-) -> Result<SmtpDetails, SmtpError> {
-	// FIXME Is this `contains` too lenient?
-	if input.yahoo_use_api && domain.to_lowercase().contains("yahoo") {
-		return yahoo::check_yahoo(to_email, input)
-			.await
-			.map_err(|err| err.into());
+) -> Result<ClientTlsParameters, AsyncSmtpError> {
+	let mut connector = TlsConnector::new()
+		.use_sni(true)
+		.danger_accept_invalid_certs(input.danger_accept_invalid_certs);
+	for pem in &input.tls_root_certificates {
+		connector = connector.add_root_certificate(Certificate::from_pem(pem)?);
 	}
+	let domain = input
+		.tls_sni_override
+		.clone()
+		.unwrap_or_else(|| host.to_string());
 
-	let fut = create_smtp_future(to_email, host, port, domain, input);
-	let (is_catch_all, deliverability) = if let Some(smtp_timeout) = input.smtp_timeout {
-		future::timeout(smtp_timeout, fut).await??
-	} else {
-		fut.await?
-	};
+	Ok(ClientTlsParameters::new(domain, connector))
+}
 
-	Ok(SmtpDetails {
-		can_connect_smtp: true,
-		has_full_inbox: deliverability.has_full_inbox,
-		is_catch_all,
-		is_deliverable: deliverability.is_deliverable,
-		is_disabled: deliverability.is_disabled,
-	})
+/// Counter to pick proxies in round-robin, shared by all checks.
+static NEXT_PROXY: AtomicUsize = AtomicUsize::new(0);
+
+/// The proxies to try for a connection, in order: the first one is picked
+/// according to `input.proxy_rotation`, and the other ones follow it.
+pub(super) fn proxies_to_try(input: &CheckEmailInput) -> Vec<&CheckEmailInputProxy> {
+	let mut proxies = input
+		.proxy
+		.iter()
+		.chain(input.proxies.iter())
+		.collect::<Vec<_>>();
+	if proxies.len() > 1 {
+		let first = match input.proxy_rotation {
+			ProxyRotation::RoundRobin => NEXT_PROXY.fetch_add(1, Ordering::Relaxed),
+			ProxyRotation::Random => SmallRng::from_entropy().gen(),
+		};
+		let len = proxies.len();
+		proxies.rotate_left(first % len);
+	}
+
+	proxies
 }
 
-/// Get all email details we can from one single `EmailAddress`.
-/// Retry the SMTP connection, in particular to avoid greylisting.
-#[async_recursion]
-async fn retry(
-	to_email: &EmailAddress,
-	host: &Name,
+/// The host the proxy should connect to: `input.smtp_ip_override` if set,
+/// otherwise `host`.
+fn proxy_target(host: &str, input: &CheckEmailInput) -> String {
+	input
+		.smtp_ip_override
+		.map_or_else(|| host.to_string(), |ip| ip.to_string())
+}
+
+/// Connect to `host:port` through `proxy`. The outer error is a failure to
+/// reach the proxy, the inner one a failure to talk to the SMTP server.
+async fn connect_via_proxy(
+	smtp_transport: &mut SmtpTransport,
+	proxy: &CheckEmailInputProxy,
+	host: &str,
 	port: u16,
-	domain: &str,
 	input: &CheckEmailInput,
-	count: usize,
-) -> Result<SmtpDetails, SmtpError> {
-	log::debug!(
-		target: LOG_TARGET,
-		"email={} Check SMTP attempt #{} on {}:{}",
-		input.to_emails[0],
-		input.retries - count + 1,
-		host,
-		port
-	);
+) -> Result<Result<SocketInfo, AsyncSmtpError>, SmtpError> {
+	if proxy.proxy_type == ProxyType::HttpConnect {
+		return Ok(future::timeout(
+			input.connect_timeout,
+			connect_http_proxy(smtp_transport, proxy, host, port, input),
+		)
+		.await?);
+	}
 
-	let result = check_smtp_without_retry(to_email, host, port, domain, input).await;
+	// A proxy might accept the connection and never answer the handshake.
+	let handshake = async {
+		match (&proxy.username, &proxy.password) {
+			(Some(username), Some(password)) => {
+				Socks5Stream::connect_with_password(
+					(proxy.host.as_ref(), proxy.port),
+					proxy_target(host, input),
+					port,
+					username.to_string(),
+					password.to_string(),
+					Config::default(),
+				)
+				.await
+			}
+			_ => {
+				Socks5Stream::connect(
+					(proxy.host.as_ref(), proxy.port),
+					proxy_target(host, input),
+					port,
+					Config::default(),
+				)
+				.await
+			}
+		}
+	};
+	let stream = future::timeout(input.connect_timeout, handshake).await??;
 
-	log::debug!(
+	// This is the address we reach the proxy from, the SMTP server sees the
+	// proxy's address instead.
+	let source_ip = stream
+		.get_socket_ref()
+		.local_addr()
+		.ok()
+		.map(|addr| addr.ip());
+	let greeting = future::timeout(
+		input.connect_timeout,
+		net::peek_greeting(stream.get_socket_ref()),
+	)
+	.await
+	.ok()
+	.flatten();
+	// Past the handshake, the SOCKS5 stream is the socket itself.
+	let plaintext_stream = plaintext_stream(stream.get_socket_ref(), input);
+	Ok(future::timeout(
+		input.connect_timeout,
+		smtp_transport.connect_with_stream(NetworkStream::Socks5Stream(stream)),
+	)
+	.await?
+	.map(|_| SocketInfo {
+		source_ip,
+		mx_ip: None,
+		greeting,
+		plaintext_stream,
+	}))
+}
+
+/// Did the connection through a proxy fail because of the proxy, i.e. we
+/// didn't get any reply from the SMTP server?
+fn is_proxy_failure(outcome: &Result<Result<SocketInfo, AsyncSmtpError>, SmtpError>) -> bool {
+	matches!(
+		outcome,
+		Err(SmtpError::SocksError(_))
+			| Err(SmtpError::TimeoutError(_))
+			| Ok(Err(AsyncSmtpError::Io(_)))
+			| Ok(Err(AsyncSmtpError::NoStream))
+	)
+}
+
+/// Wrap a TCP stream connected to `host` into a `NetworkStream`, with TLS
+/// if `input.smtp_security` is `Wrapper`.
+async fn network_stream(
+	tcp_stream: TcpStream,
+	host: &str,
+	input: &CheckEmailInput,
+) -> Result<NetworkStream, AsyncSmtpError> {
+	match input.smtp_security {
+		SmtpSecurity::Wrapper => {
+			let tls_params = tls_parameters(host, input)?;
+			Ok(NetworkStream::Tls(
+				tls_params
+					.connector
+					.connect(&tls_params.domain, tcp_stream)
+					.await?,
+			))
+		}
+		_ => Ok(NetworkStream::Tcp(tcp_stream)),
+	}
+}
+
+/// Connect to the first of `addrs` which answers, racing IPv6 and IPv4
+/// addresses.
+async fn connect_happy_eyeballs(
+	smtp_transport: &mut SmtpTransport,
+	addrs: &[SocketAddr],
+	host: &str,
+	input: &CheckEmailInput,
+) -> Result<SocketInfo, AsyncSmtpError> {
+	let tcp_stream = net::happy_eyeballs(addrs).await?;
+
+	connect_tcp_stream(smtp_transport, tcp_stream, host, input).await
+}
+
+/// Connect to the first of `addrs` which answers, or to `host:port` if the
+/// addresses weren't resolved beforehand.
+async fn connect_direct(
+	smtp_transport: &mut SmtpTransport,
+	addrs: Option<&[SocketAddr]>,
+	host: &str,
+	port: u16,
+	input: &CheckEmailInput,
+) -> Result<SocketInfo, AsyncSmtpError> {
+	let tcp_stream = match addrs {
+		Some(addrs) => TcpStream::connect(addrs).await?,
+		None => TcpStream::connect((host, port)).await?,
+	};
+
+	connect_tcp_stream(smtp_transport, tcp_stream, host, input).await
+}
+
+/// Connect to `host:port` through an HTTP proxy. The proxy resolves the
+/// host, so its IP address is unknown.
+async fn connect_http_proxy(
+	smtp_transport: &mut SmtpTransport,
+	proxy: &CheckEmailInputProxy,
+	host: &str,
+	port: u16,
+	input: &CheckEmailInput,
+) -> Result<SocketInfo, AsyncSmtpError> {
+	let tcp_stream = net::http_connect(proxy, &proxy_target(host, input), port).await?;
+	let socket_info = connect_tcp_stream(smtp_transport, tcp_stream, host, input).await?;
+
+	Ok(SocketInfo {
+		mx_ip: None,
+		..socket_info
+	})
+}
+
+/// Start the SMTP conversation on `tcp_stream`, connected to `host`. Returns
+/// what we know about the socket.
+async fn connect_tcp_stream(
+	smtp_transport: &mut SmtpTransport,
+	tcp_stream: TcpStream,
+	host: &str,
+	input: &CheckEmailInput,
+) -> Result<SocketInfo, AsyncSmtpError> {
+	// With implicit TLS, the greeting is encrypted.
+	let greeting = match input.smtp_security {
+		SmtpSecurity::Wrapper => None,
+		_ => net::peek_greeting(&tcp_stream).await,
+	};
+	let socket_info = SocketInfo {
+		source_ip: tcp_stream.local_addr().ok().map(|addr| addr.ip()),
+		mx_ip: tcp_stream.peer_addr().ok().map(|addr| addr.ip()),
+		greeting,
+		plaintext_stream: plaintext_stream(&tcp_stream, input),
+	};
+	let stream = network_stream(tcp_stream, host, input).await?;
+	smtp_transport.connect_with_stream(stream).await?;
+
+	Ok(socket_info)
+}
+
+/// A handle on `tcp_stream` if the conversation stays in plaintext, see
+/// `Connection::plaintext_stream`.
+fn plaintext_stream(tcp_stream: &TcpStream, input: &CheckEmailInput) -> Option<TcpStream> {
+	match input.smtp_security {
+		SmtpSecurity::None => Some(tcp_stream.clone()),
+		_ => None,
+	}
+}
+
+/// Does the server advertise the extension `keyword` in `extensions`?
+fn has_extension(extensions: &[String], keyword: &str) -> bool {
+	extensions.iter().any(|extension| {
+		extension
+			.split_whitespace()
+			.next()
+			.map_or(false, |word| word.eq_ignore_ascii_case(keyword))
+	})
+}
+
+/// The address to send in `RCPT TO` for `to_email`. Addresses which aren't
+/// ASCII need the server to support SMTPUTF8 (RFC 6531). Otherwise, we can
+/// still send the domain in punycode, but not the local part, in which case
+/// None is returned.
+fn rcpt_address(to_email: &EmailAddress, smtputf8: bool) -> Option<EmailAddress> {
+	let address = to_email.to_string();
+	if address.is_ascii() || smtputf8 {
+		return Some(to_email.clone());
+	}
+
+	let (local_part, domain) = address.rsplit_once('@')?;
+	if !local_part.is_ascii() {
+		return None;
+	}
+	let domain = idna::domain_to_ascii(domain).ok()?;
+	EmailAddress::new(format!("{}@{}", local_part, domain)).ok()
+}
+
+/// Parse the message size limit from the `SIZE` extension, if any. `SIZE`
+/// without a value, or `SIZE 0`, means that there's no limit.
+fn parse_max_message_size(extensions: &[String]) -> Option<u64> {
+	let mut words = extensions
+		.iter()
+		.map(|extension| extension.split_whitespace())
+		.find_map(|mut words| match words.next() {
+			Some(keyword) if keyword.eq_ignore_ascii_case("SIZE") => Some(words),
+			_ => None,
+		})?;
+
+	words
+		.next()
+		.and_then(|size| size.parse().ok())
+		.filter(|size| *size > 0)
+}
+
+/// Send a command to the server, and record it in the transcript. Fails
+/// with a timeout error if the server doesn't reply within
+/// `input.command_timeout`.
+async fn send_command<C: Display>(
+	smtp_transport: &mut SmtpTransport,
+	command: C,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<Response, AsyncSmtpError> {
+	let label = command.to_string();
+	send_command_as(smtp_transport, command, &label, input, transcript).await
+}
+
+/// Like [`send_command`], but record `label` in the transcript instead of
+/// the command, e.g. to hide credentials.
+async fn send_command_as<C: Display>(
+	smtp_transport: &mut SmtpTransport,
+	command: C,
+	label: &str,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<Response, AsyncSmtpError> {
+	transcript.command(&label);
+	let result = match input.command_timeout {
+		Some(command_timeout) => future::timeout(command_timeout, smtp_transport.command(command))
+			.await
+			.unwrap_or_else(|err| Err(AsyncSmtpError::Timeout(err))),
+		None => smtp_transport.command(command).await,
+	};
+	transcript.result(&result);
+
+	result
+}
+
+/// Start a new mail transaction on the connection, by sending the
+/// `MAIL FROM` command.
+async fn start_transaction(
+	smtp_transport: &mut SmtpTransport,
+	host: &str,
+	port: u16,
+	extensions: &[String],
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<(), SmtpError> {
+	// Internationalized recipients need the SMTPUTF8 parameter (RFC 6531).
+	let parameters = if has_extension(extensions, "SMTPUTF8")
+		&& input.to_emails.iter().any(|email| !email.is_ascii())
+	{
+		vec![MailParameter::SmtpUtfEight]
+	} else {
+		vec![]
+	};
+	// "MAIL FROM: user@example.org", or "MAIL FROM:<>" for the null sender
+	let mail_command = MailCommand::new(mail_from(input), parameters);
+	let result = send_command(smtp_transport, mail_command, input, transcript).await;
+	try_smtp!(result, smtp_transport, input.to_emails[0], host, port);
+
+	Ok(())
+}
+
+/// The address to use in the `MAIL FROM` command, None for the null sender.
+/// The input is expected to come from [`with_valid_from_email`], this only
+/// guards against misuse.
+fn mail_from(input: &CheckEmailInput) -> Option<EmailAddress> {
+	if input.use_null_sender {
+		return None;
+	}
+
+	Some(
+		EmailAddress::from_str(input.from_email.as_ref()).unwrap_or_else(|_| {
+			EmailAddress::from_str(DEFAULT_FROM_EMAIL).expect("This is a valid email. qed.")
+		}),
+	)
+}
+
+/// Make sure that `input.from_email` is a valid email address, so that the
+/// server doesn't reject our `MAIL FROM` command. If it's not, we use
+/// `input.from_email_fallback` instead, or "postmaster@<domain>" by default.
+fn with_valid_from_email<'a>(input: &'a CheckEmailInput, domain: &str) -> Cow<'a, CheckEmailInput> {
+	if input.use_null_sender || EmailAddress::from_str(input.from_email.as_ref()).is_ok() {
+		return Cow::Borrowed(input);
+	}
+
+	let fallback = input
+		.from_email_fallback
+		.clone()
+		.unwrap_or_else(|| format!("postmaster@{}", domain));
+	let from_email = if EmailAddress::from_str(fallback.as_ref()).is_ok() {
+		fallback
+	} else {
+		DEFAULT_FROM_EMAIL.into()
+	};
+	log::warn!(
 		target: LOG_TARGET,
-		"email={} Got result for attempt #{} on {}:{}, result={:?}",
-		input.to_emails[0],
-		input.retries - count + 1,
-		host,
-		port,
-		result
+		"Inputted from_email \"{}\" is not a valid email, using \"{}\" instead",
+		input.from_email,
+		from_email
 	);
 
-	match result {
-		// Only retry if the error was a temporary/transient error, or a
-		// timeout error.
-		Err(SmtpError::SmtpError(AsyncSmtpError::Transient(_)))
-		| Err(SmtpError::SmtpError(AsyncSmtpError::Timeout(_))) => {
-			if count <= 1 {
-				result
-			} else {
-				log::debug!(
-					target: LOG_TARGET,
-					"email={} Potential greylisting detected, retrying.",
-					input.to_emails[0],
-				);
-				retry(to_email, host, port, domain, input, count - 1).await
+	Cow::Owned(CheckEmailInput {
+		from_email,
+		..input.clone()
+	})
+}
+
+/// A SMTP connection on which a mail transaction has been started.
+struct Connection {
+	transport: SmtpTransport,
+	/// When the connection was established, which might be before this
+	/// check if it comes from the pool.
+	created_at: Instant,
+	/// The extensions advertised by the server in its `EHLO` reply.
+	extensions: Vec<String>,
+	/// The proxy the connection goes through, as "host:port", if any.
+	used_proxy: Option<String>,
+	/// The local IP address of the connection's socket, if known.
+	source_ip: Option<IpAddr>,
+	/// The IP address of the SMTP server, if known.
+	mx_ip: Option<IpAddr>,
+	/// The MTA software of the server, if we could tell.
+	mta_software: Option<String>,
+	/// A handle on the socket, to pipeline commands (RFC 2920), which
+	/// async-smtp doesn't support. Only set if the conversation is in
+	/// plaintext, i.e. `CheckEmailInput::smtp_security` is `None`, as
+	/// async-smtp keeps the TLS stream private.
+	plaintext_stream: Option<TcpStream>,
+}
+
+/// What we know about a new connection's socket, before async-smtp takes it
+/// over.
+#[derive(Debug, Default, Clone)]
+struct SocketInfo {
+	/// The local IP address.
+	source_ip: Option<IpAddr>,
+	/// The SMTP server's IP address, unknown through a proxy.
+	mx_ip: Option<IpAddr>,
+	/// The lines of the server's greeting, unknown with implicit TLS, see
+	/// [`net::peek_greeting`].
+	greeting: Option<Vec<String>>,
+	/// A handle on the socket, if the conversation stays in plaintext, see
+	/// `Connection::plaintext_stream`.
+	plaintext_stream: Option<TcpStream>,
+}
+
+/// Get a SMTP connection on which a mail transaction has been started. If
+/// `input.smtp_pool` is set, idle connections to the host are reused first,
+/// otherwise we open a new connection.
+async fn get_connection(
+	host: &Name,
+	port: u16,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<Connection, SmtpError> {
+	if let Some(pool) = &input.smtp_pool {
+		let pool_host = host_name(host);
+		let key = PoolKey::new(&pool_host, port, input);
+		while let Some(mut connection) = pool.take(&key).await {
+			transcript.note(format!("* Reusing connection to {}:{}", pool_host, port));
+			// If this fails, the server most likely closed the idle connection,
+			// so we try the next one.
+			if start_transaction(
+				&mut connection.transport,
+				&pool_host,
+				port,
+				&connection.extensions,
+				input,
+				transcript,
+			)
+			.await
+			.is_ok()
+			{
+				return Ok(connection);
 			}
 		}
-		_ => result,
+	}
+
+	let started = Instant::now();
+	let result = in_span!(
+		connect_to_host(host, port, input, transcript),
+		"connect_to_host",
+		email = %input.to_emails[0],
+		host = %host,
+		port
+	)
+	.await;
+	emit_event(input, || SmtpEvent::Connect {
+		host,
+		port,
+		duration: started.elapsed(),
+		error: result.as_ref().err(),
+	});
+
+	result
+}
+
+/// Report the event built by `event` to `input.smtp_event_hook`, if set.
+/// The event is only built if there's a hook.
+fn emit_event<'a, F: FnOnce() -> SmtpEvent<'a>>(input: &CheckEmailInput, event: F) {
+	if let Some(hook) = &input.smtp_event_hook {
+		hook.emit(&event());
 	}
 }
 
-/// Get all email details we can from one single `EmailAddress`, without
-/// retries.
-pub async fn check_smtp(
-	to_email: &EmailAddress,
+/// End the SMTP conversation, according to `input.smtp_end_policy`. If it's
+/// Reset and `input.smtp_pool` is set, the transaction is reset and the
+/// connection goes back to the pool, otherwise we QUIT.
+///
+/// This can't fail: we already have the result, and errors at this point,
+/// e.g. a server closing the connection without replying to `QUIT`, say
+/// nothing about it. They're only logged.
+async fn release_connection(
+	mut connection: Connection,
 	host: &Name,
 	port: u16,
-	domain: &str,
 	input: &CheckEmailInput,
-) -> Result<SmtpDetails, SmtpError> {
-	retry(to_email, host, port, domain, input, input.retries).await
+	transcript: &mut Transcript,
+) {
+	if let (SmtpEndPolicy::Reset, Some(pool)) = (input.smtp_end_policy, &input.smtp_pool) {
+		let result = send_command(&mut connection.transport, RsetCommand, input, transcript).await;
+		if result.is_ok() {
+			pool.put(PoolKey::new(&host_name(host), port, input), connection)
+				.await;
+		} else {
+			// The connection is not reusable, but the check succeeded anyways.
+			let _ = connection.transport.close().await;
+		}
+
+		return;
+	}
+
+	transcript.command(&QuitCommand);
+	if let Err(err) = connection.transport.close().await {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} Error while closing {}:{}, ignoring it: '{}'.",
+			input.to_emails[0],
+			host,
+			port,
+			err
+		);
+		transcript.note(format!("! QUIT failed: {}", err));
+	}
 }
 
-#[cfg(test)]
-mod tests {
-	use super::{check_smtp, CheckEmailInput, SmtpError};
-	use async_smtp::EmailAddress;
-	use std::{str::FromStr, time::Duration};
-	use tokio::runtime::Runtime;
-	use trust_dns_proto::rr::Name;
+/// The normalized name of a host, without the trailing dot.
+fn host_name(host: &Name) -> String {
+	host.to_string().trim_end_matches('.').to_lowercase()
+}
 
-	#[test]
-	fn should_timeout() {
-		let runtime = Runtime::new().unwrap();
+/// Record the outcome of the connection attempt in the transcript.
+fn record_connection(
+	transcript: &mut Transcript,
+	result: &Result<(), AsyncSmtpError>,
+	host: &str,
+	port: u16,
+) {
+	match result {
+		Ok(_) => transcript.note(format!("* Connected to {}:{}", host, port)),
+		Err(err) => transcript.note(format!("! Cannot connect to {}:{}: {}", host, port, err)),
+	}
+}
 
-		let to_email = EmailAddress::from_str("foo@gmail.com").unwrap();
-		let host = Name::from_str("gmail.com").unwrap();
-		let mut input = CheckEmailInput::default();
-		input.set_smtp_timeout(Duration::from_millis(1));
+/// Description of the deliverability information we can gather from
+/// communicating with the SMTP server.
+#[derive(Debug, Default, PartialEq)]
+struct Deliverability {
+	/// Is this email account's inbox full?
+	has_full_inbox: bool,
+	/// Can we send an email to this address?
+	is_deliverable: bool,
+	/// Is the email blocked or disabled by the provider?
+	is_disabled: bool,
+	/// The SMTP reply code returned to the `RCPT TO` command.
+	smtp_code: Option<u16>,
+	/// The enhanced status code returned to the `RCPT TO` command.
+	smtp_enhanced_code: Option<String>,
+	/// The text of the reply to the `RCPT TO` command.
+	smtp_message: Option<String>,
+	/// Did `VRFY` contradict `RCPT TO`?
+	has_vrfy_mismatch: bool,
+	/// Did the server refuse the email because of an anti-spam policy?
+	is_blocked: bool,
+	/// Does the account receive too many emails right now?
+	is_rate_limited: bool,
+}
 
-		let res = runtime.block_on(check_smtp(&to_email, &host, 25, "gmail.com", &input));
-		match res {
+/// Parse the 3-digit SMTP reply code out of a server response.
+fn parse_smtp_code(response: &Response) -> Option<u16> {
+	response.code.to_string().parse().ok()
+}
+
+/// Check if a transient response looks like greylisting.
+fn is_greylisting_response(response: &Response) -> bool {
+	if !matches!(parse_smtp_code(response), Some(421) | Some(451)) {
+		return false;
+	}
+
+	let message = response.message.join(" ").to_lowercase();
+	// 451 4.7.1 Greylisting in action, please come back later
+	message.contains("greylist")
+		|| message.contains("graylist")
+		// 451 4.7.1 Please try again later
+		|| message.contains("try again later")
+		// 421 4.7.0 Try later
+		|| message.contains("try later")
+		// 451 Temporarily deferred
+		|| message.contains("deferred")
+		// 451 4.7.1 <EMAIL>: Recipient address rejected: Please retry
+		|| message.contains("please retry")
+}
+
+/// Parse the delay suggested by the server in a transient reply, e.g.
+/// "451 Greylisted, please try again in 5 minutes".
+fn parse_retry_delay_hint(response: &Response) -> Option<Duration> {
+	let re = Regex::new(r"(\d+)\s*(second|sec|minute|min|hour)s?\b").expect("Correct regex. qed.");
+	let message = response.message.join(" ").to_lowercase();
+	let captures = re.captures(&message)?;
+	let value: u64 = captures[1].parse().ok()?;
+	let unit = match &captures[2] {
+		"second" | "sec" => 1,
+		"minute" | "min" => 60,
+		_ => 3600,
+	};
+
+	Some(Duration::from_secs(value.saturating_mul(unit)))
+}
+
+/// The submission ports to probe when port 25 is unreachable, see
+/// `CheckEmailInput::submission_port_fallback`, with the TLS mode each one
+/// expects.
+const SUBMISSION_PORTS: [(u16, SmtpSecurity); 2] = [
+	(587, SmtpSecurity::Opportunistic),
+	(465, SmtpSecurity::Wrapper),
+];
+
+/// Did the check fail because we couldn't reach the SMTP server at all,
+/// i.e. the connection was refused or timed out?
+fn is_unreachable(result: &Result<SmtpDetails, SmtpError>) -> bool {
+	match result {
+		Ok(details) => !details.can_connect_smtp && details.catch_all_mx_pattern.is_none(),
+		Err(err) => matches!(
+			err,
+			SmtpError::TimeoutError(_) | SmtpError::SmtpError(AsyncSmtpError::Timeout(_))
+		),
+	}
+}
+
+/// Find a submission port on which `host` answers. Any reply counts, as
+/// these ports usually require authentication before `MAIL FROM`.
+async fn probe_submission_ports(host: &Name, input: &CheckEmailInput) -> Option<u16> {
+	for (port, smtp_security) in SUBMISSION_PORTS {
+		let input = CheckEmailInput {
+			smtp_security,
+			..input.clone()
+		};
+		let mut transcript = Transcript::new(false);
+		match connect_to_host(host, port, &input, &mut transcript).await {
+			Ok(mut connection) => {
+				let _ = connection.transport.close().await;
+				return Some(port);
+			}
 			Err(SmtpError::TimeoutError(_)) => (),
-			_ => panic!("check_smtp did not time out"),
+			Err(err) if is_connection_error(&err) => (),
+			Err(_) => return Some(port),
+		}
+	}
+
+	None
+}
+
+/// Only retry if the error was a temporary/transient error, whose code is
+/// in `input.retryable_codes` if set, or a timeout error.
+fn is_retryable(err: &SmtpError, input: &CheckEmailInput) -> bool {
+	match err {
+		SmtpError::SmtpError(AsyncSmtpError::Transient(response)) => match &input.retryable_codes {
+			Some(codes) => parse_smtp_code(response).map_or(false, |code| codes.contains(&code)),
+			None => true,
+		},
+		SmtpError::SmtpError(AsyncSmtpError::Timeout(_)) => true,
+		_ => false,
+	}
+}
+
+/// The delay to wait before the retry number `attempt` (starting at 1),
+/// i.e. `input.retry_base_delay` doubled at each attempt, unless the server
+/// suggested one, capped by `input.retry_delay`.
+fn retry_delay(err: &SmtpError, attempt: usize, input: &CheckEmailInput) -> Option<Duration> {
+	let max_delay = input.retry_delay?;
+	let hint = match err {
+		SmtpError::SmtpError(AsyncSmtpError::Transient(response)) => {
+			parse_retry_delay_hint(response)
+		}
+		_ => None,
+	};
+
+	Some(
+		hint.unwrap_or_else(|| retry_backoff(attempt, input))
+			.min(max_delay),
+	)
+}
+
+/// The exponential backoff before the retry number `attempt` (starting at
+/// 1), i.e. `input.retry_base_delay` doubled at each attempt.
+fn retry_backoff(attempt: usize, input: &CheckEmailInput) -> Duration {
+	input
+		.retry_base_delay
+		.saturating_mul(1 << attempt.saturating_sub(1).min(16))
+}
+
+/// Randomize `delay` by up to `jitter` (a fraction of it, clamped between 0
+/// and 1), in both directions.
+fn with_jitter<R: Rng>(delay: Duration, jitter: f64, rng: &mut R) -> Duration {
+	let jitter = jitter.clamp(0.0, 1.0);
+	if jitter == 0.0 {
+		return delay;
+	}
+
+	delay.mul_f64(1.0 + rng.gen_range(-jitter..=jitter))
+}
+
+/// Parse both the reply code and the enhanced status code out of an SMTP
+/// error. Only transient (4xx) and permanent (5xx) errors carry a server
+/// response, other errors (IO, TLS...) don't have any code.
+fn parse_smtp_error_codes(err: &AsyncSmtpError) -> (Option<u16>, Option<String>) {
+	match err {
+		AsyncSmtpError::Transient(response) | AsyncSmtpError::Permanent(response) => (
+			parse_smtp_code(response),
+			parse_smtp_enhanced_code(response),
+		),
+		_ => (None, None),
+	}
+}
+
+/// The text of the server's response in an SMTP error, if any.
+fn parse_smtp_error_message(err: &AsyncSmtpError) -> Option<String> {
+	match err {
+		AsyncSmtpError::Transient(response) | AsyncSmtpError::Permanent(response) => {
+			Some(response.message.join(" "))
+		}
+		_ => None,
+	}
+}
+
+/// Parse the RFC 3463 enhanced status code (e.g. "5.1.1") out of a server
+/// response. By convention, it's the first word of the response text.
+fn parse_smtp_enhanced_code(response: &Response) -> Option<String> {
+	let first_word = response.first_word()?;
+	let parts: Vec<&str> = first_word.split('.').collect();
+	let is_valid = parts.len() == 3
+		&& matches!(parts[0], "2" | "4" | "5")
+		&& parts[1..]
+			.iter()
+			.all(|p| !p.is_empty() && p.len() <= 3 && p.chars().all(|c| c.is_ascii_digit()));
+
+	if is_valid {
+		Some(first_word.to_string())
+	} else {
+		None
+	}
+}
+
+/// Lowercase substrings of replies saying that the server rejected our
+/// `MAIL FROM` address, see [`SmtpError::SenderRejected`].
+const SENDER_REJECTION_PATTERNS: &[&str] = &[
+	// 550 Sender verify failed
+	// 451 Could not complete sender verify callout
+	"sender verify",
+	// 550 5.1.7 Sender verification failed
+	"sender verification",
+	// 550 Callout verification failure
+	// 550 5.7.1 Callout verification failed: 550 5.1.1 User unknown
+	"callout verification",
+	// 550 5.1.0 Sender address rejected: Domain not found
+	"sender address rejected",
+];
+
+/// Does the server's reply say that it rejected our `MAIL FROM` address,
+/// e.g. because its sender verification callback failed? Such replies often
+/// come after `RCPT TO`, as the callback needs the recipient.
+fn is_sender_rejected(err: &AsyncSmtpError) -> bool {
+	match err {
+		AsyncSmtpError::Transient(response) | AsyncSmtpError::Permanent(response) => {
+			let message = response.message.join(" ").to_lowercase();
+			// Sender address errors, e.g. "5.1.8 Bad sender's system address".
+			matches!(
+				parse_smtp_enhanced_code(response).as_deref(),
+				Some("5.1.7") | Some("5.1.8")
+			) || SENDER_REJECTION_PATTERNS
+				.iter()
+				.any(|pattern| message.contains(pattern))
+		}
+		_ => false,
+	}
+}
+
+/// Does the server's reply say that the IP we connect from is blocked?
+fn is_ip_blocked(err: &AsyncSmtpError, input: &CheckEmailInput) -> bool {
+	match err {
+		AsyncSmtpError::Transient(response) | AsyncSmtpError::Permanent(response) => {
+			let message = response.message.join(" ").to_lowercase();
+			input
+				.ip_block_indicators
+				.iter()
+				.any(|indicator| message.contains(&indicator.to_lowercase()))
 		}
+		_ => false,
+	}
+}
+
+/// Check if `to_email` exists on host SMTP server. This is the core logic of
+/// this tool.
+async fn email_deliverable(
+	smtp_transport: &mut SmtpTransport,
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<Deliverability, SmtpError> {
+	// "RCPT TO: me@email.com"
+	// FIXME Do not clone?
+	let rcpt_command = RcptCommand::new(to_email.clone(), vec![]);
+	let started = Instant::now();
+	let result = in_span!(
+		send_command(smtp_transport, rcpt_command, input, transcript),
+		"email_deliverable",
+		email = %to_email
+	)
+	.await;
+
+	rcpt_deliverability(to_email, result, started.elapsed(), input)
+}
+
+/// Classify the server's reply to `RCPT TO`, received after `duration`, see
+/// [`email_deliverable`].
+fn rcpt_deliverability(
+	to_email: &EmailAddress,
+	result: Result<Response, AsyncSmtpError>,
+	duration: Duration,
+	input: &CheckEmailInput,
+) -> Result<Deliverability, SmtpError> {
+	emit_event(input, || SmtpEvent::RcptTo {
+		to_email,
+		duration,
+		smtp_code: match &result {
+			Ok(response) => parse_smtp_code(response),
+			Err(err) => parse_smtp_error_codes(err).0,
+		},
+		is_accepted: result.is_ok(),
+	});
+	// Some block messages look like a rejection of the recipient, e.g.
+	// "550 Mailbox unavailable, your IP is blacklisted".
+	let result = match result {
+		Err(err) if is_ip_blocked(&err, input) => return Err(SmtpError::IpBlocked(err)),
+		Err(err) if is_sender_rejected(&err) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"email={} The server rejected the sender {}, try another from_email.",
+				input.to_emails[0],
+				input.from_email
+			);
+			return Err(SmtpError::SenderRejected(err));
+		}
+		result => result,
+	};
+
+	deliverability_from_result(result, input)
+}
+
+/// Classify the server's response to a `RCPT TO` (or `VRFY`) command into a
+/// `Deliverability`. Returns an error if the response is ambiguous.
+fn deliverability_from_result(
+	result: Result<Response, AsyncSmtpError>,
+	input: &CheckEmailInput,
+) -> Result<Deliverability, SmtpError> {
+	match result {
+		Ok(response) => {
+			// According to RFC 5321, `RCPT TO` command succeeds with 250 and
+			// 251 codes only (no 3xx codes at all):
+			// https://tools.ietf.org/html/rfc5321#page-56
+			//
+			// Where the 251 code is used for forwarding, which is not our case,
+			// because we always deliver to the SMTP server hosting the address
+			// itself.
+			//
+			// So, if `response.is_positive()` (which is a condition for
+			// returning `Ok` from the `command()` method above), then delivery
+			// succeeds, accordingly to RFC 5321.
+			Ok(Deliverability {
+				has_full_inbox: false,
+				is_deliverable: true, // response.is_positive()
+				is_disabled: false,
+				smtp_code: parse_smtp_code(&response),
+				smtp_enhanced_code: parse_smtp_enhanced_code(&response),
+				smtp_message: Some(response.message.join(" ")),
+				has_vrfy_mismatch: false,
+				is_blocked: false,
+				is_rate_limited: false,
+			})
+		}
+		Err(err) => {
+			let (smtp_code, smtp_enhanced_code) = parse_smtp_error_codes(&err);
+			// Security or policy status (RFC 3463), e.g. "5.7.1 Message
+			// rejected", is an anti-spam block, whatever the text says: the
+			// text often mentions the recipient, e.g. "5.7.1 Recipient
+			// address rejected: Access denied" or "5.7.1 Message
+			// undeliverable". Sender rejections are caught before, see
+			// `rcpt_deliverability`.
+			let is_policy_block = smtp_enhanced_code
+				.as_deref()
+				.map_or(false, |code| code.starts_with("5.7."));
+			let deliverability = if is_policy_block {
+				Some(deliverability_from_classification(Classification::Blocked))
+			} else {
+				classify_rcpt_error(&err.to_string(), input)
+			};
+			match deliverability {
+				Some(deliverability) => Ok(Deliverability {
+					smtp_code,
+					smtp_enhanced_code,
+					smtp_message: parse_smtp_error_message(&err),
+					..deliverability
+				}),
+				None => Err(SmtpError::SmtpError(err)),
+			}
+		}
+	}
+}
+
+/// Classify the error message of a rejected `RCPT TO` (or `VRFY`) into a
+/// `Deliverability`, without the SMTP codes. Returns None if the message is
+/// ambiguous.
+fn classify_rcpt_error(err_string: &str, input: &CheckEmailInput) -> Option<Deliverability> {
+	classify_message(err_string, input).map(deliverability_from_classification)
+}
+
+/// The `Deliverability` told by a `classification`, without the SMTP codes.
+fn deliverability_from_classification(classification: Classification) -> Deliverability {
+	Deliverability {
+		has_full_inbox: classification == Classification::FullInbox,
+		is_deliverable: classification == Classification::ReceivingAtRate,
+		is_disabled: classification == Classification::Disabled,
+		smtp_code: None,
+		smtp_enhanced_code: None,
+		smtp_message: None,
+		has_vrfy_mismatch: false,
+		is_blocked: classification == Classification::Blocked,
+		is_rate_limited: classification == Classification::ReceivingAtRate,
+	}
+}
+
+/// Check if `to_email` exists on host SMTP server, using the `VRFY` command.
+/// Returns `None` if the answer is inconclusive, e.g. when the server
+/// disabled `VRFY` (which is very common).
+async fn email_vrfy(
+	smtp_transport: &mut SmtpTransport,
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Option<Deliverability> {
+	// "VRFY me@email.com"
+	let vrfy_command = VrfyCommand::new(to_email.to_string());
+	let result = send_command(smtp_transport, vrfy_command, input, transcript).await;
+
+	match result {
+		// 252 Cannot VRFY user, but will accept message and attempt delivery
+		Ok(response) if response.has_code(252) => None,
+		result => deliverability_from_result(result, input).ok(),
+	}
+}
+
+/// If the `RCPT TO` result is ambiguous, try to resolve it with the `VRFY`
+/// command. If both commands disagree, we trust `RCPT TO`.
+async fn fallback_to_vrfy(
+	rcpt_result: Result<Deliverability, SmtpError>,
+	smtp_transport: &mut SmtpTransport,
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<Deliverability, SmtpError> {
+	let rcpt_err = match rcpt_result {
+		Err(SmtpError::SmtpError(err)) => err,
+		_ => return rcpt_result,
+	};
+	let vrfy = match email_vrfy(smtp_transport, to_email, input, transcript).await {
+		Some(vrfy) => vrfy,
+		None => return Err(SmtpError::SmtpError(rcpt_err)),
+	};
+
+	// The `RCPT TO` and `VRFY` codes might differ, but we always report
+	// `RCPT TO`'s.
+	let (smtp_code, smtp_enhanced_code) = parse_smtp_error_codes(&rcpt_err);
+	if vrfy.is_deliverable && matches!(rcpt_err, AsyncSmtpError::Permanent(_)) {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} VRFY accepted the address, but RCPT TO rejected it with '{}'.",
+			to_email,
+			rcpt_err
+		);
+		return Ok(Deliverability {
+			has_full_inbox: false,
+			is_deliverable: false,
+			is_disabled: false,
+			smtp_code,
+			smtp_enhanced_code,
+			smtp_message: parse_smtp_error_message(&rcpt_err),
+			has_vrfy_mismatch: true,
+			is_blocked: false,
+			is_rate_limited: false,
+		});
+	}
+
+	Ok(Deliverability {
+		smtp_code,
+		smtp_enhanced_code,
+		smtp_message: parse_smtp_error_message(&rcpt_err),
+		..vrfy
+	})
+}
+
+/// Check if `to_email` is a mailing list, using the `EXPN` command. Servers
+/// which disable `EXPN` will make this function return false.
+async fn smtp_is_mailing_list(
+	smtp_transport: &mut SmtpTransport,
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> bool {
+	// "EXPN me@email.com"
+	let expn_command = ExpnCommand::new(to_email.to_string());
+	let result = send_command(smtp_transport, expn_command, input, transcript).await;
+
+	// A mailing list is expanded into a multi-line 250 response, one line
+	// per member.
+	match result {
+		Ok(response) => response.has_code(250) && response.message.len() > 1,
+		Err(_) => false,
+	}
+}
+
+/// Create a random 15-char alphanumerical string.
+fn random_local_part<R: Rng>(rng: &mut R) -> String {
+	iter::repeat(())
+		.map(|()| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(15)
+		.collect()
+}
+
+/// The addresses of the catch-all probes on `domain`, see
+/// [`smtp_is_catch_all`].
+///
+/// There are `input.catch_all_probes` probes. Each probe uses a random local
+/// part, unless `input.catch_all_local_part` is set, generated from
+/// `input.catch_all_rng_seed` if set.
+fn catch_all_probes(domain: &str, input: &CheckEmailInput) -> Result<Vec<EmailAddress>, SmtpError> {
+	let mut rng = match input.catch_all_rng_seed {
+		Some(seed) => SmallRng::seed_from_u64(seed),
+		None => SmallRng::from_entropy(),
+	};
+
+	(0..input.catch_all_probes.max(1))
+		.map(|i| {
+			let local_part = match (&input.catch_all_local_part, i) {
+				(Some(local_part), 0) => local_part.clone(),
+				// Don't send the same probe twice.
+				(Some(local_part), i) => format!("{}{}", local_part, i),
+				(None, _) => random_local_part(&mut rng),
+			};
+			EmailAddress::new(format!("{}@{}", local_part, domain)).map_err(|_| {
+				SmtpError::SmtpError(AsyncSmtpError::Client("Invalid catch-all local part"))
+			})
+		})
+		.collect()
+}
+
+/// Verify the existence of a catch-all on the domain.
+///
+/// We send the `probes` to the server, and only consider the domain as
+/// catch-all if all of them are accepted.
+async fn smtp_is_catch_all(
+	smtp_transport: &mut SmtpTransport,
+	probes: &[EmailAddress],
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<bool, SmtpError> {
+	for probe_email in probes {
+		let deliverability =
+			email_deliverable(smtp_transport, probe_email, input, transcript).await?;
+		if deliverability.is_blocked {
+			// A blocked probe says nothing about the catch-all.
+			return Err(SmtpError::SmtpError(AsyncSmtpError::Client(
+				"The catch-all probe was blocked",
+			)));
+		}
+		if !deliverability.is_deliverable || deliverability.is_rate_limited {
+			// No need to send the other probes. A rate-limited probe exists,
+			// so it doesn't tell a catch-all either.
+			return Ok(false);
+		}
+	}
+
+	Ok(true)
+}
+
+/// Probe the server for a catch-all, see [`smtp_is_catch_all`]. Like the
+/// main check, the probe is retried on retryable errors, e.g. greylisting,
+/// up to `input.retries` attempts in total. The retries reuse the same
+/// connection and the same probes, as greylisting usually remembers the
+/// recipient.
+async fn probe_catch_all(
+	connection: &mut Connection,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<bool, SmtpError> {
+	let probes = catch_all_probes(domain, input)?;
+	let mut attempt = 1;
+	loop {
+		match smtp_is_catch_all(&mut connection.transport, &probes, input, transcript).await {
+			Err(err) if attempt < input.retries && is_retryable(&err, input) => {
+				log::debug!(
+					target: LOG_TARGET,
+					"Catch-all probe attempt #{} failed on {}:{}, retrying, error={:?}",
+					attempt,
+					host,
+					port,
+					err
+				);
+				let delay = retry_delay(&err, attempt, input).map(|delay| {
+					with_jitter(delay, input.retry_jitter, &mut SmallRng::from_entropy())
+				});
+				if let Some(delay) = delay {
+					until_cancelled(
+						input.cancellation_token.as_ref(),
+						async_std::task::sleep(delay),
+					)
+					.await
+					.ok_or(SmtpError::Cancelled)?;
+				}
+				reset_transaction(connection, host, port, input, transcript).await?;
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+/// The result of [`catch_all_status`].
+struct CatchAllStatus {
+	is_catch_all: bool,
+	/// The error of the catch-all probe, see
+	/// `SmtpDetails::catch_all_check_error`.
+	error: Option<String>,
+}
+
+/// Get the catch-all status of the domain, from `input.catch_all_cache` if
+/// possible, otherwise by probing the server. If the probe fails, the domain
+/// is considered not catch-all, and the error is returned in the status.
+///
+/// If we probed the server and the domain isn't catch-all, or the email is
+/// checked anyway with `input.strict_catch_all`, the transaction is reset
+/// afterwards, so that the probes don't interfere with the next `RCPT TO`.
+async fn catch_all_status(
+	connection: &mut Connection,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<CatchAllStatus, SmtpError> {
+	if !input.check_catch_all {
+		return Ok(CatchAllStatus {
+			is_catch_all: false,
+			error: None,
+		});
+	}
+
+	if let Some(is_catch_all) = input
+		.catch_all_cache
+		.as_ref()
+		.and_then(|cache| cache.get(domain))
+	{
+		return Ok(CatchAllStatus {
+			is_catch_all,
+			error: None,
+		});
+	}
+
+	let status = match probe_catch_all(connection, host, port, domain, input, transcript).await {
+		Ok(is_catch_all) => {
+			if let Some(cache) = &input.catch_all_cache {
+				cache.insert(domain, is_catch_all);
+			}
+			CatchAllStatus {
+				is_catch_all,
+				error: None,
+			}
+		}
+		Err(SmtpError::Cancelled) => return Err(SmtpError::Cancelled),
+		Err(err) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"Catch-all probe failed on {}:{}, error={:?}",
+				host,
+				port,
+				err
+			);
+			CatchAllStatus {
+				is_catch_all: false,
+				error: Some(err.to_string()),
+			}
+		}
+	};
+
+	if !status.is_catch_all || input.strict_catch_all {
+		reset_transaction(connection, host, port, input, transcript).await?;
+	}
+
+	Ok(status)
+}
+
+/// Reset the current mail transaction with `RSET`, and start a new one. If
+/// the server doesn't support `RSET`, we reconnect instead.
+async fn reset_transaction(
+	connection: &mut Connection,
+	host: &Name,
+	port: u16,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<(), SmtpError> {
+	if send_command(&mut connection.transport, RsetCommand, input, transcript)
+		.await
+		.is_ok()
+	{
+		return start_transaction(
+			&mut connection.transport,
+			&host_name(host),
+			port,
+			&connection.extensions,
+			input,
+			transcript,
+		)
+		.await;
+	}
+
+	log::debug!(
+		target: LOG_TARGET,
+		"RSET failed on {}:{}, reconnecting.",
+		host,
+		port
+	);
+	let _ = connection.transport.close().await;
+	*connection = get_connection(host, port, input, transcript).await?;
+
+	Ok(())
+}
+
+/// Check one recipient on an established connection. The returned details
+/// have an empty transcript, it's up to the caller to fill it.
+async fn check_recipient(
+	connection: &mut Connection,
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	is_catch_all: bool,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+) -> Result<SmtpDetails, SmtpError> {
+	let rcpt_email = rcpt_address(to_email, has_extension(&connection.extensions, "SMTPUTF8"));
+	let deliverability = if is_catch_all && !input.strict_catch_all {
+		Deliverability {
+			has_full_inbox: false,
+			is_deliverable: true,
+			is_disabled: false,
+			smtp_code: None,
+			smtp_enhanced_code: None,
+			smtp_message: None,
+			has_vrfy_mismatch: false,
+			is_blocked: false,
+			is_rate_limited: false,
+		}
+	} else if let Some(to_email) = &rcpt_email {
+		let mut result =
+			email_deliverable(&mut connection.transport, to_email, input, transcript).await;
+
+		// Some SMTP servers automatically close the connection after an error,
+		// so we should reconnect to perform a next command.
+		//
+		// Unfortunately `smtp_transport.is_connected()` doesn't report about this,
+		// so we can only check for "io: incomplete" SMTP error being returned.
+		// https://github.com/async-email/async-smtp/issues/37
+		if is_io_incomplete_smtp_error(&result) {
+			log::debug!(
+				target: LOG_TARGET,
+				"Got `io: incomplete` error, reconnecting."
+			);
+
+			let _ = connection.transport.close().await;
+			*connection = get_connection(host, port, input, transcript).await?;
+			result =
+				email_deliverable(&mut connection.transport, to_email, input, transcript).await;
+		}
+
+		if input.use_vrfy {
+			result = fallback_to_vrfy(
+				result,
+				&mut connection.transport,
+				to_email,
+				input,
+				transcript,
+			)
+			.await;
+		}
+
+		result?
+	} else {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} The server doesn't support SMTPUTF8, cannot verify the local part.",
+			to_email
+		);
+		Deliverability {
+			has_full_inbox: false,
+			is_deliverable: false,
+			is_disabled: false,
+			smtp_code: None,
+			smtp_enhanced_code: None,
+			smtp_message: None,
+			has_vrfy_mismatch: false,
+			is_blocked: false,
+			is_rate_limited: false,
+		}
+	};
+
+	let is_mailing_list = if input.use_expn && deliverability.is_deliverable {
+		smtp_is_mailing_list(&mut connection.transport, to_email, input, transcript).await
+	} else {
+		false
+	};
+
+	Ok(recipient_details(
+		connection,
+		deliverability,
+		is_catch_all,
+		is_mailing_list,
+		rcpt_email.is_none(),
+		input,
+	))
+}
+
+/// The details of a recipient checked on `connection`, with an empty
+/// transcript.
+fn recipient_details(
+	connection: &Connection,
+	deliverability: Deliverability,
+	is_catch_all: bool,
+	is_mailing_list: bool,
+	is_unverifiable: bool,
+	input: &CheckEmailInput,
+) -> SmtpDetails {
+	SmtpDetails {
+		can_connect_smtp: true,
+		has_full_inbox: deliverability.has_full_inbox,
+		is_catch_all,
+		is_deliverable: deliverability.is_deliverable,
+		is_disabled: deliverability.is_disabled,
+		is_blocked: deliverability.is_blocked,
+		is_rate_limited: deliverability.is_rate_limited,
+		smtp_code: deliverability.smtp_code,
+		smtp_enhanced_code: deliverability.smtp_enhanced_code,
+		smtp_message: deliverability.smtp_message,
+		has_vrfy_mismatch: deliverability.has_vrfy_mismatch,
+		has_from_email_mismatch: false,
+		is_mailing_list,
+		is_unverifiable,
+		catch_all_mx_pattern: None,
+		catch_all_check_error: None,
+		is_greylisted: false,
+		attempts: 1,
+		retries_exhausted: false,
+		mx_host: None,
+		mx_priority: None,
+		mx_ip: connection.mx_ip,
+		mta_software: connection.mta_software.clone(),
+		provider: Provider::Other,
+		supported_extensions: connection.extensions.clone(),
+		max_message_size: parse_max_message_size(&connection.extensions),
+		from_email: Some(mail_from(input).map_or_else(String::new, |email| email.to_string())),
+		used_proxy: connection.used_proxy.clone(),
+		source_ip: connection.source_ip,
+		invalid_local_part: None,
+		has_null_mx: false,
+		is_skipped: false,
+		is_timed_out: false,
+		reason: None,
+		transcript: vec![],
+	}
+}
+
+/// What a SMTP check learned so far. It lives outside of the check's
+/// future, so that we can still report it if the check times out, see
+/// `SmtpDetails::is_timed_out`.
+pub(super) struct Progress {
+	/// The details known so far, e.g. `can_connect_smtp` once connected.
+	details: SmtpDetails,
+	transcript: Transcript,
+}
+
+impl Progress {
+	fn new(input: &CheckEmailInput) -> Self {
+		Progress {
+			details: SmtpDetails::default(),
+			transcript: Transcript::new(input.collect_transcript),
+		}
+	}
+
+	/// The details to report if the check timed out, or None if we didn't
+	/// even connect.
+	fn into_timed_out_details(self) -> Option<SmtpDetails> {
+		if !self.details.can_connect_smtp {
+			return None;
+		}
+
+		Some(SmtpDetails {
+			is_timed_out: true,
+			transcript: self.transcript.into_lines(),
+			..self.details
+		})
+	}
+}
+
+async fn create_smtp_future(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	progress: &mut Progress,
+) -> Result<SmtpDetails, SmtpError> {
+	let token = input.cancellation_token.as_ref();
+	let transcript = &mut progress.transcript;
+
+	let connection = until_cancelled(token, get_connection(host, port, input, transcript))
+		.await
+		.ok_or(SmtpError::Cancelled)?;
+	let mut connection = match connection {
+		Ok(connection) => connection,
+		Err(err) if is_connection_error(&err) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"email={} Cannot connect to {}:{}, error={:?}",
+				to_email,
+				host,
+				port,
+				err
+			);
+			return Ok(SmtpDetails {
+				can_connect_smtp: false,
+				transcript: transcript.take_lines(),
+				..Default::default()
+			});
+		}
+		Err(err) => return Err(err),
+	};
+	progress.details = SmtpDetails {
+		can_connect_smtp: true,
+		supported_extensions: connection.extensions.clone(),
+		max_message_size: parse_max_message_size(&connection.extensions),
+		from_email: Some(mail_from(input).map_or_else(String::new, |email| email.to_string())),
+		used_proxy: connection.used_proxy.clone(),
+		source_ip: connection.source_ip,
+		mx_ip: connection.mx_ip,
+		mta_software: connection.mta_software.clone(),
+		..Default::default()
+	};
+
+	let conversation = async {
+		let catch_all = catch_all_status(
+			&mut connection,
+			host,
+			port,
+			domain,
+			input,
+			&mut progress.transcript,
+		)
+		.await?;
+		progress.details.is_catch_all = catch_all.is_catch_all;
+		progress.details.catch_all_check_error = catch_all.error.clone();
+
+		let mut details = check_recipient(
+			&mut connection,
+			to_email,
+			host,
+			port,
+			catch_all.is_catch_all,
+			input,
+			&mut progress.transcript,
+		)
+		.await?;
+		details.catch_all_check_error = catch_all.error;
+		Ok::<_, SmtpError>(details)
+	};
+	let mut details = match until_cancelled(token, conversation).await {
+		Some(details) => details?,
+		None => return Err(close_cancelled(connection, host, port, input).await),
+	};
+
+	let transcript = &mut progress.transcript;
+	release_connection(connection, host, port, input, transcript).await;
+
+	details.transcript = transcript.take_lines();
+	Ok(details)
+}
+
+/// Check all the emails of `to_emails` on a single connection, pushing the
+/// result of each email to `results`, in order. On error, the remaining
+/// emails are left unchecked.
+async fn create_smtp_batch_future(
+	to_emails: &[EmailAddress],
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	results: &mut Vec<Result<SmtpDetails, SmtpError>>,
+) -> Result<(), SmtpError> {
+	// The connection is for the emails of the batch, e.g. they tell whether
+	// we need SMTPUTF8 or PIPELINING.
+	let input = &CheckEmailInput {
+		to_emails: to_emails.iter().map(ToString::to_string).collect(),
+		..input.clone()
+	};
+	let token = input.cancellation_token.as_ref();
+	let mut transcript = Transcript::new(input.collect_transcript);
+	let mut connection = until_cancelled(token, get_connection(host, port, input, &mut transcript))
+		.await
+		.ok_or(SmtpError::Cancelled)??;
+
+	let checks = async {
+		let catch_all =
+			catch_all_status(&mut connection, host, port, domain, input, &mut transcript).await?;
+		if can_pipeline(&connection, catch_all.is_catch_all, input) {
+			return check_recipients_pipelined(
+				&connection,
+				to_emails,
+				host,
+				&catch_all,
+				input,
+				&mut transcript,
+				results,
+			)
+			.await;
+		}
+		for (i, to_email) in to_emails.iter().enumerate() {
+			if let Some(details) = invalid_local_part_details(to_email, input) {
+				results.push(Ok(details.with_reason()));
+				continue;
+			}
+			if i > 0 && (!catch_all.is_catch_all || input.strict_catch_all) {
+				reset_transaction(&mut connection, host, port, input, &mut transcript).await?;
+			}
+			let details = check_recipient(
+				&mut connection,
+				to_email,
+				host,
+				port,
+				catch_all.is_catch_all,
+				input,
+				&mut transcript,
+			)
+			.await?;
+			results.push(Ok(batch_details(
+				details,
+				host,
+				&catch_all,
+				&mut transcript,
+			)));
+		}
+
+		Ok::<(), SmtpError>(())
+	};
+	match until_cancelled(token, checks).await {
+		Some(result) => result?,
+		None => return Err(close_cancelled(connection, host, port, input).await),
+	}
+
+	release_connection(connection, host, port, input, &mut transcript).await;
+	if let Some(Ok(details)) = results.last_mut() {
+		details.transcript.extend(transcript.into_lines());
+	}
+
+	Ok(())
+}
+
+/// Complete the `details` of a recipient checked in a batch, see
+/// [`create_smtp_batch_future`].
+fn batch_details(
+	mut details: SmtpDetails,
+	host: &Name,
+	catch_all: &CatchAllStatus,
+	transcript: &mut Transcript,
+) -> SmtpDetails {
+	details.catch_all_check_error = catch_all.error.clone();
+	details.transcript = transcript.take_lines();
+	details.mx_host = Some(host_name(host));
+	details.provider = classify(std::slice::from_ref(host));
+	details.with_reason()
+}
+
+/// Can the `RCPT TO` commands of a batch be pipelined on `connection`, see
+/// [`check_recipients_pipelined`]? The server must advertise PIPELINING
+/// (RFC 2920), and we must send nothing else between the `RCPT TO`
+/// commands.
+fn can_pipeline(connection: &Connection, is_catch_all: bool, input: &CheckEmailInput) -> bool {
+	connection.plaintext_stream.is_some()
+		&& has_extension(&connection.extensions, "PIPELINING")
+		// Without strict mode, recipients of catch-all domains aren't checked.
+		&& (!is_catch_all || input.strict_catch_all)
+		&& !input.use_vrfy
+		&& !input.use_expn
+}
+
+/// Check all `to_emails` in the current transaction, sending their
+/// `RCPT TO` commands in a single write, see [`pipelining::send_pipelined`].
+/// The results are pushed to `results` once all the replies are read, so
+/// that the batch can be checked again one by one if this fails.
+async fn check_recipients_pipelined(
+	connection: &Connection,
+	to_emails: &[EmailAddress],
+	host: &Name,
+	catch_all: &CatchAllStatus,
+	input: &CheckEmailInput,
+	transcript: &mut Transcript,
+	results: &mut Vec<Result<SmtpDetails, SmtpError>>,
+) -> Result<(), SmtpError> {
+	let smtputf8 = has_extension(&connection.extensions, "SMTPUTF8");
+	// The emails we ask the server about, None for the other ones.
+	let rcpt_emails: Vec<Option<EmailAddress>> = to_emails
+		.iter()
+		.map(
+			|to_email| match invalid_local_part_details(to_email, input) {
+				Some(_) => None,
+				None => rcpt_address(to_email, smtputf8),
+			},
+		)
+		.collect();
+	let commands: Vec<RcptCommand> = rcpt_emails
+		.iter()
+		.flatten()
+		.map(|rcpt_email| RcptCommand::new(rcpt_email.clone(), vec![]))
+		.collect();
+	let stream = connection
+		.plaintext_stream
+		.as_ref()
+		.expect("Checked by can_pipeline. qed.");
+
+	let started = Instant::now();
+	let replies = pipelining::send_pipelined(stream, &commands, input)
+		.await
+		.map_err(SmtpError::SmtpError)?;
+	let duration = started.elapsed();
+
+	let mut replies = commands.iter().zip(replies);
+	let mut pipelined_results = Vec::with_capacity(to_emails.len());
+	for (to_email, rcpt_email) in to_emails.iter().zip(&rcpt_emails) {
+		if let Some(details) = invalid_local_part_details(to_email, input) {
+			pipelined_results.push(Ok(details.with_reason()));
+			continue;
+		}
+		let deliverability = match rcpt_email {
+			Some(rcpt_email) => {
+				let (command, result) =
+					replies.next().expect("There's one reply per RCPT TO. qed.");
+				transcript.command(command);
+				transcript.result(&result);
+				rcpt_deliverability(rcpt_email, result, duration, input)?
+			}
+			None => {
+				log::debug!(
+					target: LOG_TARGET,
+					"email={} The server doesn't support SMTPUTF8, cannot verify the local part.",
+					to_email
+				);
+				Deliverability::default()
+			}
+		};
+		let details = recipient_details(
+			connection,
+			deliverability,
+			catch_all.is_catch_all,
+			false,
+			rcpt_email.is_none(),
+			input,
+		);
+		pipelined_results.push(Ok(batch_details(details, host, catch_all, transcript)));
+	}
+	results.extend(pipelined_results);
+
+	Ok(())
+}
+
+/// Close the connection of a cancelled check, see
+/// `CheckEmailInput::cancellation_token`, and return the error to report.
+async fn close_cancelled(
+	mut connection: Connection,
+	host: &Name,
+	port: u16,
+	input: &CheckEmailInput,
+) -> SmtpError {
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Check cancelled, closing {}:{}.",
+		input.to_emails[0],
+		host,
+		port
+	);
+	let _ = connection.transport.close().await;
+
+	SmtpError::Cancelled
+}
+
+/// The details of an email whose local part is invalid, see
+/// `CheckEmailInput::strict_local_part`, or None if it's valid. We don't
+/// need to ask the server about these emails.
+fn invalid_local_part_details(
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+) -> Option<SmtpDetails> {
+	let address = to_email.to_string();
+	let (local_part, _) = address.rsplit_once('@')?;
+	let reason = validate_local_part(local_part, input.strict_local_part).err()?;
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Invalid local part, error={:?}",
+		to_email,
+		reason
+	);
+
+	Some(SmtpDetails {
+		invalid_local_part: Some(reason),
+		..Default::default()
+	})
+}
+
+/// Indicates whether the error means that we couldn't establish a connection
+/// with the SMTP server at all, e.g. the connection was refused or the TLS
+/// handshake failed. Replies from the server (such as a `554` greeting) are
+/// protocol errors, not connection errors. Timeouts are not considered
+/// connection errors either, so that they are retried.
+fn is_connection_error(err: &SmtpError) -> bool {
+	matches!(
+		err,
+		SmtpError::SmtpError(AsyncSmtpError::Io(_))
+			| SmtpError::SmtpError(AsyncSmtpError::Tls(_))
+			| SmtpError::SmtpError(AsyncSmtpError::NoStream)
+	)
+}
+
+/// Indicates whether the given [`Result`] represents an `io: incomplete`
+/// [`SmtpError::Error`].
+fn is_io_incomplete_smtp_error<T>(result: &Result<T, SmtpError>) -> bool {
+	match result {
+		Err(SmtpError::SmtpError(AsyncSmtpError::Io(err))) => {
+			err.to_string().as_str() == "incomplete"
+		}
+		_ => false,
+	}
+}
+
+/// Run a SMTP check, failing with a timeout error if it takes longer than
+/// `input.smtp_timeout`.
+async fn with_smtp_timeout<F>(fut: F, input: &CheckEmailInput) -> Result<SmtpDetails, SmtpError>
+where
+	F: Future<Output = Result<SmtpDetails, SmtpError>>,
+{
+	if let Some(smtp_timeout) = input.smtp_timeout {
+		future::timeout(smtp_timeout, fut).await?
+	} else {
+		fut.await
+	}
+}
+
+/// Wait until the limits of `input` allow a connection to `host`, see
+/// `CheckEmailInput::rate_limiter` and `connection_limit`. The connection
+/// counts as in progress until the permits are dropped.
+async fn acquire_permits(
+	host: &Name,
+	input: &CheckEmailInput,
+) -> (Option<RateLimitPermit>, Option<ConnectionPermit>) {
+	// Don't hold a global permit while waiting for a busy host.
+	let rate_limit_permit = match &input.rate_limiter {
+		Some(rate_limiter) => Some(rate_limiter.acquire(&host_name(host)).await),
+		None => None,
+	};
+	let connection_permit = match &input.connection_limit {
+		Some(connection_limit) => Some(connection_limit.acquire().await),
+		None => None,
+	};
+
+	(rate_limit_permit, connection_permit)
+}
+
+/// Get all email details we can from one single `EmailAddress`, without
+/// retries.
+async fn check_smtp_without_retry(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> Result<SmtpDetails, SmtpError> {
+	let provider = classify(std::slice::from_ref(host));
+	if let Some(pattern) = provider::find_pattern(&input.catch_all_mx_patterns, &host.to_string()) {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} MX host {} matches the catch-all pattern \"{}\", skipping SMTP.",
+			to_email,
+			host,
+			pattern
+		);
+		return Ok(SmtpDetails {
+			is_catch_all: true,
+			catch_all_mx_pattern: Some(pattern.into()),
+			mx_host: Some(host_name(host)),
+			provider,
+			..Default::default()
+		});
+	}
+
+	let uses_api = match provider {
+		Provider::Yahoo => input.yahoo_verif_method == YahooVerifMethod::Api,
+		Provider::Google => input.gmail_use_api,
+		_ => false,
+	};
+	let token = input.cancellation_token.as_ref();
+	// Wait for our turn before the SMTP timeout starts.
+	let _permits = if uses_api {
+		None
+	} else {
+		Some(
+			until_cancelled(token, acquire_permits(host, input))
+				.await
+				.ok_or(SmtpError::Cancelled)?,
+		)
+	};
+
+	let mut progress = Progress::new(input);
+	let result = match provider {
+		Provider::Yahoo => match input.yahoo_verif_method {
+			YahooVerifMethod::Api => until_cancelled(token, yahoo::check_yahoo(to_email, input))
+				.await
+				.ok_or(SmtpError::Cancelled)?
+				.map_err(|err| err.into()),
+			YahooVerifMethod::Smtp => {
+				with_smtp_timeout(
+					yahoo::check_yahoo_smtp(to_email, host, port, domain, input, &mut progress),
+					input,
+				)
+				.await
+			}
+			YahooVerifMethod::GenericSmtp => {
+				with_smtp_timeout(
+					create_smtp_future(to_email, host, port, domain, input, &mut progress),
+					input,
+				)
+				.await
+			}
+		},
+		Provider::Google if input.gmail_use_api => {
+			until_cancelled(token, gmail::check_gmail(to_email, input))
+				.await
+				.ok_or(SmtpError::Cancelled)?
+				.map_err(|err| err.into())
+		}
+		Provider::Microsoft => {
+			with_smtp_timeout(
+				microsoft::check_microsoft(to_email, host, port, domain, input, &mut progress),
+				input,
+			)
+			.await
+		}
+		Provider::ProtonMail => {
+			with_smtp_timeout(
+				protonmail::check_protonmail(to_email, host, port, domain, input, &mut progress),
+				input,
+			)
+			.await
+		}
+		_ => {
+			with_smtp_timeout(
+				create_smtp_future(to_email, host, port, domain, input, &mut progress),
+				input,
+			)
+			.await
+		}
+	};
+
+	// Keep what we learned before a timeout, once connected.
+	let result = match result {
+		Err(SmtpError::TimeoutError(err)) => progress.into_timed_out_details().ok_or_else(|| {
+			log::debug!(
+				target: LOG_TARGET,
+				"email={} Timed out before connecting to {}:{}.",
+				to_email,
+				host,
+				port
+			);
+			SmtpError::TimeoutError(err)
+		}),
+		result => result,
+	};
+
+	// The server might also block us when connecting, or on `MAIL FROM`.
+	let result = result.map_err(|err| match err {
+		SmtpError::SmtpError(err) if is_ip_blocked(&err, input) => SmtpError::IpBlocked(err),
+		SmtpError::SmtpError(err) if is_sender_rejected(&err) => SmtpError::SenderRejected(err),
+		err => err,
+	});
+
+	result.map(|details| SmtpDetails {
+		mx_host: Some(host_name(host)),
+		provider,
+		..details
+	})
+}
+
+/// Emit the retry event of `attempt`, and wait `delay` (with jitter) before
+/// the next one, unless the check is cancelled.
+async fn wait_before_retry(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	attempt: usize,
+	delay: Option<Duration>,
+	is_greylisted: bool,
+	input: &CheckEmailInput,
+) -> Result<(), SmtpError> {
+	let delay =
+		delay.map(|delay| with_jitter(delay, input.retry_jitter, &mut SmallRng::from_entropy()));
+	emit_event(input, || SmtpEvent::Retry {
+		to_email,
+		host,
+		port,
+		attempt,
+		delay,
+		is_greylisted,
+	});
+	if let Some(delay) = delay {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} Waiting {:?} before retrying.",
+			input.to_emails[0],
+			delay
+		);
+		until_cancelled(
+			input.cancellation_token.as_ref(),
+			async_std::task::sleep(delay),
+		)
+		.await
+		.ok_or(SmtpError::Cancelled)?;
+	}
+
+	Ok(())
+}
+
+/// Get all email details we can from one single `EmailAddress`.
+/// Retry the SMTP connection, in particular to avoid greylisting, or when
+/// we can't connect.
+/// `is_greylisted` tells if one of the previous attempts was greylisted.
+#[async_recursion]
+async fn retry(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	count: usize,
+	is_greylisted: bool,
+) -> Result<SmtpDetails, SmtpError> {
+	let token = input.cancellation_token.as_ref();
+	if token.map_or(false, |token| token.is_cancelled()) {
+		return Err(SmtpError::Cancelled);
+	}
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Check SMTP attempt #{} on {}:{}",
+		input.to_emails[0],
+		input.retries - count + 1,
+		host,
+		port
+	);
+
+	let started = Instant::now();
+	let result = in_span!(
+		check_smtp_without_retry(to_email, host, port, domain, input),
+		"smtp_attempt",
+		email = %to_email,
+		host = %host,
+		port,
+		attempt = input.retries - count + 1
+	)
+	.await;
+	emit_event(input, || SmtpEvent::Attempt {
+		to_email,
+		host,
+		port,
+		attempt: input.retries - count + 1,
+		duration: started.elapsed(),
+		result: &result,
+	});
+
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Got result for attempt #{} on {}:{}, result={:?}",
+		input.to_emails[0],
+		input.retries - count + 1,
+		host,
+		port,
+		result
+	);
+
+	match result {
+		Err(err) if input.defer_greylisting && err.is_greylisted() => {
+			log::debug!(
+				target: LOG_TARGET,
+				"email={} Greylisting detected, deferring the check.",
+				input.to_emails[0],
+			);
+			Err(SmtpError::Greylisted(GreylistToken::new(
+				to_email,
+				host_name(host),
+				port,
+				&err,
+				input,
+			)))
+		}
+		Err(err) if is_retryable(&err, input) && count > 1 => {
+			let is_greylisted = if err.is_greylisted() {
+				log::debug!(
+					target: LOG_TARGET,
+					"email={} Greylisting detected, retrying.",
+					input.to_emails[0],
+				);
+				true
+			} else {
+				log::debug!(
+					target: LOG_TARGET,
+					"email={} Potential greylisting detected, retrying.",
+					input.to_emails[0],
+				);
+				is_greylisted
+			};
+
+			let attempt = input.retries - count + 1;
+			let delay = retry_delay(&err, attempt, input);
+			wait_before_retry(
+				to_email,
+				host,
+				port,
+				attempt,
+				delay,
+				err.is_greylisted(),
+				input,
+			)
+			.await?;
+			retry(
+				to_email,
+				host,
+				port,
+				domain,
+				input,
+				count - 1,
+				is_greylisted,
+			)
+			.await
+		}
+		// The server might be down for a moment, or refuse connections
+		// because of a connection limit.
+		Ok(details)
+			if !details.can_connect_smtp && details.catch_all_mx_pattern.is_none() && count > 1 =>
+		{
+			log::debug!(
+				target: LOG_TARGET,
+				"email={} Cannot connect, retrying.",
+				input.to_emails[0],
+			);
+
+			let attempt = input.retries - count + 1;
+			let delay = input
+				.retry_delay
+				.map(|max_delay| retry_backoff(attempt, input).min(max_delay));
+			wait_before_retry(to_email, host, port, attempt, delay, false, input).await?;
+			retry(
+				to_email,
+				host,
+				port,
+				domain,
+				input,
+				count - 1,
+				is_greylisted,
+			)
+			.await
+		}
+		Ok(mut details) => {
+			details.is_greylisted = is_greylisted;
+			details.attempts = input.retries - count + 1;
+			details.retries_exhausted = details.attempts > 1 && count <= 1;
+			Ok(details)
+		}
+		_ => result,
+	}
+}
+
+/// Get all email details we can from one single `EmailAddress`, without
+/// retries.
+pub async fn check_smtp(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> Result<SmtpDetails, SmtpError> {
+	// Internationalized domains are used in punycode, e.g. in the catch-all
+	// probe.
+	let domain =
+		&idna::domain_to_ascii(domain).map_err(|err| SmtpError::InvalidDomain(err.to_string()))?;
+	if let Some(details) = invalid_local_part_details(to_email, input) {
+		return Ok(details.with_reason());
+	}
+	let input = with_valid_from_email(input, domain);
+	let result = match retry(to_email, host, port, domain, &input, input.retries, false).await {
+		result if port == 25 && input.submission_port_fallback && is_unreachable(&result) => {
+			match probe_submission_ports(host, &input).await {
+				Some(port) => {
+					log::debug!(
+						target: LOG_TARGET,
+						"email={} Cannot connect to port 25 of {}, but port {} answers.",
+						to_email,
+						host,
+						port
+					);
+					Err(SmtpError::Port25Blocked(port))
+				}
+				None => result,
+			}
+		}
+		result => result,
+	};
+
+	match result {
+		// Only an answer to `RCPT TO` can be compared, e.g. not the one of a
+		// catch-all domain.
+		Ok(details) if !input.alternative_from_emails.is_empty() && details.smtp_code.is_some() => {
+			Ok(check_alternative_from_emails(to_email, host, port, domain, &input, details).await)
+		}
+		result => result,
+	}
+	.map(SmtpDetails::with_reason)
+}
+
+/// The blocking version of [`check_smtp`], for synchronous code, see
+/// `check_email_blocking`.
+///
+/// # Panics
+///
+/// This function panics if called from within an async runtime: use
+/// [`check_smtp`] there instead.
+#[cfg(feature = "blocking")]
+pub fn check_smtp_blocking(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> Result<SmtpDetails, SmtpError> {
+	crate::util::blocking::block_on(check_smtp(to_email, host, port, domain, input))
+}
+
+/// How specific the `RCPT TO` answer in `details` is, to pick the answer
+/// to report among several `MAIL FROM` addresses: a rejection saying that the
+/// mailbox doesn't exist beats a full inbox, which beats an acceptance, which
+/// beats a rejection without reason, e.g. because of the sender's
+/// reputation.
+fn rcpt_specificity(details: &SmtpDetails) -> u8 {
+	let is_user_unknown = details.is_disabled
+		|| details.smtp_enhanced_code.as_deref().map_or(false, |code| {
+			code.starts_with("5.1.") && code != "5.1.7" && code != "5.1.8"
+		});
+	if is_user_unknown {
+		3
+	} else if details.has_full_inbox || details.is_rate_limited {
+		2
+	} else if details.is_deliverable {
+		1
+	} else {
+		0
+	}
+}
+
+/// Check `to_email` again with each of `input.alternative_from_emails` in
+/// `MAIL FROM`, and reconcile the answers with `details`, the answer with
+/// `input.from_email`, see [`rcpt_specificity`]. Failed checks are ignored.
+async fn check_alternative_from_emails(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	details: SmtpDetails,
+) -> SmtpDetails {
+	let mut alternative_input = input.clone();
+	alternative_input.use_null_sender = false;
+	// We already know whether the domain is catch-all.
+	alternative_input.check_catch_all = false;
+
+	let mut answers = vec![];
+	for from_email in &input.alternative_from_emails {
+		if EmailAddress::from_str(from_email).is_err() {
+			log::warn!(
+				target: LOG_TARGET,
+				"email={} Invalid alternative from email \"{}\", skipping it.",
+				to_email,
+				from_email
+			);
+			continue;
+		}
+		alternative_input.from_email = from_email.clone();
+
+		let result = retry(
+			to_email,
+			host,
+			port,
+			domain,
+			&alternative_input,
+			alternative_input.retries,
+			false,
+		)
+		.await;
+		match result {
+			Ok(answer) if answer.smtp_code.is_some() => answers.push(answer),
+			result => log::debug!(
+				target: LOG_TARGET,
+				"email={} No answer with from email {}, result={:?}",
+				to_email,
+				from_email,
+				result
+			),
+		}
+	}
+
+	let has_from_email_mismatch = answers
+		.iter()
+		.any(|answer| answer.is_deliverable != details.is_deliverable);
+	// On a tie, keep the answer to `input.from_email`.
+	let mut best = details;
+	for answer in answers {
+		if rcpt_specificity(&answer) > rcpt_specificity(&best) {
+			best = SmtpDetails {
+				is_catch_all: best.is_catch_all,
+				catch_all_check_error: best.catch_all_check_error,
+				..answer
+			};
+		}
+	}
+
+	SmtpDetails {
+		has_from_email_mismatch,
+		..best
+	}
+}
+
+/// Get all email details we can from one single `EmailAddress`, trying the
+/// domain's MX hosts in priority order. We only move on to the next host if
+/// we cannot connect to the current one, or if it times out. Any answer from
+/// the server is final, including errors, e.g. a rejection of the recipient,
+/// a block of our IP or of our sender: the backup hosts usually share the
+/// primary's policy, or only relay to it. `SmtpDetails::mx_host` tells which
+/// host answered.
+///
+/// At most `input.max_mx_attempts` hosts are tried. If none of them answers,
+/// the last host's result is returned.
+///
+/// Without any MX host, e.g. because the MX lookup failed with NXDOMAIN, we
+/// don't connect to anything, and the details' reason is `DomainNotFound`.
+///
+/// If `input.greylist_token` resumes the check of `to_email`, only the host
+/// which greylisted us is tried, whatever the MX records.
+pub async fn check_smtp_mx(
+	to_email: &EmailAddress,
+	mx_records: &[MX],
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> Result<SmtpDetails, SmtpError> {
+	if mx_records.is_empty() {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} The domain has no MX host, skipping SMTP.",
+			to_email
+		);
+		return Ok(SmtpDetails::domain_not_found());
+	}
+	if is_null_mx(mx_records) {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} The domain has a null MX, it doesn't accept emails.",
+			to_email
+		);
+		return Ok(SmtpDetails {
+			has_null_mx: true,
+			..Default::default()
+		}
+		.with_reason());
+	}
+	if let Some(token) = input
+		.greylist_token
+		.as_ref()
+		.filter(|token| token.resumes(to_email))
+	{
+		return resume_greylisted(to_email, token, domain, input).await;
+	}
+
+	let mut mx_records = mx_records.iter().collect::<Vec<_>>();
+	// The sort is stable, so hosts with the same preference keep the DNS
+	// order.
+	mx_records.sort_by_key(|mx| mx.preference());
+
+	let mut result = Err(SmtpError::SmtpError(AsyncSmtpError::Client(
+		"No MX records",
+	)));
+	for mx in mx_records.into_iter().take(input.max_mx_attempts.max(1)) {
+		let port = provider_port(mx.exchange(), port, input);
+		result = check_smtp(to_email, mx.exchange(), port, domain, input)
+			.await
+			.map(|details| SmtpDetails {
+				mx_priority: Some(mx.preference()),
+				..details
+			});
+		let is_timed_out = matches!(&result, Ok(details) if details.is_timed_out);
+		if !is_unreachable(&result) && !is_timed_out {
+			break;
+		}
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} No answer from {}, trying next MX host.",
+			to_email,
+			mx.exchange()
+		);
+	}
+
+	result
+}
+
+/// Resume a check deferred because of greylisting, on the same host and with
+/// the same `MAIL FROM` as the greylisted one.
+async fn resume_greylisted(
+	to_email: &EmailAddress,
+	token: &GreylistToken,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> Result<SmtpDetails, SmtpError> {
+	log::debug!(
+		target: LOG_TARGET,
+		"email={} Resuming the greylisted check on {}:{}.",
+		to_email,
+		token.host,
+		token.port
+	);
+	let host =
+		Name::from_str(&token.host).map_err(|err| SmtpError::InvalidDomain(err.to_string()))?;
+	let input = CheckEmailInput {
+		from_email: token.from_email.clone(),
+		..input.clone()
+	};
+
+	check_smtp(to_email, &host, token.port, domain, &input).await
+}
+
+/// The port to connect to on `host`: the one configured in
+/// `input.provider_ports` for its provider, or `port` otherwise.
+fn provider_port(host: &Name, port: u16, input: &CheckEmailInput) -> u16 {
+	input
+		.provider_ports
+		.get(&classify(std::slice::from_ref(host)))
+		.copied()
+		.unwrap_or(port)
+}
+
+/// The timeout of a batch of `len` emails: `input.smtp_timeout` for each
+/// email. None if there's no timeout, or if it overflows.
+fn batch_timeout(len: usize, input: &CheckEmailInput) -> Option<Duration> {
+	let smtp_timeout = input.smtp_timeout?;
+
+	u32::try_from(len)
+		.ok()
+		.and_then(|len| smtp_timeout.checked_mul(len))
+}
+
+/// Get all email details we can from a list of `EmailAddress`es on the same
+/// domain, reusing a single SMTP connection for all of them. The results are
+/// in the same order as `to_emails`.
+///
+/// The catch-all probe is only performed once, and `input.smtp_timeout`
+/// applies to each email. If the shared connection fails, the remaining
+/// emails are checked one by one, with retries, using [`check_smtp`].
+///
+/// If the server advertises PIPELINING (RFC 2920), the `RCPT TO` commands
+/// are sent in a single write, in the same transaction, and the replies are
+/// read afterwards. This needs a plaintext conversation, i.e.
+/// `input.smtp_security` set to `None`, and neither `input.use_vrfy` nor
+/// `input.use_expn`. Otherwise, the `RCPT TO` commands are sent one by one,
+/// resetting the transaction in between.
+pub async fn check_smtp_batch(
+	to_emails: &[EmailAddress],
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> Vec<Result<SmtpDetails, SmtpError>> {
+	// Internationalized domains are used in punycode, e.g. in the catch-all
+	// probe.
+	let domain = &match idna::domain_to_ascii(domain) {
+		Ok(domain) => domain,
+		Err(err) => {
+			return to_emails
+				.iter()
+				.map(|_| Err(SmtpError::InvalidDomain(err.to_string())))
+				.collect()
+		}
+	};
+	let mut results = Vec::with_capacity(to_emails.len());
+	let input = &*with_valid_from_email(input, domain);
+
+	// Providers with a dedicated path are checked one by one.
+	let has_dedicated_path = match classify(std::slice::from_ref(host)) {
+		Provider::Yahoo => input.yahoo_verif_method != YahooVerifMethod::GenericSmtp,
+		Provider::Google => input.gmail_use_api,
+		Provider::Microsoft | Provider::ProtonMail => true,
+		Provider::Zoho | Provider::Other => false,
+	};
+	let matches_pattern =
+		provider::find_pattern(&input.catch_all_mx_patterns, &host.to_string()).is_some();
+	if to_emails.len() > 1 && !has_dedicated_path && !matches_pattern {
+		let _permits = acquire_permits(host, input).await;
+		let fut = create_smtp_batch_future(to_emails, host, port, domain, input, &mut results);
+		let result = if let Some(timeout) = batch_timeout(to_emails.len(), input) {
+			future::timeout(timeout, fut)
+				.await
+				.unwrap_or_else(|err| Err(err.into()))
+		} else {
+			fut.await
+		};
+
+		if let Err(err) = result {
+			log::debug!(
+				target: LOG_TARGET,
+				"Batch check on {}:{} failed after {} emails, error={:?}",
+				host,
+				port,
+				results.len(),
+				err
+			);
+		}
+	}
+
+	for to_email in &to_emails[results.len()..] {
+		results.push(check_smtp(to_email, host, port, domain, input).await);
+	}
+
+	results
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(feature = "blocking")]
+	use super::check_smtp_blocking;
+	use super::mock::{MockServer, MockSession};
+	use super::{
+		batch_timeout, check_smtp, check_smtp_batch, check_smtp_mx, classify_rcpt_error,
+		deliverability_from_result, has_extension, is_retryable, is_unreachable,
+		parse_max_message_size, parse_smtp_code, parse_smtp_enhanced_code, provider_port,
+		random_local_part, rcpt_address, retry_delay, with_jitter, with_valid_from_email,
+		CheckEmailInput, LocalPartError, Provider, SmtpDetails, SmtpError, SmtpEvent,
+		SmtpEventHook, SmtpPool, VerifReason,
+	};
+	use crate::util::cancellation::CancellationToken;
+	use crate::util::input_output::{
+		CheckEmailInputProxy, ProxyType, Reachable, SmtpAuth, SmtpAuthMechanism, SmtpEndPolicy,
+		SmtpSecurity,
+	};
+	use async_smtp::{
+		smtp::{error::Error as AsyncSmtpError, response::Response},
+		EmailAddress,
+	};
+	use rand::{rngs::SmallRng, SeedableRng};
+	use std::net::IpAddr;
+	use std::sync::{Arc, Mutex};
+	use std::{str::FromStr, thread, time::Duration};
+	use tokio::runtime::Runtime;
+	use trust_dns_proto::rr::{rdata::MX, Name};
+
+	/// Check foo@example.org on the server listening on 127.0.0.1:`port`,
+	/// e.g. a [`MockServer`].
+	fn check_mock(port: u16, input: &CheckEmailInput) -> Result<SmtpDetails, SmtpError> {
+		let runtime = Runtime::new().unwrap();
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+
+		runtime.block_on(check_smtp(&to_email, &host, port, "example.org", input))
+	}
+
+	#[test]
+	fn should_timeout() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email = EmailAddress::from_str("foo@gmail.com").unwrap();
+		let host = Name::from_str("gmail.com").unwrap();
+		let mut input = CheckEmailInput::default();
+		input.set_smtp_timeout(Duration::from_millis(1));
+
+		let res = runtime.block_on(check_smtp(&to_email, &host, 25, "gmail.com", &input));
+		match res {
+			Err(SmtpError::TimeoutError(_)) => (),
+			_ => panic!("check_smtp did not time out"),
+		}
+	}
+
+	#[test]
+	fn should_return_one_result_per_email() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_emails = vec![
+			EmailAddress::from_str("foo@example.org").unwrap(),
+			EmailAddress::from_str("bar@example.org").unwrap(),
+		];
+		// Nothing listens on this port, so we cannot connect.
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let input = CheckEmailInput::default();
+
+		let res = runtime.block_on(check_smtp_batch(
+			&to_emails,
+			&host,
+			1,
+			"example.org",
+			&input,
+		));
+		assert_eq!(res.len(), 2);
+		assert!(res
+			.iter()
+			.all(|r| matches!(r, Ok(details) if !details.can_connect_smtp)));
+	}
+
+	#[test]
+	fn should_pipeline_rcpt_commands() {
+		let runtime = Runtime::new().unwrap();
+		let to_emails = vec![
+			EmailAddress::from_str("foo@example.org").unwrap(),
+			EmailAddress::from_str("bar@example.org").unwrap(),
+			EmailAddress::from_str("baz@example.org").unwrap(),
+		];
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false);
+		let rcpt_commands = |server: &MockServer| {
+			server
+				.writes()
+				.into_iter()
+				.map(|write| {
+					write
+						.into_iter()
+						.filter(|command| command.starts_with("RCPT TO:"))
+						.collect::<Vec<_>>()
+				})
+				.filter(|write| !write.is_empty())
+				.collect::<Vec<_>>()
+		};
+
+		let server = MockServer::start(vec![MockSession::new()
+			.on("EHLO", "250-localhost\r\n250 PIPELINING\r\n")
+			.on("RCPT TO:<bar@", "550 5.1.1 User unknown\r\n")]);
+		let res = runtime.block_on(check_smtp_batch(
+			&to_emails,
+			&host,
+			server.port,
+			"example.org",
+			&input,
+		));
+		let res = res.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+		assert!(res[0].is_deliverable);
+		assert!(!res[1].is_deliverable);
+		assert_eq!(res[1].smtp_code, Some(550));
+		assert!(res[2].is_deliverable);
+		assert_eq!(
+			rcpt_commands(&server),
+			vec![vec![
+				"RCPT TO:<foo@example.org>",
+				"RCPT TO:<bar@example.org>",
+				"RCPT TO:<baz@example.org>",
+			]]
+		);
+		assert_eq!(server.count("RSET"), 0);
+
+		// Without PIPELINING, the commands are sent one by one.
+		let server = MockServer::start(vec![MockSession::new()]);
+		let res = runtime.block_on(check_smtp_batch(
+			&to_emails,
+			&host,
+			server.port,
+			"example.org",
+			&input,
+		));
+		assert!(res
+			.iter()
+			.all(|r| matches!(r, Ok(details) if details.is_deliverable)));
+		assert_eq!(rcpt_commands(&server).len(), 3);
+	}
+
+	#[test]
+	fn should_parse_smtp_codes() {
+		let response = Response::from_str("550 5.1.1 <foo@bar.com>: User unknown\r\n").unwrap();
+		assert_eq!(parse_smtp_code(&response), Some(550));
+		assert_eq!(parse_smtp_enhanced_code(&response), Some("5.1.1".into()));
+
+		let response = Response::from_str("250 OK\r\n").unwrap();
+		assert_eq!(parse_smtp_code(&response), Some(250));
+		assert_eq!(parse_smtp_enhanced_code(&response), None);
+
+		let response = Response::from_str("451 4.7.1 Greylisted, try again later\r\n").unwrap();
+		assert_eq!(parse_smtp_code(&response), Some(451));
+		assert_eq!(parse_smtp_enhanced_code(&response), Some("4.7.1".into()));
+	}
+
+	#[test]
+	fn should_try_mx_hosts_in_priority_order() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		// Nothing listens on these hosts, so we fall back to the last one.
+		let mx_records = vec![
+			MX::new(20, Name::from_str("127.0.0.1").unwrap()),
+			MX::new(10, Name::from_str("127.0.0.2").unwrap()),
+		];
+		let input = CheckEmailInput::default();
+
+		let res = runtime.block_on(check_smtp_mx(
+			&to_email,
+			&mx_records,
+			1,
+			"example.org",
+			&input,
+		));
+		let details = res.unwrap();
+		assert!(!details.can_connect_smtp);
+		assert_eq!(details.mx_host, Some("127.0.0.1".into()));
+		assert_eq!(details.mx_priority, Some(20));
+		assert_eq!(details.mx_ip, None);
+	}
+
+	#[test]
+	fn should_not_try_next_mx_host_on_rejection() {
+		let runtime = Runtime::new().unwrap();
+		// An ambiguous rejection is an error, but still an answer. The
+		// backup host is the same server, so trying it would open a second
+		// session.
+		let server = MockServer::start(vec![
+			MockSession::new().on("RCPT TO:", "550 Go away\r\n"),
+			MockSession::new(),
+		]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let mx_records = vec![
+			MX::new(10, Name::from_str("127.0.0.1").unwrap()),
+			MX::new(20, Name::from_str("127.0.0.1").unwrap()),
+		];
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false);
+
+		let res = runtime.block_on(check_smtp_mx(
+			&to_email,
+			&mx_records,
+			server.port,
+			"example.org",
+			&input,
+		));
+		assert!(matches!(
+			res,
+			Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(_)))
+		));
+		assert_eq!(server.count("MAIL FROM:"), 1);
+	}
+
+	#[test]
+	fn should_use_provider_ports() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let mx_records = vec![MX::new(10, Name::from_str("127.0.0.1").unwrap())];
+		let mut input = CheckEmailInput::default();
+		input
+			.set_check_catch_all(false)
+			.set_provider_port(Provider::Google, 587)
+			.set_provider_port(Provider::Other, server.port);
+
+		// Nothing listens on port 1, so the check only succeeds on the
+		// provider's port.
+		let details = runtime
+			.block_on(check_smtp_mx(
+				&to_email,
+				&mx_records,
+				1,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(details.mx_priority, Some(10));
+		assert_eq!(details.mx_ip, Some(IpAddr::from([127, 0, 0, 1])));
+		assert_eq!(
+			provider_port(&Name::from_str("aspmx.l.google.com").unwrap(), 25, &input),
+			587
+		);
+		input.provider_ports.clear();
+		assert_eq!(
+			provider_port(&Name::from_str("aspmx.l.google.com").unwrap(), 25, &input),
+			25
+		);
+	}
+
+	#[test]
+	fn should_cap_mx_attempts() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let mx_records = vec![
+			MX::new(20, Name::from_str("127.0.0.1").unwrap()),
+			MX::new(10, Name::from_str("127.0.0.2").unwrap()),
+		];
+		let mut input = CheckEmailInput::default();
+		input.set_max_mx_attempts(1);
+
+		let details = runtime
+			.block_on(check_smtp_mx(
+				&to_email,
+				&mx_records,
+				1,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		// Only the host with the highest priority was tried.
+		assert!(!details.can_connect_smtp);
+		assert_eq!(details.mx_host, Some("127.0.0.2".into()));
+	}
+
+	#[test]
+	fn should_report_refused_connections_as_unknown() {
+		let mut input = CheckEmailInput::default();
+		input.set_retries(1);
+
+		// Nothing listens on port 1.
+		let details = check_mock(1, &input).unwrap();
+		assert!(!details.can_connect_smtp);
+		assert_eq!(details.reachable(), Reachable::Unknown);
+	}
+
+	#[test]
+	fn should_retry_refused_connections() {
+		let mut input = CheckEmailInput::default();
+		input.set_retries(3);
+
+		// Nothing listens on port 1.
+		let details = check_mock(1, &input).unwrap();
+		assert!(!details.can_connect_smtp);
+		assert_eq!(details.attempts, 3);
+		assert!(details.retries_exhausted);
+	}
+
+	#[test]
+	fn should_not_exhaust_retries_on_first_success() {
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false).set_retries(1);
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(details.attempts, 1);
+		assert!(!details.retries_exhausted);
+	}
+
+	#[test]
+	fn should_quick_reject_domains_without_mx() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let input = CheckEmailInput::default();
+
+		let details = runtime
+			.block_on(check_smtp_mx(&to_email, &[], 25, "example.org", &input))
+			.unwrap();
+		assert!(!details.can_connect_smtp);
+		assert_eq!(details.mx_host, None);
+		assert_eq!(details.reason, Some(VerifReason::DomainNotFound));
+		assert_eq!(details.reachable(), Reachable::Invalid);
+	}
+
+	#[test]
+	fn should_not_connect_to_null_mx() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let mx_records = vec![MX::new(0, Name::root())];
+		let input = CheckEmailInput::default();
+
+		let details = runtime
+			.block_on(check_smtp_mx(
+				&to_email,
+				&mx_records,
+				25,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.has_null_mx);
+		assert_eq!(details.mx_host, None);
+		assert_eq!(details.reachable(), Reachable::Invalid);
+	}
+
+	#[test]
+	fn should_fail_if_starttls_is_required_but_unavailable() {
+		// The mock server doesn't advertise STARTTLS.
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_smtp_security(SmtpSecurity::Required);
+
+		let res = check_mock(server.port, &input);
+		assert!(matches!(res, Err(SmtpError::StartTlsUnavailable)));
+	}
+
+	#[test]
+	fn should_detect_catch_all_with_mock_server() {
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let input = CheckEmailInput::default();
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_catch_all);
+		assert!(details.is_deliverable);
+		assert_eq!(details.smtp_message, None);
+		// Only the catch-all probe was sent.
+		assert_eq!(server.count("RCPT TO:"), 1);
+		assert_eq!(server.count("RCPT TO:<foo@example.org>"), 0);
+	}
+
+	#[test]
+	fn should_check_email_on_catch_all_in_strict_mode() {
+		let runtime = Runtime::new().unwrap();
+		let rejecting = MockSession::new().on("RCPT TO:<foo@", "550 5.1.1 User unknown\r\n");
+		let server = MockServer::start(vec![rejecting, MockSession::new()]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input.set_strict_catch_all(true);
+
+		let details = runtime
+			.block_on(check_smtp(
+				&to_email,
+				&host,
+				server.port,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.is_catch_all);
+		assert!(!details.is_deliverable);
+		// The target's own reply is reported along with the catch-all status.
+		assert_eq!(details.smtp_code, Some(550));
+		assert_eq!(details.smtp_message.as_deref(), Some("5.1.1 User unknown"));
+		assert_eq!(details.reachable(), Reachable::Invalid);
+		assert_eq!(server.count("RCPT TO:<foo@example.org>"), 1);
+		assert_eq!(server.count("RSET"), 1);
+
+		let details = runtime
+			.block_on(check_smtp(
+				&to_email,
+				&host,
+				server.port,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.is_catch_all);
+		assert!(details.is_deliverable);
+		assert_eq!(details.reachable(), Reachable::Risky);
+		assert_eq!(server.count("RCPT TO:<foo@example.org>"), 2);
+	}
+
+	#[test]
+	fn should_retry_greylisted_catch_all_probe() {
+		let session =
+			MockSession::new().once("RCPT TO:", "451 4.7.1 Greylisted, try again later\r\n");
+		let server = MockServer::start(vec![session]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_retries(2);
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_catch_all);
+		assert_eq!(details.catch_all_check_error, None);
+		// The same probe was sent twice, on the same connection.
+		let probes = server
+			.commands()
+			.into_iter()
+			.filter(|command| command.starts_with("RCPT TO:"))
+			.collect::<Vec<_>>();
+		assert_eq!(probes.len(), 2);
+		assert_eq!(probes[0], probes[1]);
+		assert_eq!(server.count("RSET"), 1);
+	}
+
+	#[test]
+	fn should_authenticate_before_mail_from() {
+		let runtime = Runtime::new().unwrap();
+		let ehlo = "250-localhost\r\n250 AUTH PLAIN LOGIN\r\n";
+		// "VXNlcm5hbWU6" is "Username:", "dXNlcg==" is "user", and so on.
+		let accepting = MockSession::new()
+			.on("EHLO", ehlo)
+			.on("AUTH LOGIN", "334 VXNlcm5hbWU6\r\n")
+			.on("dXNlcg==", "334 UGFzc3dvcmQ6\r\n")
+			.on("c2VjcmV0", "235 2.7.0 Authentication successful\r\n");
+		let rejecting = MockSession::new().on("EHLO", ehlo).on(
+			"AUTH PLAIN",
+			"535 5.7.8 Authentication credentials invalid\r\n",
+		);
+		let server = MockServer::start(vec![accepting, rejecting]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input
+			.set_check_catch_all(false)
+			.set_collect_transcript(true)
+			.set_smtp_auth(SmtpAuth {
+				username: "user".into(),
+				password: "secret".into(),
+				mechanism: SmtpAuthMechanism::Login,
+			});
+
+		let details = runtime
+			.block_on(check_smtp(
+				&to_email,
+				&host,
+				server.port,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.is_deliverable);
+		assert!(details
+			.transcript
+			.contains(&"> AUTH LOGIN <redacted>".to_string()));
+		assert!(details
+			.transcript
+			.iter()
+			.all(|line| !line.contains("c2VjcmV0")));
+
+		input.set_smtp_auth(SmtpAuth {
+			username: "user".into(),
+			password: "wrong".into(),
+			mechanism: SmtpAuthMechanism::Plain,
+		});
+		let res = runtime.block_on(check_smtp(
+			&to_email,
+			&host,
+			server.port,
+			"example.org",
+			&input,
+		));
+		match res {
+			Err(err @ SmtpError::AuthenticationFailed(_)) => {
+				assert_eq!(err.kind(), "authentication");
+				assert_eq!(err.sub_kind(), Some("credentials_rejected"));
+			}
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		// The mail transaction only started after authenticating.
+		assert_eq!(server.count("MAIL FROM"), 1);
+		assert_eq!(server.count("AUTH"), 2);
+	}
+
+	#[test]
+	fn should_prefer_user_unknown_among_from_emails() {
+		let accepting = MockSession::new()
+			.on("RCPT TO:<foo@", "250 2.1.5 Ok\r\n")
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n");
+		let rejecting = MockSession::new().on("RCPT TO:<foo@", "550 5.1.1 User unknown\r\n");
+		let server = MockServer::start(vec![accepting, rejecting]);
+
+		let mut input = CheckEmailInput::default();
+		input.add_alternative_from_email("other@example.net".into());
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(!details.is_deliverable);
+		assert!(details.has_from_email_mismatch);
+		assert_eq!(details.from_email.as_deref(), Some("other@example.net"));
+		assert_eq!(details.smtp_enhanced_code.as_deref(), Some("5.1.1"));
+		assert_eq!(server.count("MAIL FROM:<other@example.net>"), 1);
+		// No catch-all probe with the alternative from email.
+		assert_eq!(server.count("RCPT TO:"), 3);
+	}
+
+	#[cfg(feature = "blocking")]
+	#[test]
+	fn should_check_smtp_blocking() {
+		let server = MockServer::start(vec![MockSession::new(), MockSession::new()]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let input = CheckEmailInput::default();
+
+		// The second check reuses the runtime of the first one.
+		for _ in 0..2 {
+			let details =
+				check_smtp_blocking(&to_email, &host, server.port, "example.org", &input).unwrap();
+			assert!(details.is_catch_all);
+		}
+	}
+
+	#[test]
+	fn should_report_anti_spam_blocks() {
+		let session = MockSession::new()
+			.on("RCPT TO:<foo@", "550 5.7.1 Message refused\r\n")
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n");
+		let server = MockServer::start(vec![session]);
+
+		let input = CheckEmailInput::default();
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_blocked);
+		assert!(!details.is_deliverable);
+		assert_eq!(details.reachable(), Reachable::Unknown);
+		assert_eq!(details.reason, Some(VerifReason::Blocked));
+		// Blocks aren't retried.
+		assert_eq!(server.count("RCPT TO:<foo@example.org>"), 1);
+	}
+
+	#[test]
+	fn should_report_rate_limited_recipients() {
+		let session = MockSession::new()
+			.on(
+				"RCPT TO:<foo@",
+				"450 4.2.1 The user you are trying to contact is receiving mail at a rate that prevents additional messages from being delivered.\r\n",
+			)
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n");
+		let server = MockServer::start(vec![session]);
+
+		let input = CheckEmailInput::default();
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_rate_limited);
+		assert!(details.is_deliverable);
+		assert_eq!(details.reachable(), Reachable::Risky);
+		assert_eq!(details.reason, Some(VerifReason::RateLimited));
+	}
+
+	#[test]
+	fn should_report_catch_all_probe_error() {
+		let session = MockSession::new()
+			.on("RCPT TO:<foo@", "250 2.1.5 Ok\r\n")
+			.on("RCPT TO:", "554 5.3.0 Probe refused\r\n");
+		let server = MockServer::start(vec![session]);
+
+		let input = CheckEmailInput::default();
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(!details.is_catch_all);
+		assert!(details.is_deliverable);
+		assert!(details
+			.catch_all_check_error
+			.unwrap()
+			.contains("Probe refused"));
+	}
+
+	#[test]
+	fn should_send_seeded_catch_all_probes() {
+		let server = MockServer::start(vec![MockSession::new(), MockSession::new()]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_catch_all_rng_seed(42).set_catch_all_probes(2);
+
+		for _ in 0..2 {
+			check_mock(server.port, &input).unwrap();
+		}
+
+		let mut rng = SmallRng::seed_from_u64(42);
+		let probes = [random_local_part(&mut rng), random_local_part(&mut rng)]
+			.iter()
+			.map(|local_part| format!("RCPT TO:<{}@example.org>", local_part))
+			.collect::<Vec<_>>();
+		let rcpt_commands = server
+			.commands()
+			.into_iter()
+			.filter(|command| command.starts_with("RCPT TO:"))
+			.collect::<Vec<_>>();
+		// Both checks sent the same probes.
+		assert_eq!(rcpt_commands, [probes.clone(), probes].concat());
+	}
+
+	#[test]
+	fn should_return_partial_details_on_timeout() {
+		let server = MockServer::start(vec![MockSession::new()
+			.on("RCPT TO:<foo@", MockSession::HANG)
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n")]);
+
+		let mut input = CheckEmailInput::default();
+		input
+			.set_smtp_timeout(Duration::from_secs(1))
+			.set_retries(1);
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_timed_out);
+		assert!(details.can_connect_smtp);
+		assert!(!details.is_catch_all);
+		assert!(!details.is_deliverable);
+		assert_eq!(details.reachable(), Reachable::Unknown);
+	}
+
+	#[test]
+	fn should_send_ip_literal_in_ehlo() {
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_hello_ip(IpAddr::from([203, 0, 113, 5]));
+
+		check_mock(server.port, &input).unwrap();
+		assert_eq!(server.count("EHLO [203.0.113.5]"), 1);
+
+		// Both async-smtp's EHLO and ours.
+		let server = MockServer::start(vec![MockSession::new()]);
+		input.set_report_smtp_extensions(true);
+		check_mock(server.port, &input).unwrap();
+		assert_eq!(server.count("EHLO [203.0.113.5]"), 2);
+	}
+
+	#[test]
+	fn should_close_the_connection_when_cancelled() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![
+			MockSession::new().on("RCPT TO:<foo@", MockSession::HANG)
+		]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let token = CancellationToken::new();
+		let mut input = CheckEmailInput::default();
+		input
+			.set_check_catch_all(false)
+			.set_cancellation_token(token.clone());
+
+		let cancelling = token.clone();
+		thread::spawn(move || {
+			thread::sleep(Duration::from_millis(200));
+			cancelling.cancel();
+		});
+		let result = runtime.block_on(check_smtp(
+			&to_email,
+			&host,
+			server.port,
+			"example.org",
+			&input,
+		));
+		assert!(matches!(result, Err(SmtpError::Cancelled)));
+		assert_eq!(server.count("QUIT"), 1);
+
+		// Cancelled checks don't connect anymore.
+		let result = runtime.block_on(check_smtp(
+			&to_email,
+			&host,
+			server.port,
+			"example.org",
+			&input,
+		));
+		assert!(matches!(result, Err(SmtpError::Cancelled)));
+	}
+
+	#[test]
+	fn should_serialize_error_kinds() {
+		let rejected = SmtpError::SmtpError(AsyncSmtpError::Permanent(
+			Response::from_str("550 5.1.1 User unknown\r\n").unwrap(),
+		));
+		let json = serde_json::to_value(&rejected).unwrap();
+		assert_eq!(json["type"], "SmtpError");
+		assert_eq!(json["message"], "permanent: 5.1.1 User unknown");
+		assert_eq!(json["kind"], "smtp");
+		assert_eq!(json["sub_kind"], "recipient_rejected");
+
+		let refused = SmtpError::SmtpError(AsyncSmtpError::Io(std::io::Error::from(
+			std::io::ErrorKind::ConnectionRefused,
+		)));
+		assert_eq!(refused.sub_kind(), Some("connection_refused"));
+
+		let json = serde_json::to_value(SmtpError::Port25Blocked(587)).unwrap();
+		assert_eq!(
+			json,
+			serde_json::json!({
+				"type": "Port25Blocked",
+				"message": 587,
+				"kind": "port_25_blocked",
+				"sub_kind": null,
+			})
+		);
+		let json = serde_json::to_value(SmtpError::Cancelled).unwrap();
+		assert_eq!(json["type"], "Cancelled");
+		assert_eq!(json["kind"], "cancelled");
+	}
+
+	#[test]
+	fn should_reconnect_after_io_incomplete() {
+		// The first server closes the connection instead of answering.
+		let server = MockServer::start(vec![
+			MockSession::new().on("RCPT TO:<foo@", ""),
+			MockSession::new().on("RCPT TO:<foo@", "550 5.1.1 User unknown\r\n"),
+		]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false).set_retries(0);
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(!details.is_deliverable);
+		assert_eq!(details.smtp_code, Some(550));
+		assert_eq!(details.attempts, 1);
+		assert_eq!(server.count("RCPT TO:<foo@example.org>"), 2);
+		assert_eq!(server.count("MAIL FROM:"), 2);
+	}
+
+	#[test]
+	fn should_retry_when_greylisted() {
+		let server = MockServer::start(vec![
+			MockSession::new().on("RCPT TO:", "451 4.7.1 Greylisted, try again later\r\n"),
+			MockSession::new().on("RCPT TO:", "451 4.7.1 Greylisted, try again later\r\n"),
+			MockSession::new(),
+		]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false).set_retries(3);
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_deliverable);
+		assert!(details.is_greylisted);
+		assert_eq!(details.attempts, 3);
+		assert_eq!(server.count("RCPT TO:"), 3);
+	}
+
+	#[test]
+	fn should_defer_and_resume_greylisted_checks() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![
+			MockSession::new().on(
+				"RCPT TO:",
+				"451 4.7.1 Greylisted, please try again in 2 minutes\r\n",
+			),
+			MockSession::new(),
+		]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input
+			.set_check_catch_all(false)
+			.set_from_email("me@example.net".into())
+			.set_defer_greylisting(true);
+
+		let err = runtime
+			.block_on(check_smtp(
+				&to_email,
+				&host,
+				server.port,
+				"example.org",
+				&input,
+			))
+			.unwrap_err();
+		// Deferred without retrying.
+		assert_eq!(server.count("RCPT TO:"), 1);
+		assert!(err.is_greylisted());
+		assert_eq!(err.reason(), Some(VerifReason::Greylisted));
+		let token = match err {
+			SmtpError::Greylisted(token) => token,
+			err => panic!("unexpected error {:?}", err),
+		};
+		assert_eq!(token.host, "127.0.0.1");
+		assert_eq!(token.port, server.port);
+		assert_eq!(token.from_email, "me@example.net");
+		assert!(token.remaining() > Duration::from_secs(60));
+
+		// The token survives a round trip through JSON.
+		let json = serde_json::to_value(SmtpError::Greylisted(token.clone())).unwrap();
+		assert_eq!(json["sub_kind"], "greylisted");
+		let err: SmtpError = serde_json::from_value(json).unwrap();
+		assert!(matches!(err, SmtpError::Greylisted(ref t) if *t == token));
+
+		// The resumed check goes to the greylisting host, not the MX records.
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false).set_greylist_token(token);
+		let mx_records = vec![MX::new(10, Name::from_str("127.0.0.2").unwrap())];
+		let details = runtime
+			.block_on(check_smtp_mx(
+				&to_email,
+				&mx_records,
+				1,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(details.mx_host, Some("127.0.0.1".into()));
+		assert_eq!(server.count("MAIL FROM:<me@example.net>"), 2);
+	}
+
+	#[test]
+	fn should_try_the_next_proxy_on_failure() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![
+			MockSession::new().http_proxy("HTTP/1.1 200 Connection established\r\n\r\n")
+		]);
+		let proxy = |port| CheckEmailInputProxy {
+			host: "127.0.0.1".into(),
+			port,
+			proxy_type: ProxyType::HttpConnect,
+			..Default::default()
+		};
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input
+			// Nothing listens on this port.
+			.set_proxy(proxy(1))
+			.add_proxy(proxy(server.port))
+			.set_check_catch_all(false);
+
+		let res = runtime.block_on(check_smtp(&to_email, &host, 25, "example.org", &input));
+		let details = res.unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(
+			details.used_proxy,
+			Some(format!("127.0.0.1:{}", server.port))
+		);
+		assert_eq!(server.count("CONNECT 127.0.0.1:25 HTTP/1.1"), 1);
+	}
+
+	#[test]
+	fn should_time_out_silent_socks5_proxy() {
+		let runtime = Runtime::new().unwrap();
+		// Accepts the connection, but never answers the handshake.
+		let server = MockServer::start(vec![MockSession::new().greeting("")]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input
+			.set_proxy(CheckEmailInputProxy {
+				host: "127.0.0.1".into(),
+				port: server.port,
+				..Default::default()
+			})
+			.set_connect_timeout(Duration::from_millis(200))
+			.set_retries(0)
+			.set_check_catch_all(false);
+
+		let res = runtime
+			.block_on(async_std::future::timeout(
+				Duration::from_secs(5),
+				check_smtp(&to_email, &host, 25, "example.org", &input),
+			))
+			.expect("the handshake is bounded by the connect timeout, qed.");
+		assert!(is_unreachable(&res));
+	}
+
+	#[test]
+	fn should_only_probe_submission_ports_for_port_25() {
+		let mut input = CheckEmailInput::default();
+		input.set_submission_port_fallback(true).set_retries(0);
+
+		// Nothing listens on this port, so we cannot connect.
+		let res = check_mock(1, &input);
+		assert!(is_unreachable(&res));
+		assert!(!res.unwrap().can_connect_smtp);
+	}
+
+	#[test]
+	fn should_report_events_to_hook() {
+		let server = MockServer::start(vec![
+			MockSession::new().on("RCPT TO:", "550 5.1.1 User unknown\r\n")
+		]);
+		let events = Arc::new(Mutex::new(vec![]));
+		let hook_events = events.clone();
+		let hook = SmtpEventHook::new(move |event| {
+			let event = match event {
+				SmtpEvent::Connect { error, .. } => format!("connect {}", error.is_none()),
+				SmtpEvent::RcptTo {
+					smtp_code,
+					is_accepted,
+					..
+				} => format!("rcpt {:?} {}", smtp_code, is_accepted),
+				SmtpEvent::Attempt {
+					attempt, result, ..
+				} => format!("attempt {} {}", attempt, result.is_ok()),
+				SmtpEvent::Retry { attempt, .. } => format!("retry {}", attempt),
+			};
+			hook_events.lock().unwrap().push(event);
+		});
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false).set_smtp_event_hook(hook);
+
+		let res = check_mock(server.port, &input);
+		let details = res.unwrap();
+		assert!(!details.is_deliverable);
+		assert_eq!(details.attempts, 1);
+		assert!(!details.retries_exhausted);
+		assert_eq!(
+			*events.lock().unwrap(),
+			vec!["connect true", "rcpt Some(550) false", "attempt 1 true"]
+		);
+	}
+
+	#[test]
+	fn should_detect_sender_rejections() {
+		let server = MockServer::start(vec![
+			MockSession::new().on(
+				"RCPT TO:",
+				"550 5.7.1 Callout verification failed: 550 5.1.1 User unknown\r\n",
+			),
+			MockSession::new().on(
+				"MAIL FROM:",
+				"553 5.1.8 Domain of sender address does not exist\r\n",
+			),
+		]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false).set_retries(1);
+
+		for _ in 0..2 {
+			let err = check_mock(server.port, &input).unwrap_err();
+			// Not a rejection of the recipient.
+			assert!(matches!(err, SmtpError::SenderRejected(_)));
+			assert_eq!(err.kind(), "sender_rejected");
+			assert_eq!(err.reason(), Some(VerifReason::SenderRejected));
+		}
+	}
+
+	#[test]
+	fn should_detect_blocked_ip() {
+		let server = MockServer::start(vec![MockSession::new().on(
+			"RCPT TO:",
+			"550 5.7.1 Mailbox unavailable, your IP is blacklisted\r\n",
+		)]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false);
+
+		let res = check_mock(server.port, &input);
+		assert!(matches!(res, Err(SmtpError::IpBlocked(_))));
+	}
+
+	#[test]
+	fn should_expose_ehlo_extensions() {
+		let runtime = Runtime::new().unwrap();
+		let ehlo = "250-localhost\r\n250-PIPELINING\r\n250-SIZE 1000\r\n250 SMTPUTF8\r\n";
+		let server = MockServer::start(vec![MockSession::new()
+			.on("EHLO", ehlo)
+			.on("RCPT TO:<foo@", "250 2.1.5 Ok\r\n")
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n")]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input.set_report_smtp_extensions(true);
+
+		let res = runtime.block_on(check_smtp(
+			&to_email,
+			&host,
+			server.port,
+			"example.org",
+			&input,
+		));
+		let details = res.unwrap();
+		assert!(details.is_deliverable);
+		assert!(!details.is_catch_all);
+		assert_eq!(
+			details.supported_extensions,
+			vec!["PIPELINING", "SIZE 1000", "SMTPUTF8"]
+		);
+		assert_eq!(details.max_message_size, Some(1000));
+		assert_eq!(details.from_email, Some("user@example.org".into()));
+		assert_eq!(details.used_proxy, None);
+		assert_eq!(details.source_ip, Some(IpAddr::from([127, 0, 0, 1])));
+		assert_eq!(details.mx_ip, Some(IpAddr::from([127, 0, 0, 1])));
+		assert_eq!(details.mx_priority, None);
+	}
+
+	#[test]
+	fn should_connect_to_ip_override() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		// This host can't be resolved.
+		let host = Name::from_str("mx.example.invalid").unwrap();
+		let mut input = CheckEmailInput::default();
+		input
+			.set_check_catch_all(false)
+			.set_smtp_ip_override(IpAddr::from([127, 0, 0, 1]));
+
+		let details = runtime
+			.block_on(check_smtp(
+				&to_email,
+				&host,
+				server.port,
+				"example.org",
+				&input,
+			))
+			.unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(details.mx_host, Some("mx.example.invalid".into()));
+		assert_eq!(details.mx_ip, Some(IpAddr::from([127, 0, 0, 1])));
+	}
+
+	#[test]
+	fn should_follow_the_end_policy() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![MockSession::new(), MockSession::new()]);
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input
+			.set_check_catch_all(false)
+			.set_smtp_pool(SmtpPool::new(
+				Duration::from_secs(60),
+				Duration::from_secs(60),
+			))
+			.set_smtp_end_policy(SmtpEndPolicy::Quit);
+
+		let check = |input: &CheckEmailInput| {
+			runtime
+				.block_on(check_smtp(
+					&to_email,
+					&host,
+					server.port,
+					"example.org",
+					input,
+				))
+				.unwrap()
+		};
+
+		// The connection is closed, even with a pool.
+		assert!(check(&input).is_deliverable);
+		assert_eq!(server.count("QUIT"), 1);
+		assert_eq!(server.count("RSET"), 0);
+
+		// The connection is reset, and kept for the next check: the server
+		// only accepts two connections.
+		input.set_smtp_end_policy(SmtpEndPolicy::Reset);
+		assert!(check(&input).is_deliverable);
+		assert!(check(&input).is_deliverable);
+		assert_eq!(server.count("QUIT"), 1);
+		assert_eq!(server.count("RSET"), 2);
+	}
+
+	#[test]
+	fn should_ignore_close_errors() {
+		let session = MockSession::new()
+			.on("RCPT TO:<foo@", "250 2.1.5 Ok\r\n")
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n")
+			.on("QUIT", "554 5.3.0 Error\r\n");
+		let server = MockServer::start(vec![session]);
+
+		let input = CheckEmailInput::default();
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(server.count("QUIT"), 1);
+	}
+
+	#[test]
+	fn should_detect_mta_software() {
+		let session = MockSession::new()
+			.greeting("220-mx.example.org ESMTP Exim 4.94.2\r\n220 No spam\r\n")
+			.on("RCPT TO:", "550 5.1.1 User unknown\r\n");
+		let server = MockServer::start(vec![session]);
+
+		let input = CheckEmailInput::default();
+
+		let details = check_mock(server.port, &input).unwrap();
+		assert_eq!(details.mta_software, Some("Exim 4.94.2".into()));
+	}
+
+	#[test]
+	fn should_use_null_sender() {
+		let server = MockServer::start(vec![MockSession::new()]);
+
+		let mut input = CheckEmailInput::default();
+		input
+			.set_use_null_sender(true)
+			.set_check_catch_all(false)
+			.set_collect_transcript(true);
+
+		let res = check_mock(server.port, &input);
+		let details = res.unwrap();
+		assert!(details.is_deliverable);
+		assert_eq!(details.from_email, Some("".into()));
+		assert!(details
+			.transcript
+			.iter()
+			.any(|line| line == "> MAIL FROM:<>"));
+	}
+
+	#[test]
+	fn should_parse_max_message_size() {
+		let parse = |extensions: &[&str]| {
+			parse_max_message_size(&extensions.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+		};
+
+		assert_eq!(parse(&["PIPELINING", "SIZE 35882577"]), Some(35882577));
+		assert_eq!(parse(&["size 1000"]), Some(1000));
+		assert_eq!(parse(&["SIZE"]), None);
+		assert_eq!(parse(&["SIZE 0"]), None);
+		assert_eq!(parse(&["PIPELINING"]), None);
+	}
+
+	#[test]
+	fn should_fall_back_to_a_valid_from_email() {
+		let mut input = CheckEmailInput::default();
+		let from_email = |input: &CheckEmailInput| {
+			with_valid_from_email(input, "example.org")
+				.from_email
+				.clone()
+		};
+
+		assert_eq!(from_email(&input), "user@example.org");
+		input.set_from_email("not an email".into());
+		assert_eq!(from_email(&input), "postmaster@example.org");
+		input.set_from_email_fallback("me@example.com".into());
+		assert_eq!(from_email(&input), "me@example.com");
+		input.set_from_email_fallback("still not an email".into());
+		assert_eq!(from_email(&input), "user@example.org");
+	}
+
+	#[test]
+	fn should_compute_retry_delay() {
+		let transient = |reply: &str| {
+			SmtpError::SmtpError(AsyncSmtpError::Transient(
+				Response::from_str(reply).unwrap(),
+			))
+		};
+		let mut input = CheckEmailInput::default();
+
+		let err = transient("451 4.7.1 Greylisted, please try again in 5 minutes\r\n");
+		assert_eq!(retry_delay(&err, 1, &input), None);
+
+		input.set_retry_delay(Duration::from_secs(60));
+		assert_eq!(retry_delay(&err, 1, &input), Some(Duration::from_secs(60)));
+		let err = transient("451 4.7.1 Try again in 30 seconds\r\n");
+		assert_eq!(retry_delay(&err, 1, &input), Some(Duration::from_secs(30)));
+		let err = transient("451 4.7.1 Try again later\r\n");
+		assert_eq!(retry_delay(&err, 1, &input), Some(Duration::from_secs(1)));
+		assert_eq!(retry_delay(&err, 3, &input), Some(Duration::from_secs(4)));
+		assert_eq!(retry_delay(&err, 10, &input), Some(Duration::from_secs(60)));
+
+		input.set_retry_base_delay(Duration::from_secs(5));
+		assert_eq!(retry_delay(&err, 1, &input), Some(Duration::from_secs(5)));
+		assert_eq!(retry_delay(&err, 2, &input), Some(Duration::from_secs(10)));
+		assert_eq!(retry_delay(&err, 5, &input), Some(Duration::from_secs(60)));
+	}
+
+	#[test]
+	fn should_add_jitter_to_retry_delay() {
+		let mut rng = SmallRng::seed_from_u64(42);
+		let delay = Duration::from_secs(10);
+
+		assert_eq!(with_jitter(delay, 0.0, &mut rng), delay);
+		let delays = (0..100)
+			.map(|_| with_jitter(delay, 0.2, &mut rng))
+			.collect::<Vec<_>>();
+		assert!(delays
+			.iter()
+			.all(|d| *d >= Duration::from_secs(8) && *d <= Duration::from_secs(12)));
+		assert!(delays.iter().any(|d| *d != delay));
+	}
+
+	#[test]
+	fn should_only_retry_retryable_codes() {
+		let transient = |reply: &str| {
+			SmtpError::SmtpError(AsyncSmtpError::Transient(
+				Response::from_str(reply).unwrap(),
+			))
+		};
+		let mut input = CheckEmailInput::default();
+
+		let busy = transient("450 4.2.1 Mailbox busy\r\n");
+		let greylisted = transient("451 4.7.1 Greylisted\r\n");
+		assert!(is_retryable(&busy, &input));
+		assert!(is_retryable(&greylisted, &input));
+
+		input.set_retryable_codes(vec![421, 451]);
+		assert!(!is_retryable(&busy, &input));
+		assert!(is_retryable(&greylisted, &input));
+		assert!(!is_retryable(&SmtpError::StartTlsUnavailable, &input));
+	}
+
+	#[test]
+	fn should_derive_reachable_from_details() {
+		let deliverable = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			..Default::default()
+		};
+		assert_eq!(deliverable.reachable(), Reachable::Safe);
+		assert_eq!(
+			SmtpDetails {
+				is_catch_all: true,
+				..deliverable
+			}
+			.reachable(),
+			Reachable::Risky
+		);
+		// We couldn't connect.
+		assert_eq!(SmtpDetails::default().reachable(), Reachable::Unknown);
+		assert_eq!(
+			SmtpDetails {
+				is_skipped: true,
+				..Default::default()
+			}
+			.reachable(),
+			Reachable::Unknown
+		);
+	}
+
+	#[test]
+	fn should_send_internationalized_addresses_with_smtputf8_only() {
+		let email = |address: &str| EmailAddress::from_str(address).unwrap();
+		let utf8_email = |address: &str| -> EmailAddress {
+			serde_json::from_value(serde_json::Value::String(address.into())).unwrap()
+		};
+
+		assert_eq!(
+			rcpt_address(&email("foo@example.org"), false),
+			Some(email("foo@example.org"))
+		);
+		assert_eq!(
+			rcpt_address(&utf8_email("用户@例子.广告"), true),
+			Some(utf8_email("用户@例子.广告"))
+		);
+		assert_eq!(
+			rcpt_address(&utf8_email("foo@münchen.de"), false),
+			Some(email("foo@xn--mnchen-3ya.de"))
+		);
+		assert_eq!(rcpt_address(&utf8_email("用户@例子.广告"), false), None);
+		assert!(has_extension(
+			&["SIZE 1000".into(), "smtputf8".into()],
+			"SMTPUTF8"
+		));
+	}
+
+	#[test]
+	fn should_reject_invalid_idn_domain() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email = EmailAddress::from_str("foo@example.org").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let input = CheckEmailInput::default();
+
+		let res = runtime.block_on(check_smtp(&to_email, &host, 1, "xn--a.de", &input));
+		assert!(matches!(res, Err(SmtpError::InvalidDomain(_))));
+	}
+
+	#[test]
+	fn should_reject_invalid_local_part_without_connecting() {
+		let runtime = Runtime::new().unwrap();
+
+		let to_email: EmailAddress =
+			serde_json::from_value(serde_json::Value::String("\"john doe\"@example.org".into()))
+				.unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+
+		let details = runtime
+			.block_on(check_smtp(&to_email, &host, 1, "example.org", &input))
+			.unwrap();
+		assert_eq!(details.invalid_local_part, Some(LocalPartError::Quoted));
+		assert!(!details.is_deliverable);
+		assert!(details.transcript.is_empty());
+
+		// Without strict mode, we try to connect to the closed port.
+		input.set_strict_local_part(false);
+		let details = runtime
+			.block_on(check_smtp(&to_email, &host, 1, "example.org", &input))
+			.unwrap();
+		assert_eq!(details.invalid_local_part, None);
+	}
+
+	#[test]
+	fn should_classify_real_world_rcpt_errors() {
+		// (is_deliverable, has_full_inbox, is_disabled, is_blocked,
+		// is_rate_limited), or None if the error is ambiguous.
+		type Expected = Option<(bool, bool, bool, bool, bool)>;
+
+		let input = CheckEmailInput::default();
+		let cases: &[(&str, Expected)] = &[
+			// Gmail
+			("550 5.1.1 The email account that you tried to reach does not exist. Please try double-checking the recipient's email address for typos or unnecessary spaces. Learn more at https://support.google.com/mail/?p=NoSuchUser", Some((false, false, false, false, false))),
+			("550 5.2.1 The email account that you tried to reach is disabled. Learn more at https://support.google.com/mail/?p=DisabledUser", Some((false, false, true, false, false))),
+			("452 4.2.2 The email account that you tried to reach is over quota. Please direct the recipient to https://support.google.com/mail/?p=OverQuotaTemp", Some((false, true, false, false, false))),
+			("450 4.2.1 The user you are trying to contact is receiving mail at a rate that prevents additional messages from being delivered.", Some((true, false, false, false, true))),
+			// Microsoft
+			("550 5.5.0 Requested action not taken: mailbox unavailable (S2017062302).", Some((false, false, false, false, false))),
+			("550 5.4.1 Recipient address rejected: Access denied. AS(201806281)", Some((false, false, false, false, false))),
+			// Postfix
+			("550 5.1.1 <foo@example.org>: Recipient address rejected: User unknown in local recipient table", Some((false, false, false, false, false))),
+			("550 5.1.1 <foo@example.org>: Recipient address rejected: User unknown in virtual mailbox table", Some((false, false, false, false, false))),
+			// Exim
+			("550 Unrouteable address", Some((false, false, false, false, false))),
+			("550 No Such User Here", Some((false, false, false, false, false))),
+			// Yahoo
+			("554 delivery error: dd This user doesn't have a yahoo.com account (foo@yahoo.com) [0] - mta1234.mail.gq1.yahoo.com", Some((false, false, false, false, false))),
+			("554 delivery error: dd Sorry your message to foo@yahoo.com cannot be delivered. This mailbox is disabled (554.30). - mta1234.mail.ne1.yahoo.com", Some((false, false, true, false, false))),
+			// Others
+			("552 5.2.2 Mailbox full: insufficient system storage", Some((false, true, false, false, false))),
+			("550 5.1.1 Mailbox not found", Some((false, false, false, false, false))),
+			("550 5.1.1 Sorry, no mailbox here by that name", Some((false, false, false, false, false))),
+			// Anti-spam blocks
+			("550 5.7.1 Service unavailable, Client host [1.2.3.4] blocked using Spamhaus", Some((false, false, false, true, false))),
+			("554 5.7.1 Message rejected due to policy", Some((false, false, false, true, false))),
+			("550 5.7.1 Access denied", Some((false, false, false, true, false))),
+			("421 4.7.0 Try again later, closing connection", None),
+			("451 4.7.1 Greylisting in action, please come back later", None),
+		];
+
+		for (err_string, expected) in cases {
+			let actual = classify_rcpt_error(err_string, &input).map(|deliverability| {
+				(
+					deliverability.is_deliverable,
+					deliverability.has_full_inbox,
+					deliverability.is_disabled,
+					deliverability.is_blocked,
+					deliverability.is_rate_limited,
+				)
+			});
+			assert_eq!(actual, *expected, "{}", err_string);
+		}
+	}
+
+	#[test]
+	fn should_classify_policy_codes_as_blocks() {
+		let input = CheckEmailInput::default();
+		let deliverability = |reply: &str| {
+			let response = Response::from_str(reply).unwrap();
+			deliverability_from_result(Err(AsyncSmtpError::Permanent(response)), &input).unwrap()
+		};
+
+		// The 5.7.x code wins over the "rejected" and "undeliverable"
+		// patterns, which would say that the mailbox doesn't exist.
+		for reply in [
+			"550 5.7.1 <foo@example.org>: Recipient address rejected: Access denied\r\n",
+			"554 5.7.1 Message undeliverable, rejected as spam\r\n",
+		] {
+			let deliverability = deliverability(reply);
+			assert!(deliverability.is_blocked, "{}", reply);
+			assert!(!deliverability.is_deliverable, "{}", reply);
+			assert!(!deliverability.is_disabled, "{}", reply);
+		}
+	}
+
+	#[test]
+	fn should_not_overflow_batch_timeout() {
+		let mut input = CheckEmailInput::default();
+		assert_eq!(batch_timeout(3, &input), None);
+
+		input.set_smtp_timeout(Duration::from_secs(10));
+		assert_eq!(batch_timeout(3, &input), Some(Duration::from_secs(30)));
+
+		input.set_smtp_timeout(Duration::MAX);
+		assert_eq!(batch_timeout(3, &input), None);
+	}
+
+	#[test]
+	fn should_report_policy_codes_as_blocks() {
+		let server = MockServer::start(vec![MockSession::new().on(
+			"RCPT TO:",
+			"550 5.7.1 <foo@example.org>: Recipient address rejected: Access denied\r\n",
+		)]);
+
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false);
+
+		// Not a sender rejection, nor an unknown user.
+		let details = check_mock(server.port, &input).unwrap();
+		assert!(details.is_blocked);
+		assert!(!details.is_disabled);
+		assert_eq!(details.reason, Some(VerifReason::Blocked));
+	}
+
+	#[test]
+	fn should_detect_greylisting() {
+		let greylisted = |reply: &str| {
+			let response = Response::from_str(reply).unwrap();
+			SmtpError::SmtpError(AsyncSmtpError::Transient(response)).is_greylisted()
+		};
+
+		assert!(greylisted("451 4.7.1 Greylisted, try again later\r\n"));
+		assert!(greylisted("421 4.7.0 Try later\r\n"));
+		assert!(!greylisted("452 4.2.2 Mailbox full\r\n"));
+		assert!(!greylisted("450 4.7.1 Greylisted\r\n"));
 	}
 }
@@ -0,0 +1,121 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// The lowercase substrings of greetings identifying an MTA, with the name
+/// we report. They're checked in order, so the first match wins.
+const MTA_PATTERNS: &[(&str, &str)] = &[
+	// 220 mx.example.org ESMTP Postfix (Debian/GNU)
+	("postfix", "Postfix"),
+	// 220 mx.example.org ESMTP Exim 4.94.2 Mon, 14 Mar 2022 10:00:00 +0000
+	("exim", "Exim"),
+	// 220 mx.example.org ESMTP Sendmail 8.15.2/8.15.2; Mon, 14 Mar 2022
+	("sendmail", "Sendmail"),
+	// 220 BN8NAM11FT012.mail.protection.outlook.com Microsoft ESMTP MAIL Service ready
+	("microsoft esmtp mail service", "Microsoft Exchange"),
+	// 220 mx.google.com ESMTP a1si123456qkb.1 - gsmtp
+	("gsmtp", "Google"),
+	("postmark", "Postmark"),
+	("haraka", "Haraka"),
+	("zimbra", "Zimbra"),
+	("opensmtpd", "OpenSMTPD"),
+	("qmail", "qmail"),
+	("mimecast", "Mimecast"),
+	("mdaemon", "MDaemon"),
+	("communigate", "CommuniGate Pro"),
+	("hmailserver", "hMailServer"),
+	("kerio", "Kerio Connect"),
+	("icewarp", "IceWarp"),
+];
+
+/// The version following an MTA's name in a greeting, e.g. "4.94.2" in
+/// "Exim 4.94.2 Mon, 14 Mar 2022".
+fn parse_version(rest: &str) -> Option<&str> {
+	let rest = rest.trim_start_matches([' ', '/']);
+	let end = rest
+		.find(|c: char| !c.is_ascii_digit() && c != '.')
+		.unwrap_or(rest.len());
+	let version = rest[..end].trim_end_matches('.');
+
+	if version.starts_with(|c: char| c.is_ascii_digit()) && version.contains('.') {
+		Some(version)
+	} else {
+		None
+	}
+}
+
+/// Detect the MTA software of a server from its greeting `lines`, i.e. the
+/// initial banner and the first line of the `EHLO` reply. Returns its name,
+/// followed by its version when the greeting tells it, e.g. "Exim 4.94.2".
+pub(super) fn detect_mta<'a, I>(lines: I) -> Option<String>
+where
+	I: IntoIterator<Item = &'a str>,
+{
+	lines.into_iter().find_map(|line| {
+		let lower = line.to_ascii_lowercase();
+		MTA_PATTERNS.iter().find_map(|(pattern, name)| {
+			let start = lower.find(pattern)?;
+			let rest = &line[start + pattern.len()..];
+			Some(match parse_version(rest) {
+				Some(version) => format!("{} {}", name, version),
+				None => name.to_string(),
+			})
+		})
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::detect_mta;
+
+	#[test]
+	fn should_detect_mta() {
+		let detect = |line: &str| detect_mta(vec![line]);
+
+		assert_eq!(
+			detect("220 mx.example.org ESMTP Postfix (Debian/GNU)"),
+			Some("Postfix".into())
+		);
+		assert_eq!(
+			detect("220-mx.example.org ESMTP Exim 4.94.2 Mon, 14 Mar 2022 10:00:00 +0000"),
+			Some("Exim 4.94.2".into())
+		);
+		assert_eq!(
+			detect("220 mx.example.org ESMTP Sendmail 8.15.2/8.15.2; Mon, 14 Mar 2022"),
+			Some("Sendmail 8.15.2".into())
+		);
+		assert_eq!(
+			detect("220 BN8NAM11FT012.mail.protection.outlook.com Microsoft ESMTP MAIL Service ready at Mon, 14 Mar 2022"),
+			Some("Microsoft Exchange".into())
+		);
+		assert_eq!(
+			detect("220 mx.google.com ESMTP a1si123456qkb.1 - gsmtp"),
+			Some("Google".into())
+		);
+		assert_eq!(detect("220 mx.example.org ESMTP"), None);
+	}
+
+	#[test]
+	fn should_use_the_ehlo_greeting() {
+		assert_eq!(
+			detect_mta(vec![
+				"220 mx.example.org ESMTP",
+				"mx.example.org Haraka/2.8.28 Hello"
+			]),
+			Some("Haraka 2.8.28".into())
+		);
+		assert_eq!(detect_mta(vec![]), None);
+	}
+}
@@ -0,0 +1,262 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::util::input_output::{CheckEmailInputProxy, IpPreference};
+use async_std::io::{self, prelude::*};
+use async_std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use async_std::task;
+use futures::future::{self, FutureExt};
+use std::net::{IpAddr, Ipv6Addr, UdpSocket};
+use std::time::Duration;
+
+/// Delay between two connection attempts when racing addresses, as
+/// recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Maximum size of the headers of an HTTP proxy's reply to `CONNECT`.
+const MAX_PROXY_REPLY_SIZE: usize = 8192;
+
+/// Maximum size of the server's greeting we peek at.
+const MAX_GREETING_SIZE: usize = 1024;
+
+/// Resolve `host:port`, only keeping the addresses allowed by `preference`.
+pub(super) async fn resolve(
+	host: &str,
+	port: u16,
+	preference: IpPreference,
+) -> io::Result<Vec<SocketAddr>> {
+	let addrs = (host, port).to_socket_addrs().await?;
+
+	Ok(addrs
+		.filter(|addr| match preference {
+			IpPreference::Ipv4Only => addr.is_ipv4(),
+			IpPreference::Ipv6Only => addr.is_ipv6(),
+			IpPreference::System | IpPreference::HappyEyeballs => true,
+		})
+		.collect())
+}
+
+/// Peek at the SMTP server's greeting on `stream`, without consuming it, so
+/// that async-smtp still reads it. Only the data already received is
+/// returned, which is usually the whole greeting, one line per reply line.
+pub(super) async fn peek_greeting(stream: &TcpStream) -> Option<Vec<String>> {
+	let mut buf = [0; MAX_GREETING_SIZE];
+	let len = stream.peek(&mut buf).await.ok()?;
+	if len == 0 {
+		return None;
+	}
+
+	Some(
+		String::from_utf8_lossy(&buf[..len])
+			.lines()
+			.map(|line| line.trim().to_string())
+			.filter(|line| !line.is_empty())
+			.collect(),
+	)
+}
+
+/// The local IP address of the route to `addr`, i.e. the one we'd connect
+/// from. "Connecting" a UDP socket doesn't send anything.
+pub(super) fn route_source_ip(addr: SocketAddr) -> Option<IpAddr> {
+	let local: SocketAddr = if addr.is_ipv4() {
+		([0, 0, 0, 0], 0).into()
+	} else {
+		([0; 16], 0).into()
+	};
+	let socket = UdpSocket::bind(local).ok()?;
+	socket.connect(addr).ok()?;
+
+	socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Sort the addresses for happy eyeballs (RFC 8305): alternate between
+/// IPv6 and IPv4 addresses, starting with IPv6.
+fn interleave(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+	let mut ipv6 = addrs.iter().filter(|addr| addr.is_ipv6());
+	let mut ipv4 = addrs.iter().filter(|addr| addr.is_ipv4());
+
+	let mut sorted = Vec::with_capacity(addrs.len());
+	loop {
+		match (ipv6.next(), ipv4.next()) {
+			(None, None) => return sorted,
+			(a, b) => sorted.extend(a.into_iter().chain(b).copied()),
+		}
+	}
+}
+
+/// Connect to the first of `addrs` which accepts the connection, racing
+/// IPv6 and IPv4 addresses. Each attempt starts 250ms after the previous
+/// one, and the first one to complete wins.
+pub(super) async fn happy_eyeballs(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+	let attempts = interleave(addrs)
+		.into_iter()
+		.enumerate()
+		.map(|(i, addr)| {
+			async move {
+				task::sleep(CONNECTION_ATTEMPT_DELAY * i as u32).await;
+				TcpStream::connect(addr).await
+			}
+			.boxed()
+		})
+		.collect::<Vec<_>>();
+
+	if attempts.is_empty() {
+		return Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			"No address to connect to",
+		));
+	}
+
+	future::select_ok(attempts).await.map(|(stream, _)| stream)
+}
+
+/// Open a tunnel to `host:port` through an HTTP proxy, using the `CONNECT`
+/// method. The returned stream is connected to `host:port`.
+pub(super) async fn http_connect(
+	proxy: &CheckEmailInputProxy,
+	host: &str,
+	port: u16,
+) -> io::Result<TcpStream> {
+	let mut stream = TcpStream::connect((proxy.host.as_ref(), proxy.port)).await?;
+
+	// IPv6 addresses are bracketed, e.g. "[::1]:25".
+	let authority = match host.parse::<Ipv6Addr>() {
+		Ok(_) => format!("[{}]:{}", host, port),
+		Err(_) => format!("{}:{}", host, port),
+	};
+	let mut request = format!(
+		"CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n",
+		authority = authority
+	);
+	if let (Some(username), Some(password)) = (&proxy.username, &proxy.password) {
+		request.push_str(&format!(
+			"Proxy-Authorization: Basic {}\r\n",
+			base64::encode(format!("{}:{}", username, password))
+		));
+	}
+	request.push_str("\r\n");
+	stream.write_all(request.as_bytes()).await?;
+
+	// Read the reply one byte at a time, so that we don't consume the
+	// beginning of the SMTP greeting which might follow it.
+	let mut reply = Vec::new();
+	let mut byte = [0; 1];
+	while !reply.ends_with(b"\r\n\r\n") {
+		if reply.len() >= MAX_PROXY_REPLY_SIZE {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"HTTP proxy reply is too long",
+			));
+		}
+		stream.read_exact(&mut byte).await?;
+		reply.push(byte[0]);
+	}
+
+	let reply = String::from_utf8_lossy(&reply);
+	let status_line = reply.lines().next().unwrap_or_default();
+	match status_line.split_whitespace().nth(1) {
+		Some(code) if code.starts_with('2') => Ok(stream),
+		_ => Err(io::Error::new(
+			io::ErrorKind::ConnectionRefused,
+			format!("HTTP proxy refused to connect: {}", status_line),
+		)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::mock::{MockServer, MockSession};
+	use super::{happy_eyeballs, http_connect, interleave, route_source_ip};
+	use crate::util::input_output::{CheckEmailInputProxy, ProxyType};
+	use async_std::io::prelude::*;
+	use std::net::SocketAddr;
+	use tokio::runtime::Runtime;
+
+	/// An HTTP proxy to `server`, with credentials.
+	fn proxy(server: &MockServer) -> CheckEmailInputProxy {
+		CheckEmailInputProxy {
+			host: "127.0.0.1".into(),
+			port: server.port,
+			username: Some("user".into()),
+			password: Some("pass".into()),
+			proxy_type: ProxyType::HttpConnect,
+		}
+	}
+
+	#[test]
+	fn should_tunnel_through_http_proxy() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![
+			MockSession::new().http_proxy("HTTP/1.1 200 Connection established\r\n\r\n")
+		]);
+
+		let mut stream = runtime
+			.block_on(http_connect(&proxy(&server), "mx.example.org", 25))
+			.unwrap();
+		// The greeting of the SMTP server, through the tunnel.
+		let mut greeting = [0; 3];
+		runtime.block_on(stream.read_exact(&mut greeting)).unwrap();
+		assert_eq!(&greeting, b"220");
+
+		let request = server.commands().remove(0);
+		assert!(request.starts_with("CONNECT mx.example.org:25 HTTP/1.1\r\n"));
+		// base64("user:pass")
+		assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+	}
+
+	#[test]
+	fn should_fail_if_http_proxy_refuses() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![
+			MockSession::new().http_proxy("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+		]);
+
+		assert!(runtime
+			.block_on(http_connect(&proxy(&server), "mx.example.org", 25))
+			.is_err());
+	}
+
+	#[test]
+	fn should_find_the_source_ip_of_a_route() {
+		let addr: SocketAddr = "127.0.0.1:25".parse().unwrap();
+		assert_eq!(route_source_ip(addr), Some(addr.ip()));
+	}
+
+	#[test]
+	fn should_interleave_ipv6_and_ipv4() {
+		let addrs: Vec<SocketAddr> = vec![
+			"1.1.1.1:25".parse().unwrap(),
+			"2.2.2.2:25".parse().unwrap(),
+			"[::1]:25".parse().unwrap(),
+		];
+
+		assert_eq!(interleave(&addrs), vec![addrs[2], addrs[0], addrs[1]]);
+	}
+
+	#[test]
+	fn should_connect_to_the_address_which_answers() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![MockSession::new()]);
+		let addrs = vec![
+			// Nothing listens on this port.
+			"127.0.0.1:1".parse().unwrap(),
+			SocketAddr::from(([127, 0, 0, 1], server.port)),
+		];
+
+		let stream = runtime.block_on(happy_eyeballs(&addrs)).unwrap();
+		assert_eq!(stream.peer_addr().unwrap(), addrs[1]);
+	}
+}
@@ -0,0 +1,77 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pipelining of SMTP commands (RFC 2920), which async-smtp doesn't support:
+//! it writes one command at a time, and reads each reply with a new buffered
+//! reader, which would lose the replies received in the same packet.
+
+use crate::util::input_output::CheckEmailInput;
+use async_smtp::smtp::{error::Error as AsyncSmtpError, response::Response};
+use async_std::future;
+use async_std::io::{self, prelude::*, BufReader};
+use async_std::net::TcpStream;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Send all `commands` in a single write on `stream`, then read one reply per
+/// command, with a buffered reader kept across replies.
+///
+/// The commands bypass async-smtp, so they must leave the conversation in the
+/// state it expects, e.g. `RCPT TO` commands within a transaction. Fails if a
+/// reply can't be read, in which case the connection is unusable. Each reply
+/// must come within `input.command_timeout`, or `input.connect_timeout` if
+/// it's not set.
+pub(super) async fn send_pipelined<C: Display>(
+	stream: &TcpStream,
+	commands: &[C],
+	input: &CheckEmailInput,
+) -> Result<Vec<Result<Response, AsyncSmtpError>>, AsyncSmtpError> {
+	let payload: String = commands.iter().map(ToString::to_string).collect();
+	let mut writer = stream;
+	writer.write_all(payload.as_bytes()).await?;
+	writer.flush().await?;
+
+	let timeout = input.command_timeout.unwrap_or(input.connect_timeout);
+	let mut reader = BufReader::new(stream);
+	let mut replies = Vec::with_capacity(commands.len());
+	for _ in commands {
+		let response = future::timeout(timeout, read_response(&mut reader))
+			.await
+			.map_err(AsyncSmtpError::Timeout)??;
+		replies.push(if response.is_positive() {
+			Ok(response)
+		} else {
+			Err(response.into())
+		});
+	}
+
+	Ok(replies)
+}
+
+/// Read one reply, which spans several lines if each one but the last has a
+/// dash after the code, e.g. "250-First line".
+async fn read_response(reader: &mut BufReader<&TcpStream>) -> Result<Response, AsyncSmtpError> {
+	let mut buffer = String::new();
+	loop {
+		let start = buffer.len();
+		if reader.read_line(&mut buffer).await? == 0 {
+			return Err(io::Error::new(io::ErrorKind::Other, "incomplete").into());
+		}
+		if buffer.as_bytes()[start..].get(3) != Some(&b'-') {
+			return Response::from_str(&buffer);
+		}
+	}
+}
@@ -0,0 +1,231 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{needs_extensions, Connection};
+use crate::util::input_output::CheckEmailInput;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What connections in the pool are keyed by: the server, and the settings
+/// of the input which shape a connection, e.g. the proxies, the `EHLO` name,
+/// the TLS options or the credentials. Checks with different settings never
+/// share a connection, e.g. a check through a proxy doesn't reuse a direct
+/// connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct PoolKey {
+	host: String,
+	port: u16,
+	/// The connection settings, serialized to JSON.
+	settings: String,
+}
+
+impl PoolKey {
+	pub(super) fn new(host: &str, port: u16, input: &CheckEmailInput) -> Self {
+		let settings = serde_json::json!({
+			"from_email": input.from_email,
+			"use_null_sender": input.use_null_sender,
+			"hello_name": input.hello_name,
+			"hello_ip": input.hello_ip,
+			"hello_name_from_ptr": input.hello_name_from_ptr,
+			"egress_ip": input.egress_ip,
+			"proxy": input.proxy,
+			"proxies": input.proxies,
+			"smtp_ip_override": input.smtp_ip_override,
+			"ip_preference": input.ip_preference,
+			"smtp_security": input.smtp_security,
+			"smtp_auth": input.smtp_auth,
+			"tls_root_certificates": input.tls_root_certificates,
+			"tls_sni_override": input.tls_sni_override,
+			"danger_accept_invalid_certs": input.danger_accept_invalid_certs,
+			// Whether the extensions were fetched, see `needs_extensions`.
+			"needs_extensions": needs_extensions(input),
+		});
+
+		PoolKey {
+			host: host.to_string(),
+			port,
+			settings: settings.to_string(),
+		}
+	}
+}
+
+/// An idle connection in the pool.
+struct IdleConnection {
+	connection: Connection,
+	idle_since: Instant,
+}
+
+/// Idle connections, by key.
+type IdleConnections = HashMap<PoolKey, Vec<IdleConnection>>;
+
+/// A pool of SMTP connections, keyed by server and connection settings, see
+/// [`PoolKey`]. Connections are
+/// reused across checks until the server closes them, or until they exceed
+/// the pool's max idle time or max lifetime.
+///
+/// Cloning the pool is cheap, and all clones share the same connections.
+#[derive(Clone)]
+pub struct SmtpPool {
+	connections: Arc<Mutex<IdleConnections>>,
+	max_idle: Duration,
+	max_lifetime: Duration,
+}
+
+impl fmt::Debug for SmtpPool {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("SmtpPool")
+			.field("max_idle", &self.max_idle)
+			.field("max_lifetime", &self.max_lifetime)
+			.finish()
+	}
+}
+
+impl SmtpPool {
+	/// Create a new empty pool. Idle connections are closed after `max_idle`,
+	/// and no connection is reused after `max_lifetime`.
+	pub fn new(max_idle: Duration, max_lifetime: Duration) -> Self {
+		SmtpPool {
+			connections: Arc::new(Mutex::new(HashMap::new())),
+			max_idle,
+			max_lifetime,
+		}
+	}
+
+	/// Take an idle connection with `key` out of the pool, if any.
+	pub(super) async fn take(&self, key: &PoolKey) -> Option<Connection> {
+		let mut expired = vec![];
+		let found = {
+			let mut connections = self
+				.connections
+				.lock()
+				.expect("Pool lock is not poisoned. qed.");
+			let idle = connections.get_mut(key)?;
+			let mut found = None;
+			while let Some(idle_connection) = idle.pop() {
+				let IdleConnection {
+					connection,
+					idle_since,
+				} = idle_connection;
+				if idle_since.elapsed() < self.max_idle
+					&& connection.created_at.elapsed() < self.max_lifetime
+				{
+					found = Some(connection);
+					break;
+				}
+				expired.push(connection);
+			}
+			found
+		};
+
+		// Close the stale connections outside of the lock.
+		for mut connection in expired {
+			let _ = connection.transport.close().await;
+		}
+
+		found
+	}
+
+	/// Put a connection back into the pool. The caller must make sure that
+	/// no transaction is in progress on this connection (e.g. by sending
+	/// `RSET`).
+	pub(super) async fn put(&self, key: PoolKey, mut connection: Connection) {
+		if connection.created_at.elapsed() >= self.max_lifetime {
+			let _ = connection.transport.close().await;
+			return;
+		}
+
+		self.connections
+			.lock()
+			.expect("Pool lock is not poisoned. qed.")
+			.entry(key)
+			.or_default()
+			.push(IdleConnection {
+				connection,
+				idle_since: Instant::now(),
+			});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Connection, PoolKey, SmtpPool};
+	use crate::util::input_output::{CheckEmailInput, CheckEmailInputProxy};
+	use async_smtp::{ClientSecurity, SmtpClient};
+	use std::time::{Duration, Instant};
+	use tokio::runtime::Runtime;
+
+	async fn connection() -> Connection {
+		Connection {
+			transport: SmtpClient::with_security(("127.0.0.1", 25), ClientSecurity::None)
+				.await
+				.unwrap()
+				.into_transport(),
+			created_at: Instant::now(),
+			extensions: vec![],
+			used_proxy: None,
+			source_ip: None,
+			mx_ip: None,
+			mta_software: None,
+			plaintext_stream: None,
+		}
+	}
+
+	#[test]
+	fn should_reuse_fresh_connections_only() {
+		let runtime = Runtime::new().unwrap();
+
+		let input = CheckEmailInput::default();
+		let key = |port| PoolKey::new("mx.example.org", port, &input);
+
+		runtime.block_on(async {
+			let pool = SmtpPool::new(Duration::from_secs(60), Duration::from_secs(60));
+			pool.put(key(25), connection().await).await;
+			assert!(pool.take(&key(587)).await.is_none());
+			assert!(pool.clone().take(&key(25)).await.is_some());
+			assert!(pool.take(&key(25)).await.is_none());
+
+			let pool = SmtpPool::new(Duration::from_secs(0), Duration::from_secs(60));
+			pool.put(key(25), connection().await).await;
+			assert!(pool.take(&key(25)).await.is_none());
+		});
+	}
+
+	#[test]
+	fn should_not_share_connections_across_proxies() {
+		let runtime = Runtime::new().unwrap();
+		let proxy = |port| CheckEmailInputProxy {
+			host: "proxy.example.org".into(),
+			port,
+			..Default::default()
+		};
+		let direct = CheckEmailInput::default();
+		let mut first_proxy = CheckEmailInput::default();
+		first_proxy.set_proxy(proxy(1080));
+		let mut second_proxy = CheckEmailInput::default();
+		second_proxy.set_proxy(proxy(1081));
+		let key = |input| PoolKey::new("mx.example.org", 25, input);
+
+		runtime.block_on(async {
+			let pool = SmtpPool::new(Duration::from_secs(60), Duration::from_secs(60));
+			pool.put(key(&first_proxy), connection().await).await;
+			assert!(pool.take(&key(&direct)).await.is_none());
+			assert!(pool.take(&key(&second_proxy)).await.is_none());
+			assert!(pool.take(&key(&first_proxy)).await.is_some());
+		});
+	}
+}
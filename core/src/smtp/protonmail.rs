@@ -0,0 +1,47 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{create_smtp_future, Progress, SmtpDetails, SmtpError};
+use crate::util::{constants::LOG_TARGET, input_output::CheckEmailInput};
+use async_smtp::EmailAddress;
+use trust_dns_proto::rr::Name;
+
+/// Verify an email hosted by ProtonMail via SMTP. ProtonMail's servers
+/// accept recipients at the SMTP level without telling if they exist, so an
+/// accepted recipient is flagged with `SmtpDetails::is_unverifiable`.
+/// Rejections are still reliable.
+pub(super) async fn check_protonmail(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	progress: &mut Progress,
+) -> Result<SmtpDetails, SmtpError> {
+	let details = create_smtp_future(to_email, host, port, domain, input, progress).await?;
+	if details.is_deliverable {
+		log::debug!(
+			target: LOG_TARGET,
+			"email={} ProtonMail accepted the recipient, which doesn't confirm it exists.",
+			to_email
+		);
+	}
+
+	Ok(SmtpDetails {
+		is_unverifiable: details.is_deliverable,
+		..details
+	})
+}
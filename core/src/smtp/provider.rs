@@ -0,0 +1,160 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use trust_dns_proto::rr::Name;
+
+/// The email provider hosting a domain, as identified from its MX hosts.
+/// Some providers need a special treatment, as their SMTP servers don't
+/// reliably tell if an email exists.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+	/// Gmail and Google Workspace.
+	Google,
+	/// Yahoo, and the other domains hosted by Yahoo.
+	Yahoo,
+	/// Outlook.com, Hotmail and Office 365.
+	Microsoft,
+	/// ProtonMail, including custom domains. Its SMTP servers can't confirm
+	/// that an email exists.
+	ProtonMail,
+	/// Zoho Mail, including custom domains.
+	Zoho,
+	/// Any other provider, or a self-hosted server.
+	#[default]
+	Other,
+}
+
+/// The MX host suffixes of each provider.
+const PROVIDER_SUFFIXES: &[(Provider, &[&str])] = &[
+	(Provider::Google, &["google.com", "googlemail.com"]),
+	(Provider::Yahoo, &["yahoodns.net"]),
+	(Provider::Microsoft, &["outlook.com", "hotmail.com"]),
+	(Provider::ProtonMail, &["protonmail.ch"]),
+	(
+		Provider::Zoho,
+		&["zoho.com", "zoho.eu", "zoho.in", "zoho.com.au"],
+	),
+];
+
+/// Identify the provider of a single MX host.
+fn classify_host(host: &str) -> Provider {
+	let host = host.trim_end_matches('.').to_lowercase();
+	PROVIDER_SUFFIXES
+		.iter()
+		.find(|(_, suffixes)| {
+			suffixes
+				.iter()
+				.any(|suffix| host == *suffix || host.ends_with(&format!(".{}", suffix)))
+		})
+		.map_or(Provider::Other, |(provider, _)| *provider)
+}
+
+/// Identify the provider of a domain from its MX hosts, which should be
+/// sorted by priority. The first host belonging to a known provider wins,
+/// and `Provider::Other` is returned if there's none.
+pub fn classify(mx_hosts: &[Name]) -> Provider {
+	mx_hosts
+		.iter()
+		.map(|host| classify_host(&host.to_string()))
+		.find(|provider| *provider != Provider::Other)
+		.unwrap_or_default()
+}
+
+/// Find the first of `patterns` matching the MX `host`. Patterns are
+/// case-insensitive, and `*` matches any sequence of characters.
+pub(super) fn find_pattern<'a>(patterns: &'a [String], host: &str) -> Option<&'a str> {
+	let host = host.trim_end_matches('.');
+	patterns
+		.iter()
+		.find(|pattern| {
+			let re = format!("^{}$", regex::escape(pattern).replace(r"\*", ".*"));
+			RegexBuilder::new(&re)
+				.case_insensitive(true)
+				.build()
+				.map_or(false, |re| re.is_match(host))
+		})
+		.map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{classify, find_pattern, Provider};
+	use std::str::FromStr;
+	use trust_dns_proto::rr::Name;
+
+	fn classify_hosts(hosts: &[&str]) -> Provider {
+		classify(
+			&hosts
+				.iter()
+				.map(|host| Name::from_str(host).unwrap())
+				.collect::<Vec<_>>(),
+		)
+	}
+
+	#[test]
+	fn should_classify_mx_hosts() {
+		assert_eq!(
+			classify_hosts(&["gmail-smtp-in.l.google.com."]),
+			Provider::Google
+		);
+		assert_eq!(
+			classify_hosts(&["alt1.gmr-smtp-in.l.googlemail.com"]),
+			Provider::Google
+		);
+		assert_eq!(classify_hosts(&["MTA6.AM0.YAHOODNS.NET"]), Provider::Yahoo);
+		assert_eq!(
+			classify_hosts(&["example-org.mail.protection.outlook.com"]),
+			Provider::Microsoft
+		);
+		assert_eq!(
+			classify_hosts(&["mail.protonmail.ch", "mailsec.protonmail.ch"]),
+			Provider::ProtonMail
+		);
+		assert_eq!(classify_hosts(&["mx.zoho.eu"]), Provider::Zoho);
+	}
+
+	#[test]
+	fn should_not_classify_lookalike_hosts() {
+		assert_eq!(classify_hosts(&["mx.yahooclone.com"]), Provider::Other);
+		assert_eq!(classify_hosts(&["google.com.evil.org"]), Provider::Other);
+		assert_eq!(classify_hosts(&[]), Provider::Other);
+	}
+
+	#[test]
+	fn should_use_the_first_known_provider() {
+		assert_eq!(
+			classify_hosts(&["mx.example.org", "aspmx.l.google.com"]),
+			Provider::Google
+		);
+	}
+
+	#[test]
+	fn should_match_mx_patterns() {
+		let patterns = vec!["*.mail.example.net".to_string(), "mx?.example.org".into()];
+
+		assert_eq!(
+			find_pattern(&patterns, "MX1.Mail.Example.Net."),
+			Some("*.mail.example.net")
+		);
+		assert_eq!(find_pattern(&patterns, "mail.example.net"), None);
+		// `?` is not a wildcard.
+		assert_eq!(find_pattern(&patterns, "mx1.example.org"), None);
+		assert_eq!(find_pattern(&[], "mx.example.org"), None);
+	}
+}
@@ -0,0 +1,221 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use async_lock::{Mutex as AsyncMutex, Semaphore, SemaphoreGuardArc};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The limits of one MX host.
+struct HostLimit {
+	/// Bounds the concurrent connections to the host.
+	semaphore: Arc<Semaphore>,
+	/// The earliest time the next connection may start.
+	next_slot: Mutex<Instant>,
+}
+
+/// Limits the SMTP connections to each MX host, to avoid getting blocked
+/// when checking many emails of the same domain. Checks over the limits
+/// wait for their turn instead of failing, before their SMTP timeout
+/// starts.
+///
+/// Cloning the limiter is cheap, and all clones share the same limits.
+#[derive(Clone)]
+pub struct RateLimiter {
+	hosts: Arc<Mutex<HashMap<String, Arc<HostLimit>>>>,
+	/// The minimum time between the starts of two connections to a host.
+	min_interval: Duration,
+	max_concurrent: usize,
+}
+
+impl fmt::Debug for RateLimiter {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("RateLimiter")
+			.field("min_interval", &self.min_interval)
+			.field("max_concurrent", &self.max_concurrent)
+			.finish()
+	}
+}
+
+/// Allows a connection to a host, until it's dropped.
+pub(super) struct RateLimitPermit {
+	_guard: SemaphoreGuardArc,
+}
+
+impl RateLimiter {
+	/// Create a limiter allowing at most `max_per_second` new connections
+	/// per second, and `max_concurrent` connections at once, to each MX
+	/// host. Pass `f64::INFINITY` or `usize::MAX` for no limit.
+	pub fn new(max_per_second: f64, max_concurrent: usize) -> Self {
+		RateLimiter {
+			hosts: Arc::new(Mutex::new(HashMap::new())),
+			min_interval: if max_per_second > 0.0 && max_per_second.is_finite() {
+				Duration::from_secs_f64(1.0 / max_per_second)
+			} else {
+				Duration::ZERO
+			},
+			max_concurrent: max_concurrent.max(1),
+		}
+	}
+
+	fn host_limit(&self, host: &str) -> Arc<HostLimit> {
+		self.hosts
+			.lock()
+			.expect("Rate limiter lock is not poisoned. qed.")
+			.entry(host.to_lowercase())
+			.or_insert_with(|| {
+				Arc::new(HostLimit {
+					semaphore: Arc::new(Semaphore::new(self.max_concurrent)),
+					next_slot: Mutex::new(Instant::now()),
+				})
+			})
+			.clone()
+	}
+
+	/// Wait until a connection to `host` is allowed. The connection counts
+	/// as in progress until the returned permit is dropped.
+	pub(super) async fn acquire(&self, host: &str) -> RateLimitPermit {
+		let host_limit = self.host_limit(host);
+		let guard = host_limit.semaphore.acquire_arc().await;
+
+		let slot = {
+			let mut next_slot = host_limit
+				.next_slot
+				.lock()
+				.expect("Rate limiter lock is not poisoned. qed.");
+			let slot = (*next_slot).max(Instant::now());
+			*next_slot = slot + self.min_interval;
+			slot
+		};
+		let delay = slot.saturating_duration_since(Instant::now());
+		if !delay.is_zero() {
+			async_std::task::sleep(delay).await;
+		}
+
+		RateLimitPermit { _guard: guard }
+	}
+}
+
+/// Limits the SMTP connections in progress at once, across all hosts, e.g.
+/// to bound the open file descriptors or the load on the proxies. Checks
+/// over the limit wait in line for their turn, before their SMTP timeout
+/// starts. It complements the per-host limits of [`RateLimiter`].
+///
+/// Cloning the limit is cheap, and all clones share the same connections.
+#[derive(Clone)]
+pub struct ConnectionLimit {
+	semaphore: Arc<Semaphore>,
+	/// Only the first in line waits on the semaphore, so that the checks
+	/// get their turn roughly in order, and none starves.
+	line: Arc<AsyncMutex<()>>,
+	max_connections: usize,
+}
+
+impl fmt::Debug for ConnectionLimit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ConnectionLimit")
+			.field("max_connections", &self.max_connections)
+			.finish()
+	}
+}
+
+/// Allows a connection, until it's dropped.
+pub(super) struct ConnectionPermit {
+	_guard: SemaphoreGuardArc,
+}
+
+impl ConnectionLimit {
+	/// Create a limit of `max_connections` connections at once.
+	pub fn new(max_connections: usize) -> Self {
+		let max_connections = max_connections.max(1);
+		ConnectionLimit {
+			semaphore: Arc::new(Semaphore::new(max_connections)),
+			line: Arc::new(AsyncMutex::new(())),
+			max_connections,
+		}
+	}
+
+	/// Wait until a connection is allowed. The connection counts as in
+	/// progress until the returned permit is dropped.
+	pub(super) async fn acquire(&self) -> ConnectionPermit {
+		let _first_in_line = self.line.lock().await;
+		ConnectionPermit {
+			_guard: self.semaphore.acquire_arc().await,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ConnectionLimit, RateLimiter};
+	use std::time::{Duration, Instant};
+	use tokio::runtime::Runtime;
+
+	#[test]
+	fn should_limit_concurrent_connections_per_host() {
+		let runtime = Runtime::new().unwrap();
+		let limiter = RateLimiter::new(f64::INFINITY, 1);
+
+		runtime.block_on(async {
+			let permit = limiter.acquire("mx.example.org").await;
+			// Other hosts have their own limits.
+			let _other = limiter.acquire("mx.example.com").await;
+			assert!(async_std::future::timeout(
+				Duration::from_millis(50),
+				limiter.acquire("MX.example.org")
+			)
+			.await
+			.is_err());
+
+			drop(permit);
+			limiter.acquire("mx.example.org").await;
+		});
+	}
+
+	#[test]
+	fn should_limit_connections_across_hosts() {
+		let runtime = Runtime::new().unwrap();
+		let limit = ConnectionLimit::new(2);
+
+		runtime.block_on(async {
+			let first = limit.acquire().await;
+			let _second = limit.acquire().await;
+			assert!(
+				async_std::future::timeout(Duration::from_millis(50), limit.acquire())
+					.await
+					.is_err()
+			);
+
+			drop(first);
+			limit.acquire().await;
+		});
+	}
+
+	#[test]
+	fn should_space_connections_per_host() {
+		let runtime = Runtime::new().unwrap();
+		let limiter = RateLimiter::new(10.0, usize::MAX);
+
+		let started = Instant::now();
+		runtime.block_on(async {
+			for _ in 0..3 {
+				limiter.acquire("mx.example.org").await;
+			}
+		});
+		assert!(started.elapsed() >= Duration::from_millis(200));
+	}
+}
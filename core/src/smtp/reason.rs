@@ -0,0 +1,209 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{SmtpDetails, SmtpError};
+use serde::{Deserialize, Serialize};
+
+/// Why an email isn't safe to send to, see `SmtpDetails::reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifReason {
+	/// The mailbox doesn't exist.
+	UserUnknown,
+	/// The inbox is full.
+	MailboxFull,
+	/// The account receives too many emails right now, see
+	/// `SmtpDetails::is_rate_limited`.
+	RateLimited,
+	/// The account is disabled or blocked by the provider.
+	AccountDisabled,
+	/// The domain has no MX records, or doesn't accept emails (null MX).
+	DomainNotFound,
+	/// The domain accepts all emails, see `SmtpDetails::is_catch_all`.
+	CatchAll,
+	/// The server refused the email because of an anti-spam policy, see
+	/// `SmtpDetails::is_blocked`, or blocked the IP we connect from, e.g.
+	/// Yahoo's API rate-limited us or asked for a captcha.
+	Blocked,
+	/// The server rejected our `MAIL FROM` address, e.g. its sender
+	/// verification callback failed. Only for errors, see
+	/// [`SmtpError::SenderRejected`].
+	SenderRejected,
+	/// The server still greylisted us after all the retries. Only for
+	/// errors, see [`SmtpError::reason`].
+	Greylisted,
+	/// The check timed out.
+	Timeout,
+	/// We couldn't connect to the SMTP server.
+	CannotConnect,
+	/// The local part of the email is invalid, see
+	/// `SmtpDetails::invalid_local_part`.
+	InvalidLocalPart,
+	/// The provider accepts all recipients, see `SmtpDetails::is_unverifiable`.
+	Unverifiable,
+	/// The SMTP check was skipped, see `SmtpDetails::is_skipped`.
+	Skipped,
+}
+
+impl SmtpDetails {
+	/// Compute the reason of these details, see `SmtpDetails::reason`.
+	///
+	/// Ambiguous rejections are errors, so a rejection which isn't a full
+	/// inbox or a disabled account says that the mailbox doesn't exist.
+	fn compute_reason(&self) -> Option<VerifReason> {
+		let reason = if self.is_skipped {
+			VerifReason::Skipped
+		} else if self.invalid_local_part.is_some() {
+			VerifReason::InvalidLocalPart
+		} else if self.has_null_mx {
+			VerifReason::DomainNotFound
+		} else if self.is_timed_out {
+			VerifReason::Timeout
+		} else if self.is_blocked {
+			VerifReason::Blocked
+		} else if self.is_disabled {
+			VerifReason::AccountDisabled
+		} else if self.has_full_inbox {
+			VerifReason::MailboxFull
+		} else if self.is_rate_limited {
+			VerifReason::RateLimited
+		} else if self.is_catch_all && !self.is_catch_all_rejection() {
+			VerifReason::CatchAll
+		} else if self.is_unverifiable {
+			VerifReason::Unverifiable
+		} else if self.is_deliverable {
+			return None;
+		} else if !self.can_connect_smtp {
+			VerifReason::CannotConnect
+		} else {
+			VerifReason::UserUnknown
+		};
+
+		Some(reason)
+	}
+
+	/// Fill `reason` from the other fields.
+	pub(super) fn with_reason(self) -> Self {
+		SmtpDetails {
+			reason: self.compute_reason(),
+			..self
+		}
+	}
+}
+
+impl SmtpError {
+	/// Why the check failed, for the errors which tell something about the
+	/// email, e.g. `Greylisted`. Other errors, e.g. an invalid proxy
+	/// configuration, give None.
+	pub fn reason(&self) -> Option<VerifReason> {
+		if self.is_greylisted() {
+			return Some(VerifReason::Greylisted);
+		}
+
+		match (self, self.sub_kind()) {
+			(SmtpError::IpBlocked(_), _) => Some(VerifReason::Blocked),
+			(SmtpError::SenderRejected(_), _) => Some(VerifReason::SenderRejected),
+			(SmtpError::DeadlineExceeded, _) | (_, Some("timeout")) => Some(VerifReason::Timeout),
+			(_, Some("connection_refused")) | (_, Some("connection_reset")) => {
+				Some(VerifReason::CannotConnect)
+			}
+			(_, Some("recipient_rejected")) => Some(VerifReason::UserUnknown),
+			// Yahoo's API blocks us.
+			(err, Some("rate_limited") | Some("captcha")) if err.kind() == "yahoo" => {
+				Some(VerifReason::Blocked)
+			}
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{SmtpDetails, SmtpError, VerifReason};
+	use crate::smtp::yahoo::YahooError;
+
+	#[test]
+	fn should_compute_reason() {
+		let accepted = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			..Default::default()
+		};
+		assert_eq!(accepted.compute_reason(), None);
+
+		let catch_all = SmtpDetails {
+			is_catch_all: true,
+			..accepted
+		};
+		assert_eq!(catch_all.compute_reason(), Some(VerifReason::CatchAll));
+
+		let disabled = SmtpDetails {
+			can_connect_smtp: true,
+			is_disabled: true,
+			..Default::default()
+		};
+		assert_eq!(
+			disabled.compute_reason(),
+			Some(VerifReason::AccountDisabled)
+		);
+
+		let rate_limited = SmtpDetails {
+			can_connect_smtp: true,
+			is_rate_limited: true,
+			..Default::default()
+		};
+		assert_eq!(
+			rate_limited.compute_reason(),
+			Some(VerifReason::RateLimited)
+		);
+
+		let rejected = SmtpDetails {
+			can_connect_smtp: true,
+			smtp_code: Some(550),
+			..Default::default()
+		};
+		assert_eq!(rejected.compute_reason(), Some(VerifReason::UserUnknown));
+
+		assert_eq!(
+			SmtpDetails::default().compute_reason(),
+			Some(VerifReason::CannotConnect)
+		);
+	}
+
+	#[test]
+	fn should_give_error_reason() {
+		assert_eq!(
+			SmtpError::DeadlineExceeded.reason(),
+			Some(VerifReason::Timeout)
+		);
+		assert_eq!(SmtpError::StartTlsUnavailable.reason(), None);
+	}
+
+	#[test]
+	fn should_give_yahoo_error_reason() {
+		let err = SmtpError::YahooError(YahooError::Captcha);
+		assert_eq!(err.sub_kind(), Some("captcha"));
+		assert_eq!(err.reason(), Some(VerifReason::Blocked));
+		assert_eq!(SmtpError::YahooError(YahooError::NoCookie).reason(), None);
+
+		// The reason survives a round trip through JSON.
+		let json = serde_json::to_value(SmtpError::YahooError(YahooError::RateLimited)).unwrap();
+		assert_eq!(json["kind"], "yahoo");
+		assert_eq!(json["sub_kind"], "rate_limited");
+		let err: SmtpError = serde_json::from_value(json).unwrap();
+		assert_eq!(err.reason(), Some(VerifReason::Blocked));
+	}
+}
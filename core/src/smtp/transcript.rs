@@ -0,0 +1,77 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use async_smtp::smtp::{error::Error as AsyncSmtpError, response::Response};
+use std::fmt::Display;
+
+/// Records the lines sent to and received from the SMTP server. Sent lines
+/// are prefixed with "> ", received lines with "< ".
+///
+/// When disabled, nothing is recorded, so there's no memory overhead.
+#[derive(Debug, Default)]
+pub(super) struct Transcript(Option<Vec<String>>);
+
+impl Transcript {
+	pub fn new(enabled: bool) -> Self {
+		Transcript(if enabled { Some(vec![]) } else { None })
+	}
+
+	/// Record a command sent to the server.
+	pub fn command<C: Display>(&mut self, command: &C) {
+		if let Some(lines) = &mut self.0 {
+			lines.push(format!("> {}", command.to_string().trim_end()));
+		}
+	}
+
+	/// Record a raw line, for events which are not SMTP commands.
+	pub fn note<S: Display>(&mut self, note: S) {
+		if let Some(lines) = &mut self.0 {
+			lines.push(note.to_string());
+		}
+	}
+
+	/// Record the server's reply to a command.
+	pub fn result(&mut self, result: &Result<Response, AsyncSmtpError>) {
+		if let Some(lines) = &mut self.0 {
+			match result {
+				Ok(response)
+				| Err(AsyncSmtpError::Transient(response))
+				| Err(AsyncSmtpError::Permanent(response)) => {
+					let last = response.message.len().saturating_sub(1);
+					if response.message.is_empty() {
+						lines.push(format!("< {}", response.code));
+					}
+					for (i, line) in response.message.iter().enumerate() {
+						let separator = if i == last { ' ' } else { '-' };
+						lines.push(format!("< {}{}{}", response.code, separator, line));
+					}
+				}
+				// Other errors don't come from the server's reply.
+				Err(err) => lines.push(format!("! {}", err)),
+			}
+		}
+	}
+
+	/// Take the lines recorded so far, leaving the transcript empty.
+	pub fn take_lines(&mut self) -> Vec<String> {
+		self.0.as_mut().map(std::mem::take).unwrap_or_default()
+	}
+
+	/// Consume the transcript, returning the recorded lines.
+	pub fn into_lines(self) -> Vec<String> {
+		self.0.unwrap_or_default()
+	}
+}
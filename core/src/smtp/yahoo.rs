@@ -14,16 +14,23 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::SmtpDetails;
+use super::{
+	classification::YAHOO_PATTERNS, create_smtp_future, is_ip_blocked, Classification, Progress,
+	SmtpDetails, SmtpError,
+};
 use crate::util::{
-	constants::LOG_TARGET, input_output::CheckEmailInput, ser_with_display::ser_with_display,
+	constants::LOG_TARGET,
+	input_output::{CheckEmailInput, ProxyType},
+	ser_with_display::ser_with_display,
 };
 use async_smtp::EmailAddress;
 use regex::Regex;
-use reqwest::Error as ReqwestError;
+use reqwest::{Error as ReqwestError, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::error::Error as SerdeError;
 use std::fmt;
+use std::time::Duration;
+use trust_dns_proto::rr::Name;
 
 const SIGNUP_PAGE: &str = "https://login.yahoo.com/account/create?specId=yidReg&lang=en-US&src=&done=https%3A%2F%2Fwww.yahoo.com&display=login";
 const SIGNUP_API: &str = "https://login.yahoo.com/account/module/create?validateField=yid";
@@ -71,13 +78,22 @@ struct FormResponse {
 	errors: Vec<FormResponseItem>,
 }
 
-/// Possible errors when checking Yahoo email addresses.
+/// Possible errors when checking Yahoo email addresses. An email which
+/// doesn't exist isn't an error: the check succeeds, and the details'
+/// reason is `UserUnknown`.
 #[derive(Debug, Serialize)]
 pub enum YahooError {
 	/// Cannot find "acrumb" field in cookie.
 	NoAcrumb,
 	/// Cannot find cookie in Yahoo response.
 	NoCookie,
+	/// Yahoo replied with HTTP 429 Too Many Requests: we made too many
+	/// requests from this IP. Only returned once the retries of
+	/// `CheckEmailInput::yahoo_retries` are exhausted.
+	RateLimited,
+	/// Yahoo redirected us to a captcha challenge, which usually means that
+	/// it flagged our IP as a bot.
+	Captcha,
 	/// Error when serializing or deserializing HTTP requests and responses.
 	#[serde(serialize_with = "ser_with_display")]
 	ReqwestError(ReqwestError),
@@ -105,35 +121,110 @@ impl From<SerdeError> for YahooError {
 	}
 }
 
-/// Helper function to create a reqwest client, with optional proxy.
-fn create_client(input: &CheckEmailInput) -> Result<reqwest::Client, ReqwestError> {
-	if let Some(proxy) = &input.proxy {
+/// Helper function to create a reqwest client, with optional proxy. `api`
+/// is the name of the provider, for logging.
+pub(super) fn create_client(
+	input: &CheckEmailInput,
+	api: &str,
+) -> Result<reqwest::Client, ReqwestError> {
+	if let Some(proxy) = super::proxies_to_try(input).first() {
+		let scheme = match proxy.proxy_type {
+			ProxyType::Socks5 => "socks5",
+			ProxyType::HttpConnect => "http",
+		};
 		log::debug!(
 			target: LOG_TARGET,
-			"email={} Using proxy socks://{}:{} for Yahoo API",
+			"email={} Using proxy {}://{}:{} for {} API",
 			input.to_emails[0],
+			scheme,
 			proxy.host,
-			proxy.port
+			proxy.port,
+			api
 		);
 
-		let proxy = reqwest::Proxy::all(&format!("socks5://{}:{}", proxy.host, proxy.port))?;
-		reqwest::Client::builder().proxy(proxy).build()
+		let mut reqwest_proxy =
+			reqwest::Proxy::all(format!("{}://{}:{}", scheme, proxy.host, proxy.port))?;
+		if let (ProxyType::HttpConnect, Some(username), Some(password)) =
+			(proxy.proxy_type, &proxy.username, &proxy.password)
+		{
+			reqwest_proxy = reqwest_proxy.basic_auth(username, password);
+		}
+		reqwest::Client::builder().proxy(reqwest_proxy).build()
 	} else {
 		Ok(reqwest::Client::new())
 	}
 }
 
+/// The HTTP client for Yahoo's API: `input.yahoo_http_client` if set,
+/// otherwise a new one.
+fn yahoo_client(input: &CheckEmailInput) -> Result<reqwest::Client, ReqwestError> {
+	match &input.yahoo_http_client {
+		Some(client) => Ok(client.clone()),
+		None => create_client(input, "Yahoo"),
+	}
+}
+
+/// Make sure that Yahoo answered `response`, instead of rate-limiting us or
+/// asking for a captcha.
+fn check_response(response: reqwest::Response) -> Result<reqwest::Response, YahooError> {
+	if response.status() == StatusCode::TOO_MANY_REQUESTS {
+		return Err(YahooError::RateLimited);
+	}
+	if response.url().path().contains("/challenge") {
+		return Err(YahooError::Captcha);
+	}
+
+	Ok(response)
+}
+
+/// The delay to wait before the retry number `attempt` (starting at 1) of
+/// Yahoo's API, see `CheckEmailInput::yahoo_retry_delay`.
+fn retry_delay(attempt: usize, input: &CheckEmailInput) -> Duration {
+	input
+		.yahoo_retry_delay
+		.saturating_mul(1 << attempt.saturating_sub(1).min(16))
+}
+
 /// Use well-crafted HTTP requests to verify if a Yahoo email address exists.
 /// Inspired by https://github.com/hbattat/verifyEmail.
+///
+/// If Yahoo rate-limits us, the check is retried up to
+/// `input.yahoo_retries` times, with exponential backoff.
 pub async fn check_yahoo(
 	to_email: &EmailAddress,
 	input: &CheckEmailInput,
 ) -> Result<SmtpDetails, YahooError> {
-	let response = create_client(input)?
+	let mut attempt = 1;
+	loop {
+		match check_yahoo_once(to_email, input).await {
+			Err(YahooError::RateLimited) if attempt <= input.yahoo_retries => {
+				let delay = retry_delay(attempt, input);
+				log::debug!(
+					target: LOG_TARGET,
+					"email={} Yahoo rate-limited us, retrying in {:?}.",
+					to_email,
+					delay
+				);
+				async_std::task::sleep(delay).await;
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+/// One attempt of [`check_yahoo`].
+async fn check_yahoo_once(
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+) -> Result<SmtpDetails, YahooError> {
+	let client = yahoo_client(input)?;
+	let response = client
 		.get(SIGNUP_PAGE)
 		.header("User-Agent", USER_AGENT)
 		.send()
 		.await?;
+	let response = check_response(response)?;
 
 	// Get the cookies from the response.
 	let cookies = match response.headers().get("Set-Cookie") {
@@ -178,7 +269,7 @@ pub async fn check_yahoo(
 	};
 
 	// Mimic a real HTTP request.
-	let response = create_client(input)?
+	let response = client
 		.post(SIGNUP_API)
 		.header("Origin", "https://login.yahoo.com")
 		.header("X-Requested-With", "XMLHttpRequest")
@@ -197,9 +288,8 @@ pub async fn check_yahoo(
 			username.into(),
 		))
 		.send()
-		.await?
-		.json::<FormResponse>()
 		.await?;
+	let response = check_response(response)?.json::<FormResponse>().await?;
 
 	log::debug!(
 		target: LOG_TARGET,
@@ -219,3 +309,86 @@ pub async fn check_yahoo(
 		..Default::default()
 	})
 }
+
+/// `input` with Yahoo's quirks, see [`YAHOO_PATTERNS`]: the replies they
+/// classify as blocked mean that our IP is blocked.
+fn with_yahoo_patterns(input: &CheckEmailInput) -> CheckEmailInput {
+	let mut input = input.clone();
+	for (pattern, classification) in YAHOO_PATTERNS {
+		if *classification == Classification::Blocked {
+			input.ip_block_indicators.push(pattern.to_string());
+		}
+		input
+			.rcpt_error_patterns
+			.push((pattern.to_string(), *classification));
+	}
+
+	input
+}
+
+/// Verify a Yahoo email via SMTP, interpreting the replies of Yahoo's servers
+/// with their quirks, see `YahooVerifMethod::Smtp`. The patterns of
+/// `input.rcpt_error_patterns` still come first.
+pub(super) async fn check_yahoo_smtp(
+	to_email: &EmailAddress,
+	host: &Name,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	progress: &mut Progress,
+) -> Result<SmtpDetails, SmtpError> {
+	let input = with_yahoo_patterns(input);
+	create_smtp_future(to_email, host, port, domain, &input, progress)
+		.await
+		// Yahoo also blocks us when connecting, e.g. with a [TSS04] greeting.
+		.map_err(|err| match err {
+			SmtpError::SmtpError(err) if is_ip_blocked(&err, &input) => SmtpError::IpBlocked(err),
+			err => err,
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{check_yahoo_smtp, retry_delay, CheckEmailInput, Progress, SmtpError};
+	use crate::smtp::mock::{MockServer, MockSession};
+	use async_smtp::EmailAddress;
+	use std::str::FromStr;
+	use std::time::Duration;
+	use tokio::runtime::Runtime;
+	use trust_dns_proto::rr::Name;
+
+	#[test]
+	fn should_detect_yahoo_ip_blocks() {
+		let runtime = Runtime::new().unwrap();
+		let server = MockServer::start(vec![MockSession::new().on(
+			"RCPT TO:",
+			"421 4.7.0 [TSS04] Messages from 1.2.3.4 temporarily deferred due to unexpected volume or user complaints\r\n",
+		)]);
+
+		let to_email = EmailAddress::from_str("foo@yahoo.com").unwrap();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let mut input = CheckEmailInput::default();
+		input.set_check_catch_all(false);
+
+		let result = runtime.block_on(check_yahoo_smtp(
+			&to_email,
+			&host,
+			server.port,
+			"yahoo.com",
+			&input,
+			&mut Progress::new(&input),
+		));
+		// Not mistaken for greylisting.
+		assert!(matches!(result, Err(SmtpError::IpBlocked(_))));
+	}
+
+	#[test]
+	fn should_back_off_exponentially() {
+		let mut input = CheckEmailInput::default();
+		input.set_yahoo_retry_delay(Duration::from_millis(500));
+
+		assert_eq!(retry_delay(1, &input), Duration::from_millis(500));
+		assert_eq!(retry_delay(2, &input), Duration::from_secs(1));
+		assert_eq!(retry_delay(4, &input), Duration::from_secs(4));
+	}
+}
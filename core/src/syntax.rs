@@ -24,6 +24,11 @@ pub struct SyntaxDetails {
 	/// The email address as a async_smtp `EmailAddress`. It will be `None` if
 	/// the email address is ill-formed.
 	pub address: Option<EmailAddress>,
+	/// The address without its sub-address tag, e.g. `foo@bar.com` for
+	/// `foo+tag@bar.com`. It will be `None` if the email address isn't
+	/// sub-addressed, or is ill-formed. See
+	/// `CheckEmailInput::verify_base_address`.
+	pub base_address: Option<EmailAddress>,
 	/// The domain name, after "@". It will be the empty string if the email
 	/// address if ill-formed.
 	pub domain: String,
@@ -38,6 +43,7 @@ impl Default for SyntaxDetails {
 	fn default() -> Self {
 		SyntaxDetails {
 			address: None,
+			base_address: None,
 			domain: "".into(),
 			is_valid_syntax: false,
 			username: "".into(),
@@ -45,14 +51,164 @@ impl Default for SyntaxDetails {
 	}
 }
 
+/// Parse an internationalized email address (RFC 6531), i.e. whose local
+/// part or domain isn't ASCII. async-smtp only accepts ASCII addresses, so we
+/// validate an ASCII version of the address instead, with the domain in
+/// punycode, and the other characters of the local part replaced.
+fn parse_internationalized(email_address: &str) -> Option<EmailAddress> {
+	if email_address.is_ascii() {
+		return None;
+	}
+
+	let (local_part, domain) = email_address.rsplit_once('@')?;
+	if local_part.chars().any(char::is_control) {
+		return None;
+	}
+	let ascii_local_part = local_part
+		.chars()
+		.map(|c| if c.is_ascii() { c } else { 'a' })
+		.collect::<String>();
+	let ascii_domain = idna::domain_to_ascii(domain).ok()?;
+	EmailAddress::from_str(&format!("{}@{}", ascii_local_part, ascii_domain)).ok()?;
+
+	from_unvalidated(email_address)
+}
+
+/// Parse an email address whose local part is quoted (RFC 5321), e.g.
+/// `"john doe"@example.org`, which async-smtp doesn't accept either.
+fn parse_quoted(email_address: &str) -> Option<EmailAddress> {
+	let (local_part, domain) = email_address.rsplit_once('@')?;
+	if !local_part.starts_with('"') || validate_local_part(local_part, false).is_err() {
+		return None;
+	}
+	let ascii_domain = idna::domain_to_ascii(domain).ok()?;
+	EmailAddress::from_str(&format!("user@{}", ascii_domain)).ok()?;
+
+	from_unvalidated(email_address)
+}
+
+/// Build an `EmailAddress` without validating it, for the addresses which
+/// async-smtp rejects but we validated ourselves. Deserializing is the only
+/// way to do so.
+fn from_unvalidated(email_address: &str) -> Option<EmailAddress> {
+	serde_json::from_value(serde_json::Value::String(email_address.into())).ok()
+}
+
+/// Why a local part (the part before "@") can't be used in `RCPT TO`, see
+/// [`validate_local_part`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "message")]
+pub enum LocalPartError {
+	/// The local part is empty.
+	Empty,
+	/// The local part is longer than 64 octets.
+	TooLong,
+	/// The local part starts or ends with a dot.
+	LeadingOrTrailingDot,
+	/// The local part contains consecutive dots.
+	ConsecutiveDots,
+	/// The local part contains a character only allowed in quoted local
+	/// parts, e.g. a space or a comma.
+	IllegalCharacter(char),
+	/// The local part is quoted, e.g. `"john doe"`, which isn't allowed in
+	/// strict mode.
+	Quoted,
+	/// The quoted local part is malformed, e.g. has an unescaped quote.
+	InvalidQuotedString,
+}
+
+/// Maximum length of a local part in octets, from RFC 5321.
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+/// Check that `local_part` is valid according to RFC 5321, extended to
+/// UTF-8 by RFC 6531. In `strict` mode, quoted local parts (e.g.
+/// `"john doe"`) are rejected, as few servers accept them.
+pub fn validate_local_part(local_part: &str, strict: bool) -> Result<(), LocalPartError> {
+	if local_part.is_empty() {
+		return Err(LocalPartError::Empty);
+	}
+	if local_part.len() > MAX_LOCAL_PART_LENGTH {
+		return Err(LocalPartError::TooLong);
+	}
+
+	if local_part.starts_with('"') {
+		return match (strict, is_quoted_string(local_part)) {
+			(true, _) => Err(LocalPartError::Quoted),
+			(false, true) => Ok(()),
+			(false, false) => Err(LocalPartError::InvalidQuotedString),
+		};
+	}
+
+	if local_part.starts_with('.') || local_part.ends_with('.') {
+		return Err(LocalPartError::LeadingOrTrailingDot);
+	}
+	if local_part.contains("..") {
+		return Err(LocalPartError::ConsecutiveDots);
+	}
+	match local_part.chars().find(|c| *c != '.' && !is_atext(*c)) {
+		Some(c) => Err(LocalPartError::IllegalCharacter(c)),
+		None => Ok(()),
+	}
+}
+
+/// Is `c` allowed in an unquoted local part, i.e. `atext` in RFC 5322,
+/// which RFC 6531 extends to non-ASCII characters?
+fn is_atext(c: char) -> bool {
+	c.is_ascii_alphanumeric()
+		|| "!#$%&'*+-/=?^_`{|}~".contains(c)
+		|| (!c.is_ascii() && !c.is_control())
+}
+
+/// Is `s` a valid `Quoted-string` from RFC 5321, e.g. `"john \"doe\""`?
+fn is_quoted_string(s: &str) -> bool {
+	let inner = match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+		Some(inner) => inner,
+		None => return false,
+	};
+
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		let is_valid = match c {
+			// quoted-pair
+			'\\' => chars.next().map_or(false, |c| (' '..='~').contains(&c)),
+			'"' => false,
+			// qtextSMTP
+			c => (' '..='~').contains(&c) || (!c.is_ascii() && !c.is_control()),
+		};
+		if !is_valid {
+			return false;
+		}
+	}
+
+	true
+}
+
+/// The address without the sub-address tag of `username`, i.e. everything
+/// from the first "+", or None if `username` isn't sub-addressed. Quoted
+/// local parts are left alone.
+fn base_address(username: &str, domain: &str) -> Option<EmailAddress> {
+	if username.starts_with('"') {
+		return None;
+	}
+	match username.split_once('+') {
+		Some((user, _)) if !user.is_empty() => from_unvalidated(&format!("{}@{}", user, domain)),
+		_ => None,
+	}
+}
+
 /// From an `email_address` string, compute syntax information about it, such as
 /// username and domain.
 pub fn check_syntax(email_address: &str) -> SyntaxDetails {
-	let email_address = match EmailAddress::from_str(email_address) {
-		Ok(m) => m,
+	let email_address = match EmailAddress::from_str(email_address)
+		.ok()
+		.or_else(|| parse_internationalized(email_address))
+		.or_else(|| parse_quoted(email_address))
+	{
+		Some(m) => m,
 		_ => {
 			return SyntaxDetails {
 				address: None,
+				base_address: None,
 				domain: "".into(),
 				is_valid_syntax: false,
 				username: "".into(),
@@ -60,18 +216,15 @@ pub fn check_syntax(email_address: &str) -> SyntaxDetails {
 		}
 	};
 
-	let iter: &str = email_address.as_ref();
-	let mut iter = iter.split('@');
-	let username = iter
-		.next()
-		.expect("We checked above that email is valid. qed.")
-		.into();
-	let domain = iter
-		.next()
-		.expect("We checked above that email is valid. qed.")
-		.into();
+	// Quoted local parts may contain "@", but the domain can't.
+	let address = email_address.to_string();
+	let (username, domain): (String, String) = address
+		.rsplit_once('@')
+		.map(|(username, domain)| (username.into(), domain.into()))
+		.expect("We checked above that email is valid. qed.");
 
 	SyntaxDetails {
+		base_address: base_address(&username, &domain),
 		address: Some(email_address),
 		domain,
 		is_valid_syntax: true,
@@ -89,6 +242,7 @@ mod tests {
 			check_syntax("foo"),
 			SyntaxDetails {
 				address: None,
+				base_address: None,
 				domain: "".into(),
 				is_valid_syntax: false,
 				username: "".into(),
@@ -102,6 +256,7 @@ mod tests {
 			check_syntax("foo@bar"),
 			SyntaxDetails {
 				address: None,
+				base_address: None,
 				domain: "".into(),
 				is_valid_syntax: false,
 				username: "".into(),
@@ -109,12 +264,91 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn should_work_for_internationalized_email() {
+		let syntax = check_syntax("用户@例子.广告");
+		assert!(syntax.is_valid_syntax);
+		assert_eq!(syntax.address.unwrap().to_string(), "用户@例子.广告");
+		assert_eq!(syntax.domain, "例子.广告");
+		assert_eq!(syntax.username, "用户");
+
+		assert!(!check_syntax("用户@例子").is_valid_syntax);
+		assert!(!check_syntax("用 户@example.org").is_valid_syntax);
+	}
+
+	#[test]
+	fn should_work_for_quoted_local_part() {
+		let syntax = check_syntax("\"john@doe\"@example.org");
+		assert!(syntax.is_valid_syntax);
+		assert_eq!(syntax.username, "\"john@doe\"");
+		assert_eq!(syntax.domain, "example.org");
+
+		assert!(!check_syntax("\"john\"doe\"@example.org").is_valid_syntax);
+	}
+
+	#[test]
+	fn should_build_unvalidated_addresses() {
+		// async-smtp rejects these addresses, but still deserializes them
+		// as is. If it ever validates them when deserializing, the
+		// internationalized and quoted addresses break.
+		for email in ["用户@例子.广告", "\"john doe\"@example.org"] {
+			assert!(EmailAddress::from_str(email).is_err());
+			assert_eq!(from_unvalidated(email).unwrap().to_string(), email);
+		}
+	}
+
+	#[test]
+	fn should_detect_sub_addressing() {
+		let syntax = check_syntax("foo+bar+baz@example.org");
+		assert_eq!(
+			syntax.address.unwrap().to_string(),
+			"foo+bar+baz@example.org"
+		);
+		assert_eq!(syntax.base_address.unwrap().to_string(), "foo@example.org");
+
+		assert_eq!(check_syntax("foo@example.org").base_address, None);
+		assert_eq!(check_syntax("+foo@example.org").base_address, None);
+		assert_eq!(check_syntax("\"foo+bar\"@example.org").base_address, None);
+	}
+
+	#[test]
+	fn should_validate_local_part() {
+		assert_eq!(validate_local_part("john.doe+tag", true), Ok(()));
+		assert_eq!(validate_local_part("用户", true), Ok(()));
+		assert_eq!(
+			validate_local_part(".john", true),
+			Err(LocalPartError::LeadingOrTrailingDot)
+		);
+		assert_eq!(
+			validate_local_part("john..doe", true),
+			Err(LocalPartError::ConsecutiveDots)
+		);
+		assert_eq!(
+			validate_local_part("john doe", true),
+			Err(LocalPartError::IllegalCharacter(' '))
+		);
+		assert_eq!(
+			validate_local_part("\"john doe\"", true),
+			Err(LocalPartError::Quoted)
+		);
+		assert_eq!(validate_local_part("\"john \\\"doe\"", false), Ok(()));
+		assert_eq!(
+			validate_local_part("\"john\\\"", false),
+			Err(LocalPartError::InvalidQuotedString)
+		);
+		assert_eq!(
+			validate_local_part(&"a".repeat(65), false),
+			Err(LocalPartError::TooLong)
+		);
+	}
+
 	#[test]
 	fn should_work_for_valid_email() {
 		assert_eq!(
 			check_syntax("foo@bar.com"),
 			SyntaxDetails {
 				address: Some(EmailAddress::new("foo@bar.com".into()).unwrap()),
+				base_address: None,
 				domain: "bar.com".into(),
 				is_valid_syntax: true,
 				username: "foo".into(),
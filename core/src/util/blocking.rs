@@ -0,0 +1,37 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::future::Future;
+use tokio::runtime::{Builder, Runtime};
+
+thread_local! {
+	/// The runtime of the blocking checks, created on the first check of each
+	/// thread, and reused by the next ones.
+	static RUNTIME: Runtime = Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.expect("Failed to create the runtime of the blocking checks.");
+}
+
+/// Run `future` to completion on the current thread.
+///
+/// # Panics
+///
+/// This function panics if called from within an async runtime, e.g. inside
+/// an async function.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+	RUNTIME.with(|runtime| runtime.block_on(future))
+}
@@ -0,0 +1,122 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use event_listener::Event;
+use futures::future::{self, Either};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Inner {
+	is_cancelled: AtomicBool,
+	/// Notified when the token is cancelled.
+	event: Event,
+}
+
+/// A token to cancel checks, e.g. when the client waiting for them
+/// disconnects, see `CheckEmailInput::cancellation_token`. Cancelling is
+/// permanent.
+///
+/// Cloning the token is cheap, and all clones cancel the same checks.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+impl fmt::Debug for CancellationToken {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_tuple("CancellationToken")
+			.field(&self.is_cancelled())
+			.finish()
+	}
+}
+
+impl CancellationToken {
+	pub fn new() -> Self {
+		CancellationToken::default()
+	}
+
+	/// Cancel the checks using this token.
+	pub fn cancel(&self) {
+		self.0.is_cancelled.store(true, Ordering::SeqCst);
+		self.0.event.notify(usize::MAX);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.is_cancelled.load(Ordering::SeqCst)
+	}
+
+	/// Wait until the token is cancelled.
+	pub async fn cancelled(&self) {
+		while !self.is_cancelled() {
+			let listener = self.0.event.listen();
+			// Don't miss a cancellation between the check and `listen()`.
+			if self.is_cancelled() {
+				return;
+			}
+			listener.await;
+		}
+	}
+}
+
+/// Run `future` to completion, or return None if `token` is cancelled
+/// first. Without token, `future` always runs to completion.
+pub(crate) async fn until_cancelled<F: Future>(
+	token: Option<&CancellationToken>,
+	future: F,
+) -> Option<F::Output> {
+	let token = match token {
+		Some(token) => token,
+		None => return Some(future.await),
+	};
+
+	let cancelled = token.cancelled();
+	futures::pin_mut!(cancelled, future);
+	match future::select(cancelled, future).await {
+		Either::Left(_) => None,
+		Either::Right((output, _)) => Some(output),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{until_cancelled, CancellationToken};
+	use std::time::Duration;
+	use tokio::runtime::Runtime;
+
+	#[test]
+	fn should_stop_when_cancelled() {
+		let runtime = Runtime::new().unwrap();
+		let token = CancellationToken::new();
+
+		assert_eq!(
+			runtime.block_on(until_cancelled(Some(&token), async { 42 })),
+			Some(42)
+		);
+
+		let cancelling = token.clone();
+		std::thread::spawn(move || {
+			std::thread::sleep(Duration::from_millis(50));
+			cancelling.cancel();
+		});
+		let forever = futures::future::pending::<()>();
+		assert_eq!(
+			runtime.block_on(until_cancelled(Some(&token), forever)),
+			None
+		);
+		assert!(token.is_cancelled());
+	}
+}
@@ -0,0 +1,53 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// An error read back from its JSON serialization, e.g. from a cached
+/// `CheckEmailOutput`, when the original error can't be rebuilt because it
+/// wraps an error from another crate, such as an I/O error. It keeps the JSON
+/// object as is, so that it serializes back to the same JSON.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct DeserializedError(Map<String, Value>);
+
+impl DeserializedError {
+	fn get_str(&self, key: &str) -> Option<&str> {
+		self.0.get(key).and_then(Value::as_str)
+	}
+
+	/// The variant of the original error, e.g. "SmtpError".
+	pub fn error_type(&self) -> Option<&str> {
+		self.get_str("type")
+	}
+
+	/// The details of the original error, usually its human-readable
+	/// message.
+	pub fn message(&self) -> Option<&Value> {
+		self.0.get("message")
+	}
+
+	/// The `kind` field of the original error, if it had one.
+	pub fn kind(&self) -> Option<&str> {
+		self.get_str("kind")
+	}
+
+	/// The `sub_kind` field of the original error, if it had one.
+	pub fn sub_kind(&self) -> Option<&str> {
+		self.get_str("sub_kind")
+	}
+}
@@ -14,42 +14,109 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::dns::DnsDetails;
 use crate::misc::{MiscDetails, MiscError};
 use crate::mx::{MxDetails, MxError};
-use crate::smtp::{SmtpDetails, SmtpError};
+use crate::smtp::{
+	Classification, ConnectionLimit, GreylistToken, Provider, RateLimiter, SmtpDetails, SmtpError,
+	SmtpEventHook, SmtpPool,
+};
 use crate::syntax::SyntaxDetails;
-use async_smtp::{ClientSecurity, ClientTlsParameters};
-use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use crate::util::{cancellation::CancellationToken, ttl_cache::TtlCache};
+use async_smtp::{smtp::authentication::Mechanism, ClientSecurity, ClientTlsParameters};
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+
+/// The protocol spoken by a proxy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProxyType {
+	/// A SOCKS5 proxy.
+	#[default]
+	Socks5,
+	/// An HTTP proxy, supporting the `CONNECT` method. The username and
+	/// password, if any, are sent with basic authentication.
+	HttpConnect,
+}
+
+/// How to pick the proxy to use for each connection, when several proxies
+/// are configured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProxyRotation {
+	/// Use each proxy in turn.
+	#[default]
+	RoundRobin,
+	/// Pick a random proxy.
+	Random,
+}
 
 /// Perform the email verification via a specified proxy. The usage of a proxy
 /// is optional.
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct CheckEmailInputProxy {
-	/// Use the specified SOCKS5 proxy host to perform email verification.
+	/// Use the specified proxy host to perform email verification.
 	pub host: String,
-	/// Use the specified SOCKS5 proxy port to perform email verification.
+	/// Use the specified proxy port to perform email verification.
 	pub port: u16,
 	/// Username to pass to proxy authentication.
 	pub username: Option<String>,
 	/// Password to pass to proxy authentication.
 	pub password: Option<String>,
+	/// The protocol spoken by the proxy.
+	///
+	/// Defaults to Socks5.
+	#[serde(default)]
+	pub proxy_type: ProxyType,
 }
 
 /// Define how to apply TLS to a SMTP client connection. Will be converted into
 /// async_smtp::ClientSecurity.
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub enum SmtpSecurity {
-	/// Insecure connection only (for testing purposes).
+	/// Plaintext connection only, even if the server supports `STARTTLS`,
+	/// e.g. for internal relays or testing purposes.
 	None,
 	/// Start with insecure connection and use `STARTTLS` when available.
 	Opportunistic,
-	/// Start with insecure connection and require `STARTTLS`.
+	/// Start with insecure connection and require `STARTTLS`. If the server
+	/// doesn't advertise it, the check fails with
+	/// `SmtpError::StartTlsUnavailable`.
 	Required,
 	/// Use TLS wrapped connection.
 	Wrapper,
 }
 
+/// How to verify emails hosted by Yahoo, see
+/// `CheckEmailInput::yahoo_verif_method`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum YahooVerifMethod {
+	/// Use Yahoo's signup API, without connecting to their SMTP servers.
+	#[default]
+	Api,
+	/// Connect to Yahoo's SMTP servers, and interpret their replies knowing
+	/// Yahoo's quirks, e.g. that "[TSS04] Messages temporarily deferred"
+	/// means that our IP is blocked, not that we're greylisted.
+	Smtp,
+	/// Connect to Yahoo's SMTP servers, and interpret their replies like
+	/// those of any other server.
+	GenericSmtp,
+}
+
+/// What to do with the SMTP connection once a check is done, see
+/// `CheckEmailInput::smtp_end_policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SmtpEndPolicy {
+	/// Send `QUIT` and close the connection.
+	#[default]
+	Quit,
+	/// Send `RSET` to start a fresh transaction, and keep the connection open
+	/// in `CheckEmailInput::smtp_pool` for the next checks. Without a pool,
+	/// this is the same as Quit.
+	Reset,
+}
+
 impl SmtpSecurity {
 	pub fn to_client_security(self, tls_params: ClientTlsParameters) -> ClientSecurity {
 		match self {
@@ -61,6 +128,71 @@ impl SmtpSecurity {
 	}
 }
 
+/// The SASL mechanism used to authenticate to the SMTP server, see
+/// [`SmtpAuth`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SmtpAuthMechanism {
+	/// `AUTH PLAIN`, sending the credentials with the command.
+	#[default]
+	Plain,
+	/// `AUTH LOGIN`, sending the username and the password in reply to the
+	/// server's challenges.
+	Login,
+}
+
+impl SmtpAuthMechanism {
+	pub fn to_mechanism(self) -> Mechanism {
+		match self {
+			Self::Plain => Mechanism::Plain,
+			Self::Login => Mechanism::Login,
+		}
+	}
+}
+
+/// Credentials to authenticate with (`AUTH`) before checking emails, for
+/// relays which require a login before `RCPT TO`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SmtpAuth {
+	pub username: String,
+	pub password: String,
+	/// The mechanism to authenticate with.
+	///
+	/// Defaults to Plain.
+	#[serde(default)]
+	pub mechanism: SmtpAuthMechanism,
+}
+
+/// Which IP versions to use when connecting to the SMTP server.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum IpPreference {
+	/// Connect to the first address returned by the system's resolver.
+	#[default]
+	System,
+	/// Only connect over IPv4, i.e. to the host's A records.
+	Ipv4Only,
+	/// Only connect over IPv6, i.e. to the host's AAAA records.
+	Ipv6Only,
+	/// Race IPv6 and IPv4 connections, and use whichever completes first
+	/// ("happy eyeballs", RFC 8305).
+	HappyEyeballs,
+}
+
+/// The DNS resolver used for the MX and TXT lookups.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub enum DnsResolver {
+	/// Use the system's configuration, e.g. /etc/resolv.conf on Unix.
+	#[default]
+	System,
+	/// Query these nameservers, over UDP and TCP, e.g. internal resolvers.
+	Nameservers(Vec<SocketAddr>),
+	/// Use this trust-dns resolver configuration. It can't be serialized.
+	///
+	/// Note: DNS-over-HTTPS and DNS-over-TLS nameservers are not supported,
+	/// as the async-std runtime of the resolver can't connect to them.
+	#[serde(skip)]
+	Custom(Box<ResolverConfig>, ResolverOpts),
+}
+
 /// Builder pattern for the input argument into the main `email_exists`
 /// function.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -71,33 +203,429 @@ pub struct CheckEmailInput {
 	///
 	/// Defaults to "user@example.org".
 	pub from_email: String,
+	/// Email to use in the `MAIL FROM:` SMTP command if `from_email` is not a
+	/// valid email address.
+	///
+	/// Defaults to None, i.e. "postmaster@<domain>", where domain is the
+	/// domain of the email being checked.
+	pub from_email_fallback: Option<String>,
+	/// Send `MAIL FROM:<>` (the null reverse-path used by bounces) instead of
+	/// `from_email`. Some servers only reject unknown recipients when the
+	/// sender is not null, so comparing both can improve accuracy.
+	///
+	/// Defaults to false.
+	pub use_null_sender: bool,
+	/// Other emails to use in the `MAIL FROM:` SMTP command, to check the
+	/// email again with each of them after the check with `from_email`. Some
+	/// servers answer differently depending on the sender, e.g. because of
+	/// its reputation. The most specific answer is reported, see
+	/// `SmtpDetails::has_from_email_mismatch`.
+	///
+	/// Defaults to an empty list, i.e. only `from_email` is used.
+	pub alternative_from_emails: Vec<String>,
 	/// Name to use in the `EHLO:` SMTP command.
 	///
 	/// Defaults to "localhost" (note: "localhost" is not a FQDN).
 	pub hello_name: String,
-	/// Perform the email verification via the specified SOCK5 proxy. The usage of a
-	/// proxy is optional.
+	/// Use the reverse DNS (PTR record) of the IP address we connect from as
+	/// the `EHLO` name, as some servers penalize names which don't match it.
+	/// If the lookup fails, `hello_ip` or `hello_name` is used instead.
+	///
+	/// Defaults to false.
+	pub hello_name_from_ptr: bool,
+	/// Send this IP address as an address literal in the `EHLO` command,
+	/// e.g. `EHLO [203.0.113.5]`, instead of `hello_name`. Some servers
+	/// expect one from clients without a valid FQDN, e.g. on dynamic IPs.
+	///
+	/// Defaults to None, i.e. `hello_name` is used.
+	pub hello_ip: Option<IpAddr>,
+	/// The public IP address the SMTP servers see us connect from, e.g. the
+	/// exit IP of the proxy or the NAT, for `hello_name_from_ptr`.
+	///
+	/// Defaults to None, i.e. the local address of the route to the server.
+	/// Through a proxy, there's no PTR lookup then.
+	pub egress_ip: Option<IpAddr>,
+	/// Perform the email verification via the specified SOCKS5 or HTTP proxy.
+	/// The usage of a proxy is optional.
 	pub proxy: Option<CheckEmailInputProxy>,
+	/// More proxies to spread the connections over, on top of `proxy`. Each
+	/// connection picks one of them according to `proxy_rotation`, and if it
+	/// fails, the next proxies are tried.
+	///
+	/// Defaults to none.
+	pub proxies: Vec<CheckEmailInputProxy>,
+	/// How to pick the proxy for each connection, among `proxy` and
+	/// `proxies`.
+	///
+	/// Defaults to RoundRobin.
+	pub proxy_rotation: ProxyRotation,
+	/// Substrings (case-insensitive) which, in a server reply, mean that the
+	/// IP we connect from (e.g. the proxy's) is blocked. Such replies fail
+	/// with `SmtpError::IpBlocked`, instead of being interpreted as an
+	/// answer about the email.
+	///
+	/// Defaults to "blocked using", "spamhaus", "blacklisted", "blocklisted"
+	/// and "on our block list".
+	pub ip_block_indicators: Vec<String>,
+	/// Domains of disposable email providers, in addition to the built-in
+	/// list, see `MiscDetails::is_disposable`. Their subdomains are also
+	/// considered disposable.
+	///
+	/// Defaults to an empty list.
+	pub disposable_domains: Vec<String>,
+	/// Use the built-in list of disposable email providers. Turn it off to
+	/// only use `disposable_domains`.
+	///
+	/// Defaults to true.
+	pub use_builtin_disposable_domains: bool,
+	/// The local parts of role-based accounts, e.g. "info" or "sales", see
+	/// `MiscDetails::is_role_account`.
+	///
+	/// Defaults to None, i.e. the built-in list, which covers the roles of
+	/// RFC 2142 and many common ones.
+	pub role_accounts: Option<Vec<String>>,
+	/// Domains of free email providers, in addition to the built-in list,
+	/// see `MiscDetails::is_free_provider`.
+	///
+	/// Defaults to an empty list.
+	pub free_provider_domains: Vec<String>,
+	/// Check whether the email has a Gravatar, see
+	/// `MiscDetails::has_gravatar`. This sends the MD5 hash of the email to
+	/// gravatar.com.
+	///
+	/// Defaults to false.
+	pub check_gravatar: bool,
+	/// For sub-addressed emails, e.g. `foo+tag@bar.com`, verify the base
+	/// address `foo@bar.com` instead, as both almost always go to the same
+	/// mailbox, and some servers reject the tagged form. Both addresses are
+	/// recorded in `SyntaxDetails`.
+	///
+	/// Defaults to false.
+	pub verify_base_address: bool,
+	/// Reject quoted local parts (e.g. `"john doe"@example.org`) without
+	/// connecting to the SMTP server, as few servers accept them. Local parts
+	/// which are invalid according to RFC 5321 are always rejected, see
+	/// `SmtpDetails::invalid_local_part`.
+	///
+	/// Defaults to true.
+	pub strict_local_part: bool,
+	/// Skip the SMTP verification step, i.e. only check the syntax and the
+	/// MX records, without opening any SMTP connection. The SMTP details are
+	/// then marked as skipped, see `SmtpDetails::is_skipped`.
+	///
+	/// Defaults to false.
+	pub skip_smtp: bool,
 	/// SMTP port to use for email validation. Generally, ports 25, 465, 587
 	/// and 2525 are used.
 	///
 	/// Defaults to 25.
 	pub smtp_port: u16,
-	/// Add optional timeout for the SMTP verification step.
+	/// SMTP port to use for the MX hosts of a given provider, see
+	/// `smtp::classify`, instead of `smtp_port`. E.g. some providers answer
+	/// better on a submission port, and internal relays may listen on a
+	/// nonstandard port.
+	///
+	/// Defaults to none, i.e. `smtp_port` for all providers.
+	pub provider_ports: HashMap<Provider, u16>,
+	/// Maximum number of MX hosts to try, in priority order, when they
+	/// don't give a conclusive answer. This bounds the time spent on domains
+	/// with many MX records. At least one host is always tried.
+	///
+	/// Defaults to 3.
+	pub max_mx_attempts: usize,
+	/// Connect to this IP address instead of resolving the MX hosts, e.g.
+	/// to check emails against a staging server. The MX hosts are still
+	/// looked up, and their names are used for SNI and provider detection,
+	/// while `EHLO` uses `hello_name` as usual. Through a proxy, the proxy
+	/// connects to this IP address.
+	///
+	/// Defaults to None, i.e. the MX hosts are resolved.
+	pub smtp_ip_override: Option<IpAddr>,
+	/// When we can't connect to port 25 (`smtp_port`), check whether the
+	/// server answers on the submission ports 587 (with STARTTLS) and 465
+	/// (with implicit TLS). If it does, outbound port 25 is most likely
+	/// blocked on our side, and the check fails with
+	/// `SmtpError::Port25Blocked` instead of a connection error. These
+	/// ports require authentication, so they can't verify the email.
+	///
+	/// Defaults to false.
+	pub submission_port_fallback: bool,
+	/// Add optional timeout for the whole SMTP verification step, i.e. the
+	/// total time spent on each attempt, including connecting and sending
+	/// all the commands.
 	pub smtp_timeout: Option<Duration>,
-	/// For Yahoo email addresses, use Yahoo's API instead of connecting
-	/// directly to their SMTP servers.
+	/// Deadline for the whole check of each email, including the MX lookup
+	/// and all the SMTP attempts. Once exceeded, the step in flight is
+	/// cancelled, and the check returns the details gathered so far, with
+	/// `MxError::DeadlineExceeded` or `SmtpError::DeadlineExceeded`.
 	///
-	/// Defaults to true.
-	pub yahoo_use_api: bool,
-	/// Number of retries of SMTP connections to do.
+	/// Defaults to None, i.e. no deadline.
+	pub deadline: Option<Duration>,
+	/// Timeout for connecting to the SMTP server, including the DNS lookup
+	/// of the host, the server's greeting and the initial `EHLO`.
+	///
+	/// Defaults to 30s.
+	pub connect_timeout: Duration,
+	/// Timeout for each SMTP command sent after connecting (`MAIL FROM`,
+	/// `RCPT TO`...), i.e. how long we wait for the server's reply.
+	///
+	/// Defaults to None, i.e. the connect timeout also applies to commands.
+	pub command_timeout: Option<Duration>,
+	/// Which IP versions to use when connecting to the SMTP server. Ignored
+	/// when using a proxy.
+	///
+	/// Defaults to System.
+	pub ip_preference: IpPreference,
+	/// The DNS resolver used for the MX and TXT lookups. The SMTP hosts are
+	/// always resolved with the system's resolver.
+	///
+	/// Defaults to System.
+	pub dns_resolver: DnsResolver,
+	/// How to verify emails hosted by Yahoo (i.e. whose MX host is on
+	/// yahoodns.net): with Yahoo's API, or by connecting to their SMTP
+	/// servers.
+	///
+	/// Defaults to Api.
+	pub yahoo_verif_method: YahooVerifMethod,
+	/// Number of retries of Yahoo's API when it rate-limits us, i.e. replies
+	/// with HTTP 429. If it still does after the retries, the check fails
+	/// with `YahooError::RateLimited`. Other errors aren't retried.
+	///
+	/// Defaults to 2.
+	pub yahoo_retries: usize,
+	/// Delay before the first retry of Yahoo's API, doubled at each
+	/// following retry.
+	///
+	/// Defaults to 1s.
+	pub yahoo_retry_delay: Duration,
+	/// The HTTP client for Yahoo's API, e.g. to set its timeouts, user agent
+	/// or proxy, so that its requests leave from the same IPs as the SMTP
+	/// connections. `proxy` and `proxies` are then ignored for Yahoo's API.
+	///
+	/// Defaults to None, i.e. a new client is built for each check, through
+	/// the first proxy if any.
+	#[serde(skip)]
+	pub yahoo_http_client: Option<reqwest::Client>,
+	/// For emails hosted by Google (i.e. whose MX host is on google.com or
+	/// googlemail.com), use a Gmail HTTP endpoint instead of connecting to
+	/// their SMTP servers, which often accept unknown recipients.
+	///
+	/// Defaults to false.
+	pub gmail_use_api: bool,
+	/// MX host patterns of providers to treat as catch-all, without
+	/// connecting to their SMTP servers, e.g. "*.mail.example.net". The only
+	/// wildcard is `*`, which matches any sequence of characters. Patterns
+	/// are case-insensitive.
+	///
+	/// Defaults to none.
+	pub catch_all_mx_patterns: Vec<String>,
+	/// Number of retries of SMTP connections to do, on transient errors or
+	/// when we can't connect. The catch-all probe is retried up to as many
+	/// times as well, on the same connection.
 	///
 	/// Defaults to 2 to avoid greylisting.
 	pub retries: usize,
+	/// Maximum delay to wait before retrying after a transient error, or a
+	/// failed connection. We
+	/// wait for the delay suggested by the server (e.g. "try again in 5
+	/// minutes") if any, or use exponential backoff starting at
+	/// `retry_base_delay`, but never wait longer than this.
+	///
+	/// Defaults to None, i.e. retry immediately.
+	pub retry_delay: Option<Duration>,
+	/// Delay before the first retry when the server doesn't suggest one,
+	/// doubled at each following retry, up to `retry_delay`. Only applies if
+	/// `retry_delay` is set.
+	///
+	/// Defaults to 1s.
+	pub retry_base_delay: Duration,
+	/// Instead of retrying when the server greylists us, return
+	/// `SmtpError::Greylisted` right away, with a token to resume the check
+	/// later, see `greylist_token`. Greylisting usually needs a wait of
+	/// several minutes, which is better spent re-enqueuing the email in a
+	/// job queue than blocking the check.
+	///
+	/// Defaults to false.
+	pub defer_greylisting: bool,
+	/// Resume a check deferred because of greylisting, see
+	/// `defer_greylisting`: the email of the token is checked on the host
+	/// which greylisted us, with the same `MAIL FROM`. Other emails are
+	/// checked as usual.
+	///
+	/// Defaults to None.
+	pub greylist_token: Option<GreylistToken>,
+	/// SMTP reply codes (e.g. 421, 451) which trigger a retry. Other
+	/// transient errors are returned right away. Timeouts are always
+	/// retried.
+	///
+	/// Defaults to None, i.e. all transient (4xx) errors are retried.
+	pub retryable_codes: Option<Vec<u16>>,
+	/// Randomize each retry delay by up to this fraction of it, in both
+	/// directions, so that concurrent checks don't all retry at the same time.
+	/// E.g. 0.2 turns a 10s delay into a random delay between 8s and 12s.
+	/// Only applies if `retry_delay` is set.
+	///
+	/// Defaults to 0.2.
+	pub retry_jitter: f64,
 	/// How to apply TLS to a SMTP client connection.
 	///
-	/// Defaults to Opportunistic.
+	/// Defaults to None.
 	pub smtp_security: SmtpSecurity,
+	/// Credentials to authenticate with after `EHLO` (and `STARTTLS`),
+	/// before `MAIL FROM`. If the server rejects them, the check fails with
+	/// `SmtpError::AuthenticationFailed`.
+	///
+	/// Defaults to None, i.e. no authentication.
+	pub smtp_auth: Option<SmtpAuth>,
+	/// Additional root certificates (PEM-encoded) to trust when connecting
+	/// to the SMTP server over TLS, on top of the system's ones, e.g. for an
+	/// internal CA.
+	///
+	/// Defaults to none.
+	pub tls_root_certificates: Vec<Vec<u8>>,
+	/// Hostname to send in the TLS handshake (SNI) instead of the MX host's.
+	/// The server's certificate is then verified against this name, so a
+	/// certificate which doesn't match the MX host is accepted, as long as
+	/// it matches this name.
+	///
+	/// Defaults to None, i.e. the MX host.
+	pub tls_sni_override: Option<String>,
+	/// DANGER: Accept any TLS certificate from the SMTP server, including
+	/// expired, self-signed or otherwise invalid ones. This makes the TLS
+	/// connection vulnerable to man-in-the-middle attacks, but checking
+	/// whether a mailbox exists doesn't rely on the certificate chain, and
+	/// some legacy servers would be unreachable otherwise.
+	///
+	/// Defaults to false.
+	pub danger_accept_invalid_certs: bool,
+	/// Collect the raw SMTP conversation into `SmtpDetails::transcript`.
+	/// Useful for debugging, or to audit why an email has been classified the
+	/// way it was.
+	///
+	/// Defaults to false.
+	pub collect_transcript: bool,
+	/// When the `RCPT TO` command yields an ambiguous result, try the `VRFY`
+	/// command too. Some legacy servers answer `VRFY` more reliably.
+	///
+	/// Defaults to false.
+	pub use_vrfy: bool,
+	/// For deliverable emails, send the `EXPN` command to detect mailing
+	/// lists. This adds one round-trip to the SMTP conversation.
+	///
+	/// Defaults to false.
+	pub use_expn: bool,
+	/// Report the extensions advertised by the server, in
+	/// `SmtpDetails::supported_extensions` and `max_message_size`.
+	/// async-smtp doesn't expose its `EHLO` reply, so this sends `EHLO` a
+	/// second time, which adds one round-trip per connection. `EHLO` is
+	/// also sent again when the check needs the extensions anyway: with
+	/// `smtp_auth`, for internationalized emails, and to pipeline the
+	/// `RCPT TO` commands of several emails.
+	///
+	/// Defaults to false.
+	pub report_smtp_extensions: bool,
+	/// Check if the domain has a catch-all address. Disabling this saves one
+	/// `RCPT TO` command per email, in which case `SmtpDetails::is_catch_all`
+	/// is always false.
+	///
+	/// Defaults to true.
+	pub check_catch_all: bool,
+	/// Local part to use for the catch-all probe. If several probes are sent,
+	/// the probe's index is appended to it from the 2nd probe on.
+	///
+	/// Defaults to a random 15-char alphanumerical string.
+	pub catch_all_local_part: Option<String>,
+	/// Number of probes to send to detect a catch-all. The domain is only
+	/// considered catch-all if all probes are accepted, which reduces false
+	/// positives on servers which randomly accept some addresses.
+	///
+	/// Defaults to 1.
+	pub catch_all_probes: usize,
+	/// Seed of the random generator of the catch-all probes' local parts,
+	/// for reproducible probes, e.g. in tests. With a seed, every check sends
+	/// the same probes, so servers may learn to accept them.
+	///
+	/// Defaults to None, i.e. a random seed for each check.
+	pub catch_all_rng_seed: Option<u64>,
+	/// On a catch-all domain, still send the `RCPT TO` command for the email,
+	/// instead of reporting it as deliverable right away. Some catch-all
+	/// servers still reject invalid addresses: the email is then reported
+	/// as invalid, and otherwise as risky, see `SmtpDetails::reachable`.
+	/// Either way, the details carry both `is_catch_all` and the server's
+	/// reply for the email, see `SmtpDetails::smtp_message`.
+	///
+	/// Defaults to false.
+	pub strict_catch_all: bool,
+	/// Substrings of `RCPT TO` rejection messages, with what they say about
+	/// the email, e.g. provider-specific wordings of "user unknown". They're
+	/// matched case-insensitively, before the built-in ones.
+	///
+	/// Defaults to an empty list.
+	pub rcpt_error_patterns: Vec<(String, Classification)>,
+	/// Substrings of `RCPT TO` rejection messages saying that the email
+	/// doesn't exist, in addition to the built-in ones. They're matched
+	/// case-insensitively, after the built-in ones.
+	///
+	/// Defaults to an empty list.
+	pub extra_unknown_user_patterns: Vec<String>,
+	/// Cache the catch-all status of each domain, so that the catch-all probe
+	/// only runs once per domain. Results are only reused within the lifetime
+	/// of the cache provided by the caller, and until their TTL expires, as a
+	/// domain's catch-all status can change.
+	///
+	/// Defaults to None, i.e. no cache.
+	#[serde(skip)]
+	pub catch_all_cache: Option<TtlCache<bool>>,
+	/// Cache the MX lookup of each domain. Successful lookups are reused
+	/// for their DNS TTL. Domains without MX records are cached for the
+	/// negative TTL of the DNS response, or else the TTL of the cache.
+	///
+	/// Defaults to None, i.e. no cache.
+	#[serde(skip)]
+	pub mx_cache: Option<TtlCache<MxDetails>>,
+	/// Reuse SMTP connections across checks, by keeping them in this pool
+	/// after each check instead of closing them, see `smtp_end_policy`. The
+	/// pool can be shared between several checks by cloning it. Checks only
+	/// reuse connections made with the same settings, e.g. the same proxy
+	/// and `hello_name`.
+	///
+	/// Defaults to None, i.e. each check opens and closes its own connection.
+	#[serde(skip)]
+	pub smtp_pool: Option<SmtpPool>,
+	/// What to do with the SMTP connection once a check is done: close it,
+	/// or reset it and keep it in `smtp_pool`. Idle connections from the
+	/// pool are reused either way.
+	///
+	/// Defaults to Quit. `set_smtp_pool` sets it to Reset.
+	pub smtp_end_policy: SmtpEndPolicy,
+	/// Limit the SMTP connections to each MX host, see [`RateLimiter`]. The
+	/// limits can be shared between several checks by cloning it.
+	///
+	/// Defaults to None, i.e. no limit.
+	#[serde(skip)]
+	pub rate_limiter: Option<RateLimiter>,
+	/// Limit the SMTP connections in progress at once, across all hosts,
+	/// see [`ConnectionLimit`]. The limit can be shared between several
+	/// checks by cloning it.
+	///
+	/// Defaults to None, i.e. no limit.
+	#[serde(skip)]
+	pub connection_limit: Option<ConnectionLimit>,
+	/// Cancel the SMTP verification step when this token is cancelled. The
+	/// SMTP connection is then closed cleanly, and the check returns
+	/// `SmtpError::Cancelled`.
+	///
+	/// Defaults to None, i.e. the check can only be stopped by dropping its
+	/// future, which doesn't close the connection cleanly.
+	#[serde(skip)]
+	pub cancellation_token: Option<CancellationToken>,
+	/// Called at key points of each SMTP check (connection, `RCPT TO`,
+	/// attempts and retries) with their timings and outcomes, e.g. to collect
+	/// metrics. See [`crate::smtp::SmtpEvent`].
+	///
+	/// Defaults to None, in which case no event is built.
+	#[serde(skip)]
+	pub smtp_event_hook: Option<SmtpEventHook>,
 }
 
 impl Default for CheckEmailInput {
@@ -105,13 +633,79 @@ impl Default for CheckEmailInput {
 		CheckEmailInput {
 			to_emails: vec![],
 			from_email: "user@example.org".into(),
+			from_email_fallback: None,
+			alternative_from_emails: vec![],
+			use_null_sender: false,
 			hello_name: "localhost".into(),
+			hello_name_from_ptr: false,
+			hello_ip: None,
+			egress_ip: None,
 			proxy: None,
+			proxies: vec![],
+			proxy_rotation: ProxyRotation::RoundRobin,
+			ip_block_indicators: vec![
+				"blocked using".into(),
+				"spamhaus".into(),
+				"blacklisted".into(),
+				"blocklisted".into(),
+				"on our block list".into(),
+			],
+			disposable_domains: vec![],
+			use_builtin_disposable_domains: true,
+			role_accounts: None,
+			free_provider_domains: vec![],
+			check_gravatar: false,
+			verify_base_address: false,
+			strict_local_part: true,
+			skip_smtp: false,
 			smtp_port: 25,
+			provider_ports: HashMap::new(),
+			max_mx_attempts: 3,
+			smtp_ip_override: None,
+			submission_port_fallback: false,
 			smtp_security: SmtpSecurity::None,
+			smtp_auth: None,
+			tls_root_certificates: vec![],
+			tls_sni_override: None,
+			danger_accept_invalid_certs: false,
 			smtp_timeout: None,
-			yahoo_use_api: true,
+			deadline: None,
+			connect_timeout: Duration::from_secs(30),
+			command_timeout: None,
+			ip_preference: IpPreference::System,
+			dns_resolver: DnsResolver::System,
+			yahoo_verif_method: YahooVerifMethod::Api,
+			yahoo_retries: 2,
+			yahoo_retry_delay: Duration::from_secs(1),
+			yahoo_http_client: None,
+			gmail_use_api: false,
+			catch_all_mx_patterns: vec![],
 			retries: 2,
+			retry_delay: None,
+			retry_base_delay: Duration::from_secs(1),
+			defer_greylisting: false,
+			greylist_token: None,
+			retryable_codes: None,
+			retry_jitter: 0.2,
+			collect_transcript: false,
+			use_vrfy: false,
+			use_expn: false,
+			report_smtp_extensions: false,
+			check_catch_all: true,
+			catch_all_local_part: None,
+			catch_all_probes: 1,
+			catch_all_rng_seed: None,
+			strict_catch_all: false,
+			rcpt_error_patterns: vec![],
+			extra_unknown_user_patterns: vec![],
+			catch_all_cache: None,
+			mx_cache: None,
+			smtp_pool: None,
+			smtp_end_policy: SmtpEndPolicy::Quit,
+			rate_limiter: None,
+			connection_limit: None,
+			cancellation_token: None,
+			smtp_event_hook: None,
 		}
 	}
 }
@@ -140,6 +734,28 @@ impl CheckEmailInput {
 		self
 	}
 
+	/// Set the email to use in the `MAIL FROM:` SMTP command if `from_email`
+	/// is not a valid email address. Defaults to `postmaster@<domain>`, where
+	/// domain is the domain of the email being checked.
+	pub fn set_from_email_fallback(&mut self, email: String) -> &mut CheckEmailInput {
+		self.from_email_fallback = Some(email);
+		self
+	}
+
+	/// Add an email to check the email again with in the `MAIL FROM:` SMTP
+	/// command, see `alternative_from_emails`.
+	pub fn add_alternative_from_email(&mut self, email: String) -> &mut CheckEmailInput {
+		self.alternative_from_emails.push(email);
+		self
+	}
+
+	/// Set whether to send `MAIL FROM:<>` (the null sender) instead of
+	/// `from_email`. Defaults to false.
+	pub fn set_use_null_sender(&mut self, use_null_sender: bool) -> &mut CheckEmailInput {
+		self.use_null_sender = use_null_sender;
+		self
+	}
+
 	/// Set the name to use in the `EHLO:` SMTP command. Defaults to `localhost`
 	/// if not explicitly set.
 	#[deprecated(since = "0.8.24", note = "Please use set_hello_name instead")]
@@ -155,6 +771,27 @@ impl CheckEmailInput {
 		self
 	}
 
+	/// Set whether to use the PTR record of the IP address we connect from as
+	/// the `EHLO` name. Defaults to false.
+	pub fn set_hello_name_from_ptr(&mut self, hello_name_from_ptr: bool) -> &mut CheckEmailInput {
+		self.hello_name_from_ptr = hello_name_from_ptr;
+		self
+	}
+
+	/// Set the IP address to send as an address literal in the `EHLO`
+	/// command, instead of `hello_name`. Defaults to None.
+	pub fn set_hello_ip(&mut self, hello_ip: IpAddr) -> &mut CheckEmailInput {
+		self.hello_ip = Some(hello_ip);
+		self
+	}
+
+	/// Set the public IP address the SMTP servers see us connect from.
+	/// Defaults to None, i.e. the local address of the route to the server.
+	pub fn set_egress_ip(&mut self, egress_ip: IpAddr) -> &mut CheckEmailInput {
+		self.egress_ip = Some(egress_ip);
+		self
+	}
+
 	/// Use the specified SOCK5 proxy to perform email verification.
 	#[deprecated(since = "0.8.24", note = "Please use set_proxy instead")]
 	pub fn proxy(&mut self, proxy_host: String, proxy_port: u16) -> &mut CheckEmailInput {
@@ -172,12 +809,76 @@ impl CheckEmailInput {
 		self
 	}
 
+	/// Add a proxy to spread the connections over, on top of the one set with
+	/// `set_proxy`.
+	pub fn add_proxy(&mut self, proxy: CheckEmailInputProxy) -> &mut CheckEmailInput {
+		self.proxies.push(proxy);
+		self
+	}
+
+	/// Set how to pick the proxy for each connection, when several proxies
+	/// are configured. Defaults to RoundRobin.
+	pub fn set_proxy_rotation(&mut self, rotation: ProxyRotation) -> &mut CheckEmailInput {
+		self.proxy_rotation = rotation;
+		self
+	}
+
+	/// Set the substrings which, in a server reply, mean that the IP we
+	/// connect from is blocked. Defaults to "blocked using", "spamhaus",
+	/// "blacklisted", "blocklisted" and "on our block list".
+	pub fn set_ip_block_indicators(&mut self, indicators: Vec<String>) -> &mut CheckEmailInput {
+		self.ip_block_indicators = indicators;
+		self
+	}
+
 	/// Set the number of SMTP retries to do.
 	pub fn set_retries(&mut self, retries: usize) -> &mut CheckEmailInput {
 		self.retries = retries;
 		self
 	}
 
+	/// Set the maximum delay to wait between SMTP retries. Defaults to None,
+	/// i.e. retry immediately.
+	pub fn set_retry_delay(&mut self, delay: Duration) -> &mut CheckEmailInput {
+		self.retry_delay = Some(delay);
+		self
+	}
+
+	/// Set the delay before the first retry, doubled at each following
+	/// retry. Defaults to 1s.
+	pub fn set_retry_base_delay(&mut self, delay: Duration) -> &mut CheckEmailInput {
+		self.retry_base_delay = delay;
+		self
+	}
+
+	/// Set whether to return a resumption token instead of retrying when the
+	/// server greylists us. Defaults to false.
+	pub fn set_defer_greylisting(&mut self, defer: bool) -> &mut CheckEmailInput {
+		self.defer_greylisting = defer;
+		self
+	}
+
+	/// Set the token resuming a check deferred because of greylisting.
+	/// Defaults to None.
+	pub fn set_greylist_token(&mut self, token: GreylistToken) -> &mut CheckEmailInput {
+		self.greylist_token = Some(token);
+		self
+	}
+
+	/// Set the SMTP reply codes which trigger a retry. Defaults to None, i.e.
+	/// all transient (4xx) errors are retried.
+	pub fn set_retryable_codes(&mut self, codes: Vec<u16>) -> &mut CheckEmailInput {
+		self.retryable_codes = Some(codes);
+		self
+	}
+
+	/// Set the fraction of each retry delay by which it's randomized, in
+	/// both directions. Defaults to 0.2, i.e. ±20%.
+	pub fn set_retry_jitter(&mut self, jitter: f64) -> &mut CheckEmailInput {
+		self.retry_jitter = jitter;
+		self
+	}
+
 	/// Add optional timeout for the SMTP verification step.
 	#[deprecated(since = "0.8.24", note = "Please use set_smtp_timeout instead")]
 	pub fn smtp_timeout(&mut self, duration: Duration) -> &mut CheckEmailInput {
@@ -185,36 +886,373 @@ impl CheckEmailInput {
 		self
 	}
 
+	/// Add a domain of a disposable email provider, see
+	/// `disposable_domains`.
+	pub fn add_disposable_domain(&mut self, domain: String) -> &mut CheckEmailInput {
+		self.disposable_domains.push(domain);
+		self
+	}
+
+	/// Set whether to use the built-in list of disposable email providers.
+	/// Defaults to true.
+	pub fn set_use_builtin_disposable_domains(
+		&mut self,
+		use_builtin: bool,
+	) -> &mut CheckEmailInput {
+		self.use_builtin_disposable_domains = use_builtin;
+		self
+	}
+
+	/// Set the local parts of role-based accounts, replacing the built-in
+	/// list. Defaults to None, i.e. the built-in list.
+	pub fn set_role_accounts(&mut self, roles: Vec<String>) -> &mut CheckEmailInput {
+		self.role_accounts = Some(roles);
+		self
+	}
+
+	/// Add a domain of a free email provider, see `free_provider_domains`.
+	pub fn add_free_provider_domain(&mut self, domain: String) -> &mut CheckEmailInput {
+		self.free_provider_domains.push(domain);
+		self
+	}
+
+	/// Set whether to check if the email has a Gravatar. Defaults to false.
+	pub fn set_check_gravatar(&mut self, check_gravatar: bool) -> &mut CheckEmailInput {
+		self.check_gravatar = check_gravatar;
+		self
+	}
+
+	/// Set whether to verify the base address of sub-addressed emails
+	/// instead. Defaults to false.
+	pub fn set_verify_base_address(&mut self, verify: bool) -> &mut CheckEmailInput {
+		self.verify_base_address = verify;
+		self
+	}
+
+	/// Set whether to reject quoted local parts without connecting to the
+	/// SMTP server. Defaults to true.
+	pub fn set_strict_local_part(&mut self, strict: bool) -> &mut CheckEmailInput {
+		self.strict_local_part = strict;
+		self
+	}
+
+	/// Set whether to skip the SMTP verification step, and only check the
+	/// syntax and MX records. Defaults to false.
+	pub fn set_skip_smtp(&mut self, skip_smtp: bool) -> &mut CheckEmailInput {
+		self.skip_smtp = skip_smtp;
+		self
+	}
+
 	/// Change the SMTP port.
 	pub fn set_smtp_port(&mut self, port: u16) -> &mut CheckEmailInput {
 		self.smtp_port = port;
 		self
 	}
 
+	/// Set the SMTP port to use for the MX hosts of `provider`, instead of
+	/// `smtp_port`.
+	pub fn set_provider_port(&mut self, provider: Provider, port: u16) -> &mut CheckEmailInput {
+		self.provider_ports.insert(provider, port);
+		self
+	}
+
+	/// Set the maximum number of MX hosts to try when they don't give a
+	/// conclusive answer. Defaults to 3.
+	pub fn set_max_mx_attempts(&mut self, max_mx_attempts: usize) -> &mut CheckEmailInput {
+		self.max_mx_attempts = max_mx_attempts;
+		self
+	}
+
+	/// Set the IP address to connect to instead of resolving the MX hosts.
+	/// Defaults to None.
+	pub fn set_smtp_ip_override(&mut self, ip: IpAddr) -> &mut CheckEmailInput {
+		self.smtp_ip_override = Some(ip);
+		self
+	}
+
+	/// Set whether to probe the submission ports when port 25 is
+	/// unreachable, to tell if it's blocked on our side. Defaults to false.
+	pub fn set_submission_port_fallback(&mut self, fallback: bool) -> &mut CheckEmailInput {
+		self.submission_port_fallback = fallback;
+		self
+	}
+
 	/// Set the SMTP client security to use for TLS.
 	pub fn set_smtp_security(&mut self, smtp_security: SmtpSecurity) -> &mut CheckEmailInput {
 		self.smtp_security = smtp_security;
 		self
 	}
 
+	/// Set the credentials to authenticate with before `MAIL FROM`, for
+	/// relays which require a login. Defaults to None.
+	pub fn set_smtp_auth(&mut self, smtp_auth: SmtpAuth) -> &mut CheckEmailInput {
+		self.smtp_auth = Some(smtp_auth);
+		self
+	}
+
+	/// Add a root certificate (PEM-encoded) to trust when connecting to the
+	/// SMTP server over TLS, on top of the system's ones.
+	pub fn add_tls_root_certificate(&mut self, pem: Vec<u8>) -> &mut CheckEmailInput {
+		self.tls_root_certificates.push(pem);
+		self
+	}
+
+	/// Set the hostname to send in the TLS handshake (SNI), instead of the
+	/// MX host's. The server's certificate is verified against this name.
+	/// Defaults to None.
+	pub fn set_tls_sni_override(&mut self, sni: String) -> &mut CheckEmailInput {
+		self.tls_sni_override = Some(sni);
+		self
+	}
+
+	/// DANGER: Set whether to accept invalid TLS certificates from the SMTP
+	/// server, e.g. expired or self-signed ones. Defaults to false.
+	pub fn set_danger_accept_invalid_certs(&mut self, accept: bool) -> &mut CheckEmailInput {
+		self.danger_accept_invalid_certs = accept;
+		self
+	}
+
 	/// Add optional timeout for the SMTP verification step.
 	pub fn set_smtp_timeout(&mut self, duration: Duration) -> &mut CheckEmailInput {
 		self.smtp_timeout = Some(duration);
 		self
 	}
 
+	/// Set a deadline for the whole check of each email, including all the
+	/// retries. Defaults to None.
+	pub fn set_deadline(&mut self, duration: Duration) -> &mut CheckEmailInput {
+		self.deadline = Some(duration);
+		self
+	}
+
+	/// Set the timeout for connecting to the SMTP server. Defaults to 30s.
+	pub fn set_connect_timeout(&mut self, duration: Duration) -> &mut CheckEmailInput {
+		self.connect_timeout = duration;
+		self
+	}
+
+	/// Set which IP versions to use when connecting to the SMTP server.
+	/// Defaults to System.
+	pub fn set_ip_preference(&mut self, preference: IpPreference) -> &mut CheckEmailInput {
+		self.ip_preference = preference;
+		self
+	}
+
+	/// Set the DNS resolver used for the MX and TXT lookups. Defaults to
+	/// System.
+	pub fn set_dns_resolver(&mut self, resolver: DnsResolver) -> &mut CheckEmailInput {
+		self.dns_resolver = resolver;
+		self
+	}
+
+	/// Set the timeout for each SMTP command. Defaults to None, i.e. the
+	/// connect timeout also applies to commands.
+	pub fn set_command_timeout(&mut self, duration: Duration) -> &mut CheckEmailInput {
+		self.command_timeout = Some(duration);
+		self
+	}
+
 	/// Set whether to use Yahoo's API or connecting directly to their SMTP
 	/// servers. Defaults to true.
 	#[deprecated(since = "0.8.24", note = "Please use set_yahoo_use_api instead")]
 	pub fn yahoo_use_api(&mut self, use_api: bool) -> &mut CheckEmailInput {
-		self.yahoo_use_api = use_api;
+		self.set_yahoo_use_api(use_api)
+	}
+
+	/// Set whether to collect the raw SMTP conversation in
+	/// `SmtpDetails::transcript`. Defaults to false.
+	pub fn set_collect_transcript(&mut self, collect: bool) -> &mut CheckEmailInput {
+		self.collect_transcript = collect;
+		self
+	}
+
+	/// Set whether to fall back to the `VRFY` command when `RCPT TO` is
+	/// ambiguous. Defaults to false.
+	pub fn set_use_vrfy(&mut self, use_vrfy: bool) -> &mut CheckEmailInput {
+		self.use_vrfy = use_vrfy;
+		self
+	}
+
+	/// Set whether to send the `EXPN` command to detect mailing lists.
+	/// Defaults to false.
+	pub fn set_use_expn(&mut self, use_expn: bool) -> &mut CheckEmailInput {
+		self.use_expn = use_expn;
+		self
+	}
+
+	/// Set whether to report the extensions advertised by the server, at
+	/// the cost of a second `EHLO`. Defaults to false.
+	pub fn set_report_smtp_extensions(&mut self, report: bool) -> &mut CheckEmailInput {
+		self.report_smtp_extensions = report;
+		self
+	}
+
+	/// Set whether to check if the domain has a catch-all address. Defaults
+	/// to true.
+	pub fn set_check_catch_all(&mut self, check_catch_all: bool) -> &mut CheckEmailInput {
+		self.check_catch_all = check_catch_all;
+		self
+	}
+
+	/// Set the local part to use for the catch-all probe. Defaults to a random
+	/// 15-char alphanumerical string.
+	pub fn set_catch_all_local_part(&mut self, local_part: String) -> &mut CheckEmailInput {
+		self.catch_all_local_part = Some(local_part);
+		self
+	}
+
+	/// Set the number of probes to send to detect a catch-all. Defaults to 1.
+	pub fn set_catch_all_probes(&mut self, probes: usize) -> &mut CheckEmailInput {
+		self.catch_all_probes = probes;
+		self
+	}
+
+	/// Set the seed of the random generator of the catch-all probes' local
+	/// parts. Defaults to None, i.e. a random seed for each check.
+	pub fn set_catch_all_rng_seed(&mut self, seed: u64) -> &mut CheckEmailInput {
+		self.catch_all_rng_seed = Some(seed);
+		self
+	}
+
+	/// Set whether to still send the `RCPT TO` command for the email on a
+	/// catch-all domain. Defaults to false.
+	pub fn set_strict_catch_all(&mut self, strict_catch_all: bool) -> &mut CheckEmailInput {
+		self.strict_catch_all = strict_catch_all;
+		self
+	}
+
+	/// Add a substring of `RCPT TO` rejection messages, with what it says
+	/// about the email, see `rcpt_error_patterns`.
+	pub fn add_rcpt_error_pattern(
+		&mut self,
+		pattern: String,
+		classification: Classification,
+	) -> &mut CheckEmailInput {
+		self.rcpt_error_patterns.push((pattern, classification));
+		self
+	}
+
+	/// Set the substrings of `RCPT TO` rejection messages saying that the
+	/// email doesn't exist, in addition to the built-in ones. Defaults to an
+	/// empty list.
+	pub fn set_extra_unknown_user_patterns(
+		&mut self,
+		patterns: Vec<String>,
+	) -> &mut CheckEmailInput {
+		self.extra_unknown_user_patterns = patterns;
+		self
+	}
+
+	/// Use the given cache to store the catch-all status of each domain. The
+	/// cache can be shared between several checks by cloning it.
+	pub fn set_catch_all_cache(&mut self, cache: TtlCache<bool>) -> &mut CheckEmailInput {
+		self.catch_all_cache = Some(cache);
+		self
+	}
+
+	/// Use the given cache to store the MX lookup of each domain. The cache
+	/// can be shared between several checks by cloning it.
+	pub fn set_mx_cache(&mut self, cache: TtlCache<MxDetails>) -> &mut CheckEmailInput {
+		self.mx_cache = Some(cache);
+		self
+	}
+
+	/// Use the given pool to reuse SMTP connections across checks, and
+	/// reset connections at the end of checks to keep them in the pool.
+	/// Defaults to None.
+	pub fn set_smtp_pool(&mut self, pool: SmtpPool) -> &mut CheckEmailInput {
+		self.smtp_pool = Some(pool);
+		self.smtp_end_policy = SmtpEndPolicy::Reset;
+		self
+	}
+
+	/// Set what to do with the SMTP connection once a check is done.
+	/// Defaults to Quit.
+	pub fn set_smtp_end_policy(&mut self, policy: SmtpEndPolicy) -> &mut CheckEmailInput {
+		self.smtp_end_policy = policy;
+		self
+	}
+
+	/// Use the given limiter to limit the SMTP connections to each MX host.
+	/// Defaults to None.
+	pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) -> &mut CheckEmailInput {
+		self.rate_limiter = Some(rate_limiter);
+		self
+	}
+
+	/// Use the given limit on the SMTP connections in progress at once.
+	/// Defaults to None.
+	pub fn set_connection_limit(
+		&mut self,
+		connection_limit: ConnectionLimit,
+	) -> &mut CheckEmailInput {
+		self.connection_limit = Some(connection_limit);
+		self
+	}
+
+	/// Set the token cancelling the SMTP verification step. Defaults to None.
+	pub fn set_cancellation_token(&mut self, token: CancellationToken) -> &mut CheckEmailInput {
+		self.cancellation_token = Some(token);
+		self
+	}
+
+	/// Set the hook receiving the events of SMTP checks, e.g. to collect
+	/// metrics. Defaults to None.
+	pub fn set_smtp_event_hook(&mut self, hook: SmtpEventHook) -> &mut CheckEmailInput {
+		self.smtp_event_hook = Some(hook);
 		self
 	}
 
 	/// Set whether to use Yahoo's API or connecting directly to their SMTP
-	/// servers. Defaults to true.
+	/// servers, like to any other server. Defaults to true. See
+	/// `set_yahoo_verif_method` for more options.
 	pub fn set_yahoo_use_api(&mut self, use_api: bool) -> &mut CheckEmailInput {
-		self.yahoo_use_api = use_api;
+		self.yahoo_verif_method = if use_api {
+			YahooVerifMethod::Api
+		} else {
+			YahooVerifMethod::GenericSmtp
+		};
+		self
+	}
+
+	/// Set how to verify emails hosted by Yahoo. Defaults to Api.
+	pub fn set_yahoo_verif_method(&mut self, method: YahooVerifMethod) -> &mut CheckEmailInput {
+		self.yahoo_verif_method = method;
+		self
+	}
+
+	/// Set the number of retries of Yahoo's API when it rate-limits us.
+	/// Defaults to 2.
+	pub fn set_yahoo_retries(&mut self, retries: usize) -> &mut CheckEmailInput {
+		self.yahoo_retries = retries;
+		self
+	}
+
+	/// Set the delay before the first retry of Yahoo's API, doubled at each
+	/// following retry. Defaults to 1s.
+	pub fn set_yahoo_retry_delay(&mut self, delay: Duration) -> &mut CheckEmailInput {
+		self.yahoo_retry_delay = delay;
+		self
+	}
+
+	/// Set the HTTP client for Yahoo's API. Defaults to None.
+	pub fn set_yahoo_http_client(&mut self, client: reqwest::Client) -> &mut CheckEmailInput {
+		self.yahoo_http_client = Some(client);
+		self
+	}
+
+	/// Set whether to use a Gmail HTTP endpoint instead of connecting
+	/// directly to Google's SMTP servers. Defaults to false.
+	pub fn set_gmail_use_api(&mut self, use_api: bool) -> &mut CheckEmailInput {
+		self.gmail_use_api = use_api;
+		self
+	}
+
+	/// Add a MX host pattern (e.g. "*.mail.example.net") of a provider to
+	/// treat as catch-all, without connecting to its SMTP servers. `*`
+	/// matches any sequence of characters.
+	pub fn add_catch_all_mx_pattern(&mut self, pattern: String) -> &mut CheckEmailInput {
+		self.catch_all_mx_patterns.push(pattern);
 		self
 	}
 }
@@ -241,12 +1279,25 @@ pub enum Reachable {
 	Unknown,
 }
 
+/// The version of the JSON output of [`CheckEmailOutput`], serialized in
+/// its `schema_version` field. It's bumped whenever the meaning of existing
+/// fields changes, so that consumers can adapt. Adding fields doesn't bump
+/// it.
+///
+/// Version 2: anti-spam blocks are reported in `smtp.is_blocked` instead
+/// of an error, rate-limited recipients stay `is_deliverable`, sender
+/// rejections are reported as `sender_rejected` errors, and unreachable
+/// servers are `unknown` instead of `invalid`.
+pub const SCHEMA_VERSION: u32 = 2;
+
 /// The result of the [check_email](check_email) function.
 #[derive(Debug)]
 pub struct CheckEmailOutput {
 	/// Input by the user.
 	pub input: String,
 	pub is_reachable: Reachable,
+	/// Details about the DNS records of the domain, other than MX.
+	pub dns: DnsDetails,
 	/// Misc details about the email address.
 	pub misc: Result<MiscDetails, MiscError>,
 	/// Details about the MX host.
@@ -262,6 +1313,7 @@ impl Default for CheckEmailOutput {
 		CheckEmailOutput {
 			input: String::default(),
 			is_reachable: Reachable::Unknown,
+			dns: DnsDetails::default(),
 			misc: Ok(MiscDetails::default()),
 			mx: Ok(MxDetails::default()),
 			smtp: Ok(SmtpDetails::default()),
@@ -283,8 +1335,10 @@ impl Serialize for CheckEmailOutput {
 		}
 
 		let mut map = serializer.serialize_map(Some(1))?;
+		map.serialize_entry("schema_version", &SCHEMA_VERSION)?;
 		map.serialize_entry("input", &self.input)?;
 		map.serialize_entry("is_reachable", &self.is_reachable)?;
+		map.serialize_entry("dns", &self.dns)?;
 		match &self.misc {
 			Ok(t) => map.serialize_entry("misc", &t)?,
 			Err(error) => map.serialize_entry("misc", &MyError { error })?,
@@ -301,3 +1355,134 @@ impl Serialize for CheckEmailOutput {
 		map.end()
 	}
 }
+
+/// Deserialize the JSON written by the `Serialize` impl above, e.g. to read
+/// back cached results. Errors which wrapped errors from other crates are
+/// read back as `SmtpError::Deserialized` or `MxError::Deserialized`, which
+/// serialize back to the same JSON.
+impl<'de> Deserialize<'de> for CheckEmailOutput {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		// The JSON of `Result<T, E>` is either `T` or `{ "error": E }`.
+		#[derive(Deserialize)]
+		#[serde(untagged)]
+		enum MyResult<T, E> {
+			Err { error: E },
+			Ok(T),
+		}
+
+		impl<T, E> From<MyResult<T, E>> for Result<T, E> {
+			fn from(result: MyResult<T, E>) -> Self {
+				match result {
+					MyResult::Ok(t) => Ok(t),
+					MyResult::Err { error } => Err(error),
+				}
+			}
+		}
+
+		#[derive(Deserialize)]
+		struct Serialized {
+			input: String,
+			is_reachable: Reachable,
+			#[serde(default)]
+			dns: DnsDetails,
+			misc: MyResult<MiscDetails, MiscError>,
+			mx: MyResult<MxDetails, MxError>,
+			smtp: MyResult<SmtpDetails, SmtpError>,
+			syntax: SyntaxDetails,
+		}
+
+		let serialized = Serialized::deserialize(deserializer)?;
+		Ok(CheckEmailOutput {
+			input: serialized.input,
+			is_reachable: serialized.is_reachable,
+			dns: serialized.dns,
+			misc: serialized.misc.into(),
+			mx: serialized.mx.into(),
+			smtp: serialized.smtp.into(),
+			syntax: serialized.syntax,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CheckEmailOutput, Reachable};
+	use crate::mx::{MxDetails, MxError};
+	use crate::smtp::{SmtpDetails, SmtpError};
+	use crate::syntax::check_syntax;
+	use async_smtp::smtp::error::Error as AsyncSmtpError;
+	use async_std_resolver::ResolveError;
+
+	fn round_trip(output: &CheckEmailOutput) -> (serde_json::Value, CheckEmailOutput) {
+		let json = serde_json::to_value(output).unwrap();
+		let deserialized: CheckEmailOutput = serde_json::from_value(json.clone()).unwrap();
+		assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+		(json, deserialized)
+	}
+
+	#[test]
+	fn should_round_trip_output() {
+		let output = CheckEmailOutput {
+			input: "foo@bar.com".into(),
+			is_reachable: Reachable::Safe,
+			mx: Ok(serde_json::from_str::<MxDetails>(
+				r#"{"records":["mx1.bar.com.","mx2.bar.com."]}"#,
+			)
+			.unwrap()),
+			smtp: Ok(SmtpDetails {
+				can_connect_smtp: true,
+				is_deliverable: true,
+				attempts: 1,
+				..Default::default()
+			}),
+			syntax: check_syntax("foo@bar.com"),
+			..Default::default()
+		};
+
+		let (json, deserialized) = round_trip(&output);
+		assert_eq!(json["mx"]["records"][1], "mx2.bar.com.");
+		assert_eq!(deserialized.is_reachable, Reachable::Safe);
+		assert!(deserialized.smtp.unwrap().is_deliverable);
+		assert_eq!(deserialized.syntax, output.syntax);
+	}
+
+	#[test]
+	fn should_round_trip_errors() {
+		let io_error = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+		let output = CheckEmailOutput {
+			input: "foo@bar.com".into(),
+			mx: Err(MxError::from(ResolveError::from(
+				"No connections available",
+			))),
+			smtp: Err(SmtpError::SmtpError(AsyncSmtpError::Io(io_error))),
+			..Default::default()
+		};
+
+		let (json, deserialized) = round_trip(&output);
+		assert_eq!(json["smtp"]["error"]["sub_kind"], "connection_refused");
+		match deserialized.smtp {
+			Err(error @ SmtpError::Deserialized(_)) => {
+				assert_eq!(error.kind(), "smtp");
+				assert_eq!(error.sub_kind(), Some("connection_refused"));
+			}
+			other => panic!("unexpected smtp result: {:?}", other),
+		}
+		assert!(matches!(deserialized.mx, Err(MxError::Deserialized(_))));
+
+		// Errors without foreign errors are rebuilt.
+		let output = CheckEmailOutput {
+			mx: Err(MxError::DeadlineExceeded),
+			smtp: Err(SmtpError::Port25Blocked(587)),
+			..Default::default()
+		};
+		let (_, deserialized) = round_trip(&output);
+		assert!(matches!(deserialized.mx, Err(MxError::DeadlineExceeded)));
+		assert!(matches!(
+			deserialized.smtp,
+			Err(SmtpError::Port25Blocked(587))
+		));
+	}
+}
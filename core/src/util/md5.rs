@@ -0,0 +1,90 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal MD5 implementation (RFC 1321), only used to compute Gravatar
+//! hashes. MD5 is broken as a cryptographic hash, don't use it as such.
+
+/// Per-round shift amounts.
+const S: [u32; 64] = [
+	7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+	14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+	21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// Compute the MD5 digest of `input`, as a lowercase hex string.
+pub fn md5_hex(input: &[u8]) -> String {
+	// K[i] = floor(abs(sin(i + 1)) * 2^32).
+	let k: Vec<u32> = (0..64)
+		.map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+		.collect();
+
+	let mut message = input.to_vec();
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0);
+	}
+	message.extend_from_slice(&((input.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+	let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+	for chunk in message.chunks(64) {
+		let m: Vec<u32> = chunk
+			.chunks(4)
+			.map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+			.collect();
+		let [mut a, mut b, mut c, mut d] = state;
+
+		for i in 0..64 {
+			let (f, g) = match i / 16 {
+				0 => ((b & c) | (!b & d), i),
+				1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+				2 => (b ^ c ^ d, (3 * i + 5) % 16),
+				_ => (c ^ (b | !d), (7 * i) % 16),
+			};
+			let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+			a = d;
+			d = c;
+			c = b;
+			b = b.wrapping_add(f.rotate_left(S[i]));
+		}
+
+		state[0] = state[0].wrapping_add(a);
+		state[1] = state[1].wrapping_add(b);
+		state[2] = state[2].wrapping_add(c);
+		state[3] = state[3].wrapping_add(d);
+	}
+
+	state
+		.iter()
+		.flat_map(|word| word.to_le_bytes())
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::md5_hex;
+
+	#[test]
+	fn should_compute_md5() {
+		assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+		assert_eq!(
+			md5_hex(b"The quick brown fox jumps over the lazy dog"),
+			"9e107d9d372bb6826bd81d3542a419d6"
+		);
+		// Longer than one 64-byte block.
+		assert_eq!(md5_hex(&[b'a'; 100]), "36a92cc94a9e0fa21f625f8bfb007adf");
+	}
+}
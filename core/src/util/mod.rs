@@ -14,6 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cancellation;
 pub mod constants;
+pub mod deserialized_error;
 pub mod input_output;
+pub mod md5;
 pub mod ser_with_display;
+pub mod span;
+pub mod ttl_cache;
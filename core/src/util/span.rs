@@ -0,0 +1,42 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// Run `$future` inside a `tracing` span named `$name`, with the given
+/// fields, e.g. `in_span!(fut, "connect", host = %host, port)`. Without the
+/// `tracing` feature, the future is returned as is, and the fields are not
+/// evaluated.
+#[cfg(feature = "tracing")]
+macro_rules! in_span {
+	($future:expr, $name:expr, $($fields:tt)*) => {
+		tracing::Instrument::instrument(
+			$future,
+			tracing::debug_span!(
+				target: crate::util::constants::LOG_TARGET,
+				$name,
+				$($fields)*
+			),
+		)
+	};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! in_span {
+	($future:expr, $($span:tt)*) => {
+		$future
+	};
+}
+
+pub(crate) use in_span;
@@ -0,0 +1,94 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A simple in-memory cache, keyed by domain, whose entries expire after a
+/// given time-to-live.
+///
+/// Cloning the cache is cheap, and all clones share the same entries. So
+/// results are reused for as long as the caller keeps (a clone of) the cache
+/// around, and no longer.
+#[derive(Clone)]
+pub struct TtlCache<V> {
+	entries: Arc<Mutex<HashMap<String, (V, Instant)>>>,
+	ttl: Duration,
+}
+
+impl<V> fmt::Debug for TtlCache<V> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("TtlCache").field("ttl", &self.ttl).finish()
+	}
+}
+
+impl<V: Clone> TtlCache<V> {
+	/// Create a new empty cache, whose entries live for `ttl`.
+	pub fn new(ttl: Duration) -> Self {
+		TtlCache {
+			entries: Arc::new(Mutex::new(HashMap::new())),
+			ttl,
+		}
+	}
+
+	/// Get the value cached for `key`, if it hasn't expired yet.
+	pub fn get(&self, key: &str) -> Option<V> {
+		let mut entries = self
+			.entries
+			.lock()
+			.expect("Cache lock is not poisoned. qed.");
+		match entries.get(&key.to_lowercase()) {
+			Some((value, expires_at)) if *expires_at > Instant::now() => Some(value.clone()),
+			Some(_) => {
+				entries.remove(&key.to_lowercase());
+				None
+			}
+			None => None,
+		}
+	}
+
+	/// Cache `value` for `key`, using the cache's default time-to-live.
+	pub fn insert(&self, key: &str, value: V) {
+		self.insert_with_ttl(key, value, self.ttl)
+	}
+
+	/// Cache `value` for `key`, for the given time-to-live.
+	pub fn insert_with_ttl(&self, key: &str, value: V, ttl: Duration) {
+		self.entries
+			.lock()
+			.expect("Cache lock is not poisoned. qed.")
+			.insert(key.to_lowercase(), (value, Instant::now() + ttl));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::TtlCache;
+	use std::time::Duration;
+
+	#[test]
+	fn should_expire_entries() {
+		let cache = TtlCache::new(Duration::from_secs(60));
+		cache.insert("Gmail.com", true);
+		cache.insert_with_ttl("yahoo.com", false, Duration::from_secs(0));
+
+		assert_eq!(cache.clone().get("gmail.com"), Some(true));
+		assert_eq!(cache.get("yahoo.com"), None);
+		assert_eq!(cache.get("outlook.com"), None);
+	}
+}
@@ -0,0 +1,140 @@
+// check-if-email-exists
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::smtp::{ConnectionLimit, RateLimiter, SmtpPool};
+use crate::util::{
+	cancellation::CancellationToken,
+	input_output::{CheckEmailInput, SmtpEndPolicy},
+	ttl_cache::TtlCache,
+};
+use crate::{check_single_email, CheckEmailOutput};
+use std::time::Duration;
+
+/// A reusable email verifier. It owns the verification configuration, as
+/// well as the state which can be shared across checks, such as caches.
+///
+/// Long-running services should create one `EmailVerifier` and reuse it for
+/// all their checks, to keep this state warm.
+#[derive(Debug, Clone)]
+pub struct EmailVerifier {
+	/// The configuration used for each check. Its `to_emails` field is
+	/// ignored.
+	input: CheckEmailInput,
+}
+
+impl Default for EmailVerifier {
+	fn default() -> Self {
+		EmailVerifier::new(CheckEmailInput::default())
+	}
+}
+
+impl EmailVerifier {
+	/// Create a new EmailVerifier, using the configuration in `input`. The
+	/// `input.to_emails` field is ignored.
+	pub fn new(mut input: CheckEmailInput) -> EmailVerifier {
+		input.to_emails = vec![];
+		EmailVerifier { input }
+	}
+
+	/// The configuration used for each check.
+	pub fn input(&self) -> &CheckEmailInput {
+		&self.input
+	}
+
+	/// Cache the catch-all status of each domain for `ttl`, across all the
+	/// checks performed by this verifier.
+	pub fn set_catch_all_cache(&mut self, ttl: Duration) -> &mut EmailVerifier {
+		self.input.catch_all_cache = Some(TtlCache::new(ttl));
+		self
+	}
+
+	/// Cache the MX lookup of each domain, across all the checks performed
+	/// by this verifier. Lookups are cached for their DNS TTL. Domains
+	/// without MX records are cached for the negative TTL of the DNS
+	/// response, or else for `negative_ttl`.
+	pub fn set_mx_cache(&mut self, negative_ttl: Duration) -> &mut EmailVerifier {
+		self.input.mx_cache = Some(TtlCache::new(negative_ttl));
+		self
+	}
+
+	/// Reuse SMTP connections across all the checks performed by this
+	/// verifier. Idle connections are closed after `max_idle`, and no
+	/// connection is reused after `max_lifetime`.
+	pub fn set_smtp_pool(
+		&mut self,
+		max_idle: Duration,
+		max_lifetime: Duration,
+	) -> &mut EmailVerifier {
+		self.input
+			.set_smtp_pool(SmtpPool::new(max_idle, max_lifetime));
+		self
+	}
+
+	/// Set what to do with the SMTP connection at the end of each check:
+	/// `QUIT`, or `RSET` to keep it in the pool set with `set_smtp_pool`.
+	/// Defaults to Quit, or Reset once a pool is set.
+	pub fn set_smtp_end_policy(&mut self, policy: SmtpEndPolicy) -> &mut EmailVerifier {
+		self.input.smtp_end_policy = policy;
+		self
+	}
+
+	/// Limit the SMTP connections to each MX host, across all the checks
+	/// performed by this verifier: at most `max_per_second` new connections
+	/// per second, and `max_concurrent` connections at once. Checks over the
+	/// limits wait for their turn.
+	pub fn set_rate_limit(
+		&mut self,
+		max_per_second: f64,
+		max_concurrent: usize,
+	) -> &mut EmailVerifier {
+		self.input.rate_limiter = Some(RateLimiter::new(max_per_second, max_concurrent));
+		self
+	}
+
+	/// Limit the SMTP connections in progress at once to `max_connections`,
+	/// across all the checks performed by this verifier and all hosts.
+	/// Checks over the limit wait in line for their turn.
+	pub fn set_max_connections(&mut self, max_connections: usize) -> &mut EmailVerifier {
+		self.input.connection_limit = Some(ConnectionLimit::new(max_connections));
+		self
+	}
+
+	/// Verify a single email address.
+	pub async fn verify(&self, email: &str) -> CheckEmailOutput {
+		let input = CheckEmailInput {
+			to_emails: vec![email.into()],
+			..self.input.clone()
+		};
+
+		check_single_email(input).await
+	}
+
+	/// Verify a single email address, cancelling the SMTP verification step
+	/// when `token` is cancelled, see `CheckEmailInput::cancellation_token`.
+	pub async fn verify_cancellable(
+		&self,
+		email: &str,
+		token: CancellationToken,
+	) -> CheckEmailOutput {
+		let input = CheckEmailInput {
+			to_emails: vec![email.into()],
+			cancellation_token: Some(token),
+			..self.input.clone()
+		};
+
+		check_single_email(input).await
+	}
+}
@@ -38,6 +38,8 @@ pub struct PostReqBody {
 }
 
 /// Error Response from POST /
+// Never constructed: the errors below are sent as plain text.
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize)]
 #[deprecated(
 	since = "0.8.24",
@@ -74,7 +76,8 @@ async fn req_handler(req: Request<Body>) -> Result<Response<Body>, hyper::Error>
 			input
 				.set_from_email(body.from_email.unwrap_or_else(|| CONF.from_email.clone()))
 				.set_hello_name(body.hello_name.unwrap_or_else(|| CONF.hello_name.clone()))
-				.set_yahoo_use_api(CONF.yahoo_use_api);
+				.set_yahoo_use_api(CONF.yahoo_use_api)
+				.set_gmail_use_api(CONF.gmail_use_api);
 			if let Some(proxy_host) = body.proxy_host.map(Cow::Owned).or_else(|| CONF.proxy_host.as_ref().map(Cow::Borrowed)) {
 				input.set_proxy(CheckEmailInputProxy {
 					host:proxy_host.into_owned(),
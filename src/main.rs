@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+// The HTTP server is deprecated, but still ships until its removal in v0.9.0.
+#[allow(deprecated)]
 mod http;
 
 use std::net::IpAddr;
@@ -62,6 +64,11 @@ pub struct Cli {
 	#[clap(long, env, default_value = "true", parse(try_from_str))]
 	pub yahoo_use_api: bool,
 
+	/// For email addresses hosted by Google, use a Gmail HTTP endpoint
+	/// instead of connecting directly to their SMTP servers.
+	#[clap(long, env, default_value = "false", parse(try_from_str))]
+	pub gmail_use_api: bool,
+
 	/// The email to check.
 	pub to_email: Option<String>,
 
@@ -109,13 +116,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 			.set_from_email(CONF.from_email.clone())
 			.set_hello_name(CONF.hello_name.clone())
 			.set_smtp_port(CONF.smtp_port)
-			.set_yahoo_use_api(CONF.yahoo_use_api);
+			.set_yahoo_use_api(CONF.yahoo_use_api)
+			.set_gmail_use_api(CONF.gmail_use_api);
 		if let Some(proxy_host) = &CONF.proxy_host {
 			input.set_proxy(CheckEmailInputProxy {
 				host: proxy_host.clone(),
 				port: CONF.proxy_port,
 				username: CONF.proxy_username.clone(),
 				password: CONF.proxy_password.clone(),
+				..Default::default()
 			});
 		}
 
@@ -132,6 +141,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 	}
 
 	// Run the web server if --http flag is on.
+	#[allow(deprecated)]
 	if CONF.http {
 		http::run((CONF.http_host, CONF.http_port)).await?;
 	}
@@ -18,8 +18,7 @@
 
 #[cfg(test)]
 mod tests {
-	use check_if_email_exists::{check_email, CheckEmailInput};
-	use serde_json;
+	use check_if_email_exists::{check_email, CheckEmailInput, CheckEmailOutput};
 	use std::fs;
 	use tokio::runtime::Runtime;
 
@@ -49,7 +48,9 @@ mod tests {
 			// Uncomment to see the JSON result of `check_email`.
 			// println!("{}", actual);
 
-			// For the input,misc,smtp,syntax fields, we match exact JSON.
+			// For the schema_version,input,misc,smtp,syntax fields, we match
+			// exact JSON.
+			assert_eq!(expected.get("schema_version"), actual.get("schema_version"),);
 			assert_eq!(expected.get("input"), actual.get("input"),);
 			assert_eq!(expected.get("is_reachable"), actual.get("is_reachable"),);
 			assert_eq!(expected.get("misc"), actual.get("misc"),);
@@ -93,6 +94,22 @@ mod tests {
 		test_all_from_folder("./src/fixtures");
 	}
 
+	#[test]
+	fn should_round_trip_fixtures() {
+		for path in fs::read_dir("./src/fixtures").unwrap() {
+			let path = path.unwrap().path();
+			let expected: serde_json::Value =
+				serde_json::from_reader(fs::File::open(&path).unwrap()).unwrap();
+
+			let output: CheckEmailOutput = serde_json::from_value(expected.clone()).unwrap();
+			let actual = serde_json::to_value(&output).unwrap();
+
+			for (key, value) in expected.as_object().unwrap() {
+				assert_eq!(Some(value), actual.get(key), "{:?}: {}", path, key);
+			}
+		}
+	}
+
 	#[test]
 	fn should_pass_sensitive_fixtures() {
 		// These fixtures contain real-file emails, they are not committed to